@@ -0,0 +1,8 @@
+/* Device-to-device copy used as the attainable memory bandwidth when no peak is given.
+ * One float4 per work item; the host passes the length in float4s. */
+__kernel void copy_float4(const __global float4* input,
+                          __global float4* output,
+                          const uint len) {
+    const uint i = get_global_id(0);
+    if (i < len) output[i] = input[i];
+}
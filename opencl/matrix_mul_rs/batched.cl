@@ -0,0 +1,64 @@
+/* Batched version of tiled.cl: the third NDRange dimension indexes the batch.
+ *
+ * With BATCH_UNIFORM every multiplication is M-by-N times N-by-P and the matrices sit at
+ * fixed strides of M * N, N * P and M * P elements. Otherwise each batch entry has its own
+ * dimensions and offsets, read from `shapes` (M, N, P) and `offsets` (A, B, C); the NDRange
+ * covers the largest entry and groups whose tile falls outside a smaller one exit early. */
+
+#include "include/tile_load.h" TYPE=float
+
+__kernel void batched(const __global float* A,
+                      const __global float* B,
+                      __global float* C,
+#ifdef BATCH_UNIFORM
+                      const uint M,
+                      const uint N,
+                      const uint P
+#else
+                      const __global uint4* shapes,
+                      const __global uint4* offsets
+#endif
+                      ) {
+    const size_t batch = get_global_id(2);
+#ifdef BATCH_UNIFORM
+    const size_t a_offset = batch * M * N, b_offset = batch * N * P, c_offset = batch * M * P;
+#else
+    const uint M = shapes[batch].x, N = shapes[batch].y, P = shapes[batch].z;
+    const size_t a_offset = offsets[batch].x, b_offset = offsets[batch].y, c_offset = offsets[batch].z;
+#endif
+
+    const size_t tile_row = get_group_id(0);
+    const size_t tile_col = get_group_id(1);
+    /* The whole group leaves together, so the barriers below are still reached by all of its work items */
+    if (tile_row * TILE_SIZE >= M || tile_col * TILE_SIZE >= P) return;
+
+    const size_t row = get_local_id(0);
+    const size_t col = get_local_id(1);
+    const size_t tile_num = (N + TILE_SIZE - 1) / TILE_SIZE;
+
+    float c_acc = 0.0f;
+    __local float current_a_tile[TILE_SIZE][TILE_SIZE];
+    __local float current_b_tile[TILE_SIZE][TILE_SIZE];
+
+    for (size_t tile = 0; tile < tile_num; tile++) {
+        const size_t a_i_row = (tile_row * TILE_SIZE) + row;
+        const size_t a_i_col = (tile * TILE_SIZE) + col;
+
+        const size_t b_i_row = (tile * TILE_SIZE) + row;
+        const size_t b_i_col = (tile_col * TILE_SIZE) + col;
+
+        current_a_tile[row][col] = load_or_zero_float(A + a_offset, a_i_row, a_i_col, M, N, N);
+        current_b_tile[row][col] = load_or_zero_float(B + b_offset, b_i_row, b_i_col, N, P, P);
+
+        barrier(CLK_LOCAL_MEM_FENCE);
+
+        for (size_t n = 0; n < TILE_SIZE; n++)
+            c_acc += current_a_tile[row][n] * current_b_tile[n][col];
+
+        barrier(CLK_LOCAL_MEM_FENCE);
+    }
+
+    const size_t result_row = tile_row * TILE_SIZE + row;
+    const size_t result_col = tile_col * TILE_SIZE + col;
+    if (result_row < M && result_col < P) C[c_offset + result_row * P + result_col] = c_acc;
+}
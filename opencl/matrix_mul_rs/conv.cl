@@ -0,0 +1,73 @@
+/* 2D convolution (cross-correlation, as in most frameworks) of an N x C x H x W input with K filters
+ * of C x KH x KW weights (always stored KCRS), zero-padded by PH/PW, producing N x K x OH x OW.
+ * Input and output use NCHW or NHWC (LAYOUT_NHWC). */
+
+#include "include/tensor_index.h"
+
+/* Direct convolution: one work item per output element, NDRange (OW, OH, N * K) */
+__kernel void conv_direct(const __global float* input,
+                          const __global float* weights,
+                          __global float* output,
+                          const uint N, const uint C, const uint H, const uint W,
+                          const uint K, const uint OH, const uint OW,
+                          const uint KH, const uint KW,
+                          const uint SH, const uint SW,
+                          const uint PH, const uint PW) {
+    const uint ow = get_global_id(0);
+    const uint oh = get_global_id(1);
+    const uint nk = get_global_id(2);
+    if (ow >= OW || oh >= OH || nk >= N * K) return;
+    const uint n = nk / K, k = nk % K;
+
+    const int h0 = (int) (oh * SH) - (int) PH;
+    const int w0 = (int) (ow * SW) - (int) PW;
+
+    float acc = 0.0f;
+    for (uint c = 0; c < C; c++) {
+        for (uint kh = 0; kh < KH; kh++) {
+            const int h = h0 + (int) kh;
+            if (h < 0 || h >= (int) H) continue;
+            for (uint kw = 0; kw < KW; kw++) {
+                const int w = w0 + (int) kw;
+                if (w < 0 || w >= (int) W) continue;
+                acc += input[TENSOR_INDEX(n, c, h, w, C, H, W)] * weights[((k * C + c) * KH + kh) * KW + kw];
+            }
+        }
+    }
+    output[TENSOR_INDEX(n, k, oh, ow, K, OH, OW)] = acc;
+}
+
+/* Lowers the input to a (N * OH * OW) x (C * KH * KW) row-major matrix, one row per output pixel,
+ * so that multiplying it by the (C * KH * KW) x K transposed weights yields the output in NHWC.
+ * NDRange (C * KH * KW, N * OH * OW); padding becomes zeros. */
+__kernel void im2col(const __global float* input,
+                     __global float* columns,
+                     const uint N, const uint C, const uint H, const uint W,
+                     const uint OH, const uint OW,
+                     const uint KH, const uint KW,
+                     const uint SH, const uint SW,
+                     const uint PH, const uint PW) {
+    const uint col = get_global_id(0);
+    const uint row = get_global_id(1);
+    if (col >= C * KH * KW || row >= N * OH * OW) return;
+
+    const uint c = col / (KH * KW), kh = (col / KW) % KH, kw = col % KW;
+    const uint n = row / (OH * OW), oh = (row / OW) % OH, ow = row % OW;
+    const int h = (int) (oh * SH) - (int) PH + (int) kh;
+    const int w = (int) (ow * SW) - (int) PW + (int) kw;
+
+    columns[row * (C * KH * KW) + col] = (h < 0 || h >= (int) H || w < 0 || w >= (int) W)
+        ? 0.0f : input[TENSOR_INDEX(n, c, h, w, C, H, W)];
+}
+
+/* Reorders the NHWC result of the im2col GEMM into NCHW; NDRange (OW * OH, K, N) */
+__kernel void nhwc_to_nchw(const __global float* nhwc,
+                           __global float* nchw,
+                           const uint N, const uint K, const uint OH, const uint OW) {
+    const uint pixel = get_global_id(0);
+    const uint k = get_global_id(1);
+    const uint n = get_global_id(2);
+    if (pixel >= OH * OW || k >= K || n >= N) return;
+
+    nchw[(n * K + k) * OH * OW + pixel] = nhwc[(n * OH * OW + pixel) * K + k];
+}
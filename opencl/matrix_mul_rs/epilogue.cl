@@ -0,0 +1,13 @@
+/* Applies the bias and activation of the GEMM epilogue to a finished C matrix in place;
+ * used to compare fused epilogues against a separate elementwise pass */
+__kernel void epilogue(__global float* C,
+                       const uint M,
+                       const uint P
+                       EPILOGUE_PARAM) {
+    const uint row = get_global_id(0);
+    const uint col = get_global_id(1);
+
+    if (row >= M || col >= P)
+        return;
+    C[row * P + col] = EPILOGUE(C[row * P + col], col);
+}
@@ -0,0 +1,7 @@
+/* Enables the subgroup extension the host detected: SUBGROUP_KHR (cl_khr_subgroups) or SUBGROUP_INTEL (cl_intel_subgroups) */
+
+#if defined(SUBGROUP_KHR)
+#pragma OPENCL EXTENSION cl_khr_subgroups : enable
+#elif defined(SUBGROUP_INTEL)
+#pragma OPENCL EXTENSION cl_intel_subgroups : enable
+#endif
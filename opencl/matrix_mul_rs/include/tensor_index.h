@@ -0,0 +1,7 @@
+/* Index of element (n, c, h, w) of an N x C x H x W tensor stored as NCHW, or NHWC with LAYOUT_NHWC */
+
+#ifdef LAYOUT_NHWC
+#define TENSOR_INDEX(n, c, h, w, C, H, W) ((((n) * (H) + (h)) * (W) + (w)) * (C) + (c))
+#else
+#define TENSOR_INDEX(n, c, h, w, C, H, W) ((((n) * (C) + (c)) * (H) + (h)) * (W) + (w))
+#endif
@@ -0,0 +1,9 @@
+/* Template parameter: TYPE, the element type (float, or e.g. float4 for matrices read four columns at a time).
+ *
+ * Element (row, col) of a rows x cols row-major matrix with the given row stride, or 0 outside of it:
+ * edge tiles overhang the matrix when its dimensions are not multiples of TILE_SIZE. */
+${TYPE} load_or_zero_${TYPE}(const __global ${TYPE}* X,
+                             const size_t row, const size_t col,
+                             const size_t rows, const size_t cols, const size_t stride) {
+    return (row < rows && col < cols) ? X[row * stride + col] : (${TYPE}) 0.0f;
+}
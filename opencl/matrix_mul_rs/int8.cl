@@ -0,0 +1,91 @@
+/* int8 x int8 -> int32 GEMM with per-tensor zero points.
+ *
+ * Both inputs come packed four values per uint: A row-major as M rows of ceil(N / 4) words,
+ * B transposed as P rows of ceil(N / 4) words, so that four consecutive k of a row and a column
+ * are a single load and a single 4-way dot product. Rows are zero-padded to whole words.
+ *
+ * Zero points are not subtracted before the product (that would leave the int8 range); instead
+ *     sum (a - za)(b - zb) = sum ab - zb * sum a - za * sum b + N * za * zb
+ * where the row and column sums come from dot products with packed ones. */
+
+#ifdef INT_DOT_KHR
+#pragma OPENCL EXTENSION cl_khr_integer_dot_product : enable
+#define DOT4(a, b) dot_4x8packed_ss_int(a, b)
+#else
+inline int dot4_emulated(uint a, uint b) {
+    const int4 products = convert_int4(as_char4(a)) * convert_int4(as_char4(b));
+    return products.x + products.y + products.z + products.w;
+}
+#define DOT4(a, b) dot4_emulated(a, b)
+#endif
+
+#define PACKED_ONES 0x01010101u
+
+inline int apply_zero_points(int acc, int sum_a, int sum_b, uint n, int a_zero, int b_zero) {
+    return acc - b_zero * sum_a - a_zero * sum_b + (int) n * a_zero * b_zero;
+}
+
+/* One work item per element of C, reading both inputs straight from global memory */
+__kernel void int8_naive(const __global uint* A,
+                         const __global uint* BT,
+                         __global int* C,
+                         const uint M,
+                         const uint N,
+                         const uint P,
+                         const int A_ZERO,
+                         const int B_ZERO) {
+    const uint row = get_global_id(0);
+    const uint col = get_global_id(1);
+    if (row >= M || col >= P) return;
+
+    const uint words = (N + 3) / 4;
+    int acc = 0, sum_a = 0, sum_b = 0;
+    for (uint k = 0; k < words; k++) {
+        const uint a = A[row * words + k];
+        const uint b = BT[col * words + k];
+        acc += DOT4(a, b);
+        sum_a += DOT4(a, PACKED_ONES);
+        sum_b += DOT4(b, PACKED_ONES);
+    }
+    C[row * P + col] = apply_zero_points(acc, sum_a, sum_b, N, A_ZERO, B_ZERO);
+}
+
+/* Same tiling as tiled.cl, except that a tile spans TILE_SIZE words, i.e. 4 * TILE_SIZE values of k */
+__kernel void int8_tiled(const __global uint* A,
+                         const __global uint* BT,
+                         __global int* C,
+                         const uint M,
+                         const uint N,
+                         const uint P,
+                         const int A_ZERO,
+                         const int B_ZERO) {
+    const uint row = get_global_id(0);
+    const uint col = get_global_id(1);
+    const uint local_row = get_local_id(0);
+    const uint local_col = get_local_id(1);
+    /* Work item (r, c) also loads the B word of column r of this tile so that global reads stay coalesced */
+    const uint b_col = get_group_id(1) * TILE_SIZE + local_row;
+
+    const uint words = (N + 3) / 4;
+    __local uint a_tile[TILE_SIZE][TILE_SIZE];
+    __local uint bt_tile[TILE_SIZE][TILE_SIZE];
+
+    int acc = 0, sum_a = 0, sum_b = 0;
+    for (uint tile = 0; tile < words; tile += TILE_SIZE) {
+        const uint k = tile + local_col;
+        a_tile[local_row][local_col] = (row < M && k < words) ? A[row * words + k] : 0;
+        bt_tile[local_row][local_col] = (b_col < P && k < words) ? BT[b_col * words + k] : 0;
+        barrier(CLK_LOCAL_MEM_FENCE);
+
+        for (uint i = 0; i < TILE_SIZE; i++) {
+            const uint a = a_tile[local_row][i];
+            const uint b = bt_tile[local_col][i];
+            acc += DOT4(a, b);
+            sum_a += DOT4(a, PACKED_ONES);
+            sum_b += DOT4(b, PACKED_ONES);
+        }
+        barrier(CLK_LOCAL_MEM_FENCE);
+    }
+
+    if (row < M && col < P) C[row * P + col] = apply_zero_points(acc, sum_a, sum_b, N, A_ZERO, B_ZERO);
+}
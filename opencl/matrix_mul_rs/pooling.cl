@@ -0,0 +1,35 @@
+/* 2D max pooling over an N x C x H x W tensor stored as NCHW or NHWC (LAYOUT_NHWC).
+ * One work item per output element; the NDRange is (OW, OH, N * C), rounded up to whole work groups.
+ * Window positions that fall into the padding are skipped rather than treated as zeros. */
+
+#include "include/tensor_index.h"
+
+__kernel void max_pool(const __global float* input,
+                       __global float* output,
+                       const uint N, const uint C, const uint H, const uint W,
+                       const uint OH, const uint OW,
+                       const uint KH, const uint KW,
+                       const uint SH, const uint SW,
+                       const uint PH, const uint PW) {
+    const uint ow = get_global_id(0);
+    const uint oh = get_global_id(1);
+    const uint nc = get_global_id(2);
+    if (ow >= OW || oh >= OH || nc >= N * C) return;
+    const uint n = nc / C, c = nc % C;
+
+    /* Signed, since the window may start in the padding */
+    const int h0 = (int) (oh * SH) - (int) PH;
+    const int w0 = (int) (ow * SW) - (int) PW;
+
+    float max_value = -INFINITY;
+    for (uint kh = 0; kh < KH; kh++) {
+        const int h = h0 + (int) kh;
+        if (h < 0 || h >= (int) H) continue;
+        for (uint kw = 0; kw < KW; kw++) {
+            const int w = w0 + (int) kw;
+            if (w < 0 || w >= (int) W) continue;
+            max_value = fmax(max_value, input[TENSOR_INDEX(n, c, h, w, C, H, W)]);
+        }
+    }
+    output[TENSOR_INDEX(n, c, oh, ow, C, OH, OW)] = max_value;
+}
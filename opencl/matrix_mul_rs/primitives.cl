@@ -0,0 +1,159 @@
+/* Parallel primitives. Parameters (set by the host):
+ * LOCAL_SIZE is the work group size of the 1D kernels, a power of two
+ * TILE_SIZE is the transpose tile edge (the local size is TILE_SIZE x TILE_SIZE); TILE_PAD extra columns per
+ * tile row move the column-wise reads of the tile into different local memory banks
+ * HISTOGRAM_BINS is the number of histogram bins
+ * SUBGROUP_KHR (cl_khr_subgroups) or SUBGROUP_INTEL (cl_intel_subgroups) enables reduce_subgroup */
+
+#include "include/subgroup_extensions.h"
+
+/* Each work group sums 2 * LOCAL_SIZE consecutive elements (the first add happens during the load) and writes
+ * one partial sum; the host reruns the kernel on the partial sums until a single value is left.
+ * Sequential addressing keeps the active work items contiguous, so whole subgroups retire at each step. */
+__kernel void reduce_tree(const __global float* input,
+                          __global float* partial,
+                          const uint len) {
+    __local float scratch[LOCAL_SIZE];
+    const uint lid = get_local_id(0);
+    const uint i = get_group_id(0) * LOCAL_SIZE * 2 + lid;
+
+    float sum = i < len ? input[i] : 0.0f;
+    if (i + LOCAL_SIZE < len) sum += input[i + LOCAL_SIZE];
+    scratch[lid] = sum;
+    barrier(CLK_LOCAL_MEM_FENCE);
+
+    for (uint stride = LOCAL_SIZE / 2; stride > 0; stride >>= 1) {
+        if (lid < stride) scratch[lid] += scratch[lid + stride];
+        barrier(CLK_LOCAL_MEM_FENCE);
+    }
+    if (lid == 0) partial[get_group_id(0)] = scratch[0];
+}
+
+#if defined(SUBGROUP_KHR) || defined(SUBGROUP_INTEL)
+/* Same contract as reduce_tree, but subgroups reduce in registers and only one value per subgroup
+ * goes through local memory, leaving a single barrier */
+__kernel void reduce_subgroup(const __global float* input,
+                              __global float* partial,
+                              const uint len) {
+    __local float subgroup_sums[LOCAL_SIZE];
+    const uint lid = get_local_id(0);
+    const uint i = get_group_id(0) * LOCAL_SIZE * 2 + lid;
+
+    float sum = i < len ? input[i] : 0.0f;
+    if (i + LOCAL_SIZE < len) sum += input[i + LOCAL_SIZE];
+    sum = sub_group_reduce_add(sum);
+    if (get_sub_group_local_id() == 0) subgroup_sums[get_sub_group_id()] = sum;
+    barrier(CLK_LOCAL_MEM_FENCE);
+
+    if (lid == 0) {
+        float total = 0.0f;
+        for (uint s = 0; s < get_num_sub_groups(); s++) total += subgroup_sums[s];
+        partial[get_group_id(0)] = total;
+    }
+}
+#endif
+
+/* Work-efficient (Blelloch) exclusive scan of 2 * LOCAL_SIZE elements per work group: the up-sweep builds a
+ * balanced tree of partial sums in place, the down-sweep turns it into prefix sums, O(n) adds in total.
+ * Each group's total goes to block_sums, which the host scans recursively and adds back with scan_add_offsets. */
+__kernel void scan_blocks(const __global uint* input,
+                          __global uint* output,
+                          __global uint* block_sums,
+                          const uint len) {
+    __local uint temp[2 * LOCAL_SIZE];
+    const uint lid = get_local_id(0);
+    const uint base = get_group_id(0) * 2 * LOCAL_SIZE;
+
+    temp[2 * lid] = base + 2 * lid < len ? input[base + 2 * lid] : 0;
+    temp[2 * lid + 1] = base + 2 * lid + 1 < len ? input[base + 2 * lid + 1] : 0;
+
+    uint offset = 1;
+    for (uint active = LOCAL_SIZE; active > 0; active >>= 1) {
+        barrier(CLK_LOCAL_MEM_FENCE);
+        if (lid < active) {
+            temp[offset * (2 * lid + 2) - 1] += temp[offset * (2 * lid + 1) - 1];
+        }
+        offset <<= 1;
+    }
+
+    if (lid == 0) {
+        block_sums[get_group_id(0)] = temp[2 * LOCAL_SIZE - 1];
+        temp[2 * LOCAL_SIZE - 1] = 0;
+    }
+
+    for (uint active = 1; active <= LOCAL_SIZE; active <<= 1) {
+        offset >>= 1;
+        barrier(CLK_LOCAL_MEM_FENCE);
+        if (lid < active) {
+            const uint left = offset * (2 * lid + 1) - 1, right = offset * (2 * lid + 2) - 1;
+            const uint left_value = temp[left];
+            temp[left] = temp[right];
+            temp[right] += left_value;
+        }
+    }
+    barrier(CLK_LOCAL_MEM_FENCE);
+
+    if (base + 2 * lid < len) output[base + 2 * lid] = temp[2 * lid];
+    if (base + 2 * lid + 1 < len) output[base + 2 * lid + 1] = temp[2 * lid + 1];
+}
+
+/* Adds the scanned block sums to the blocks scanned by scan_blocks */
+__kernel void scan_add_offsets(__global uint* output,
+                               const __global uint* block_offsets,
+                               const uint len) {
+    const uint i = get_global_id(0);
+    if (i < len) output[i] += block_offsets[i / (2 * LOCAL_SIZE)];
+}
+
+/* Baseline: reads are coalesced, writes are strided by rows */
+__kernel void transpose_naive(const __global float* input,
+                              __global float* output,
+                              const uint rows, const uint cols) {
+    const uint x = get_global_id(0);
+    const uint y = get_global_id(1);
+    if (x < cols && y < rows) output[x * rows + y] = input[y * cols + x];
+}
+
+/* Goes through a TILE_SIZE x TILE_SIZE local tile so that both the global reads and writes are row-wise;
+ * the tile is written by rows and read by columns. Without padding a column of the tile sits in a single
+ * local memory bank whenever TILE_SIZE is a multiple of the bank count. NDRange (cols, rows), rounded up. */
+__kernel void transpose_tiled(const __global float* input,
+                              __global float* output,
+                              const uint rows, const uint cols) {
+    __local float tile[TILE_SIZE][TILE_SIZE + TILE_PAD];
+    const uint lx = get_local_id(0), ly = get_local_id(1);
+
+    uint x = get_global_id(0), y = get_global_id(1);
+    if (x < cols && y < rows) tile[ly][lx] = input[y * cols + x];
+    barrier(CLK_LOCAL_MEM_FENCE);
+
+    /* Mirror the tile position; local x now runs along an output row */
+    x = get_group_id(1) * TILE_SIZE + lx;
+    y = get_group_id(0) * TILE_SIZE + ly;
+    if (x < rows && y < cols) output[y * rows + x] = tile[lx][ly];
+}
+
+/* Values must be below HISTOGRAM_BINS. Each work group counts into a private copy of the bins with local
+ * atomics, then merges it with one global atomic per bin, so global contention no longer grows with the
+ * input. Grid-stride loop: the host launches only enough groups to fill the device. */
+__kernel void histogram_local(const __global uint* data,
+                              __global uint* bins,
+                              const uint len) {
+    __local uint local_bins[HISTOGRAM_BINS];
+    for (uint b = get_local_id(0); b < HISTOGRAM_BINS; b += get_local_size(0)) local_bins[b] = 0;
+    barrier(CLK_LOCAL_MEM_FENCE);
+
+    for (uint i = get_global_id(0); i < len; i += get_global_size(0)) atomic_inc(&local_bins[data[i]]);
+    barrier(CLK_LOCAL_MEM_FENCE);
+
+    for (uint b = get_local_id(0); b < HISTOGRAM_BINS; b += get_local_size(0)) {
+        if (local_bins[b] > 0) atomic_add(&bins[b], local_bins[b]);
+    }
+}
+
+/* Baseline: one global atomic per element */
+__kernel void histogram_global(const __global uint* data,
+                               __global uint* bins,
+                               const uint len) {
+    for (uint i = get_global_id(0); i < len; i += get_global_size(0)) atomic_inc(&bins[data[i]]);
+}
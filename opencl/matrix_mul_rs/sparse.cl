@@ -0,0 +1,114 @@
+/* Sparse kernels over an M x N matrix A in CSR (row_offsets[M + 1], col_indices[nnz], values[nnz]) or
+ * ELL (ELL_WIDTH entries per row, stored entry-major so that consecutive rows are adjacent; padding has
+ * value 0 and column 0). Parameters (set by the host):
+ * LOCAL_SIZE is the 1D work group size
+ * ELL_WIDTH is the longest row of A, the row width of the ELL arrays
+ * VECTOR_WIDTH work items share a row in spmv_csr_vector; a power of two dividing LOCAL_SIZE
+ * SUBGROUP_KHR (cl_khr_subgroups) or SUBGROUP_INTEL (cl_intel_subgroups) enables spmv_csr_subgroup */
+
+#include "include/subgroup_extensions.h"
+
+/* One work item per row; neighbouring work items read far apart, and long rows stall the whole group */
+__kernel void spmv_csr_scalar(const __global uint* row_offsets,
+                              const __global uint* col_indices,
+                              const __global float* values,
+                              const __global float* x,
+                              __global float* y,
+                              const uint M) {
+    const uint row = get_global_id(0);
+    if (row >= M) return;
+
+    float acc = 0.0f;
+    for (uint i = row_offsets[row]; i < row_offsets[row + 1]; i++) {
+        acc += values[i] * x[col_indices[i]];
+    }
+    y[row] = acc;
+}
+
+/* VECTOR_WIDTH work items per row read the row's entries together (coalesced) and combine their
+ * partial sums with a tree in local memory */
+__kernel void spmv_csr_vector(const __global uint* row_offsets,
+                              const __global uint* col_indices,
+                              const __global float* values,
+                              const __global float* x,
+                              __global float* y,
+                              const uint M) {
+    __local float partial[LOCAL_SIZE];
+    const uint lid = get_local_id(0);
+    const uint lane = lid % VECTOR_WIDTH;
+    const uint row = get_global_id(0) / VECTOR_WIDTH;
+
+    float acc = 0.0f;
+    if (row < M) {
+        for (uint i = row_offsets[row] + lane; i < row_offsets[row + 1]; i += VECTOR_WIDTH) {
+            acc += values[i] * x[col_indices[i]];
+        }
+    }
+    partial[lid] = acc;
+    barrier(CLK_LOCAL_MEM_FENCE);
+
+    for (uint stride = VECTOR_WIDTH / 2; stride > 0; stride >>= 1) {
+        if (lane < stride) partial[lid] += partial[lid + stride];
+        barrier(CLK_LOCAL_MEM_FENCE);
+    }
+    if (lane == 0 && row < M) y[row] = partial[lid];
+}
+
+#if defined(SUBGROUP_KHR) || defined(SUBGROUP_INTEL)
+/* One subgroup per row, reduced in registers. The subgroup size is only known on the device, so rows
+ * are distributed in a grid-stride loop over all subgroups of the launch. */
+__kernel void spmv_csr_subgroup(const __global uint* row_offsets,
+                                const __global uint* col_indices,
+                                const __global float* values,
+                                const __global float* x,
+                                __global float* y,
+                                const uint M) {
+    const uint lane = get_sub_group_local_id();
+    const uint subgroup_size = get_sub_group_size();
+    const uint subgroups = get_num_groups(0) * get_num_sub_groups();
+
+    for (uint row = get_group_id(0) * get_num_sub_groups() + get_sub_group_id(); row < M; row += subgroups) {
+        float acc = 0.0f;
+        for (uint i = row_offsets[row] + lane; i < row_offsets[row + 1]; i += subgroup_size) {
+            acc += values[i] * x[col_indices[i]];
+        }
+        acc = sub_group_reduce_add(acc);
+        if (lane == 0) y[row] = acc;
+    }
+}
+#endif
+
+/* One work item per row; the entry-major layout makes every load coalesced at the cost of padding */
+__kernel void spmv_ell(const __global uint* col_indices,
+                       const __global float* values,
+                       const __global float* x,
+                       __global float* y,
+                       const uint M) {
+    const uint row = get_global_id(0);
+    if (row >= M) return;
+
+    float acc = 0.0f;
+    for (uint k = 0; k < ELL_WIDTH; k++) {
+        acc += values[k * M + row] * x[col_indices[k * M + row]];
+    }
+    y[row] = acc;
+}
+
+/* C = A * B with a dense N x P B; NDRange (P, M), rounded up. Work items of a row share the entries of A
+ * (broadcast loads) while reading consecutive columns of B. */
+__kernel void spmm_csr(const __global uint* row_offsets,
+                       const __global uint* col_indices,
+                       const __global float* values,
+                       const __global float* B,
+                       __global float* C,
+                       const uint M, const uint P) {
+    const uint col = get_global_id(0);
+    const uint row = get_global_id(1);
+    if (col >= P || row >= M) return;
+
+    float acc = 0.0f;
+    for (uint i = row_offsets[row]; i < row_offsets[row + 1]; i++) {
+        acc += values[i] * B[col_indices[i] * P + col];
+    }
+    C[row * P + col] = acc;
+}
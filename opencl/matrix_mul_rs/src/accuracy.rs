@@ -0,0 +1,73 @@
+use std::{cmp, thread, fmt, fmt::{Display, Formatter}};
+
+/* Error of a float result against a double precision reference. Unlike verify_results' fixed tolerance,
+ * these show how far off a result is, so that algorithms can be compared. */
+pub struct ErrorStats {
+    pub max_abs: f64,
+    pub rms: f64,
+    /* Largest error relative to the largest magnitude in the reference */
+    pub normwise: f64,
+    /* Largest error in units in the last place of the float nearest to the expected value */
+    pub max_ulps: f64
+}
+
+impl ErrorStats {
+    pub fn measure(expected: &[f64], actual: &[f32]) -> ErrorStats {
+        let (mut max_abs, mut sum_sq, mut max_ulps, mut max_expected) = (0.0f64, 0.0f64, 0.0f64, 0.0f64);
+        for (&expected, &actual) in expected.iter().zip(actual.iter()) {
+            let error = (actual as f64 - expected).abs();
+            max_abs = max_abs.max(error);
+            sum_sq += error * error;
+            max_ulps = max_ulps.max(error / ulp(expected as f32));
+            max_expected = max_expected.max(expected.abs());
+        }
+        ErrorStats {
+            max_abs,
+            rms: (sum_sq / cmp::max(1, expected.len()) as f64).sqrt(),
+            normwise: if max_expected > 0.0 { max_abs / max_expected } else { max_abs },
+            max_ulps
+        }
+    }
+
+    pub fn print(&self) {
+        println!("Error against the f64 reference: {}", self);
+    }
+}
+
+impl Display for ErrorStats {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "max {:.3e}, rms {:.3e}, normwise {:.3e}, max {:.1} ulps", self.max_abs, self.rms, self.normwise, self.max_ulps)
+    }
+}
+
+/* Spacing of floats at x */
+fn ulp(x: f32) -> f64 {
+    let x = x.abs();
+    if x == 0.0 || !x.is_finite() { return ::std::f32::MIN_POSITIVE as f64 * ::std::f32::EPSILON as f64; }
+    (f32::from_bits(x.to_bits() + 1) as f64) - x as f64
+}
+
+/* C = A * B accumulated in double precision, split by rows over all cores: the reference for large problems */
+pub fn cpu_gemm_f64(a: &[f32], b: &[f32], m: u32, n: u32, p: u32) -> Vec<f64> {
+    let (m, n, p) = (m as usize, n as usize, p as usize);
+    let mut c = vec![0.0f64; m * p];
+    let threads = thread::available_parallelism().map(|t| t.get()).unwrap_or(1);
+    let rows_per_thread = cmp::max(1, (m + threads - 1) / threads);
+    thread::scope(|scope| {
+        for (chunk_index, c_rows) in c.chunks_mut(rows_per_thread * p).enumerate() {
+            scope.spawn(move || {
+                for (i, c_row) in c_rows.chunks_mut(p).enumerate() {
+                    let row = chunk_index * rows_per_thread + i;
+                    /* i-k-j order keeps the inner loop on consecutive elements of B and C */
+                    for k in 0..n {
+                        let a_ik = a[row * n + k] as f64;
+                        for (c_ij, &b_kj) in c_row.iter_mut().zip(b[k * p..(k + 1) * p].iter()) {
+                            *c_ij += a_ik * b_kj as f64;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    c
+}
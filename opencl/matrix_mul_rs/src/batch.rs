@@ -0,0 +1,101 @@
+use std::{cmp, fmt, fmt::{Display, Formatter}};
+use gen_error::{GenResult, GenError};
+
+/* Dimensions of one multiplication: M-by-N times N-by-P */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchShape {
+    pub m: u32,
+    pub n: u32,
+    pub p: u32
+}
+
+impl BatchShape {
+    /* Parses "MxNxP" */
+    pub fn parse(s: &str) -> GenResult<BatchShape> {
+        let dims = s.trim().split('x').map(|d| d.parse::<u32>()).collect::<Result<Vec<_>, _>>()?;
+        match dims.as_slice() {
+            &[m, n, p] if m > 0 && n > 0 && p > 0 => Ok(BatchShape { m, n, p }),
+            _ => gen_error_format!("Expected batch dimensions as MxNxP, got \"{}\"", s)
+        }
+    }
+
+    pub fn flops(&self) -> u64 {
+        (2 * self.n as u64 - 1) * self.m as u64 * self.p as u64
+    }
+}
+
+impl Display for BatchShape {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}x{}x{}", self.m, self.n, self.p)
+    }
+}
+
+/* Where each multiplication of a batch lives within the A, B and C buffers */
+pub struct BatchLayout {
+    pub shapes: Vec<BatchShape>,
+    /* All shapes are equal, so the kernel can use fixed strides */
+    pub uniform: bool,
+    /* A, B and C element offsets of every entry */
+    pub offsets: Vec<[u32; 3]>,
+    pub lens: [usize; 3]
+}
+
+impl BatchLayout {
+    /* Per-batch shapes are repeated cyclically to `count` entries (or used as-is if `count` is 0) */
+    pub fn new(count: u32, shapes: &[BatchShape]) -> BatchLayout {
+        let count = if count == 0 { shapes.len() } else { count as usize };
+        let shapes = shapes.iter().cycle().take(count).cloned().collect::<Vec<_>>();
+        let uniform = shapes.windows(2).all(|pair| pair[0] == pair[1]);
+
+        let mut offsets = Vec::with_capacity(count);
+        let mut lens = [0usize; 3];
+        for shape in shapes.iter() {
+            offsets.push([lens[0] as u32, lens[1] as u32, lens[2] as u32]);
+            lens[0] += (shape.m * shape.n) as usize;
+            lens[1] += (shape.n * shape.p) as usize;
+            lens[2] += (shape.m * shape.p) as usize;
+        }
+        BatchLayout { shapes, uniform, offsets, lens }
+    }
+
+    /* Largest extent in each dimension, which the NDRange has to cover */
+    pub fn max_shape(&self) -> BatchShape {
+        self.shapes.iter().fold(BatchShape { m: 0, n: 0, p: 0 }, |max, shape| BatchShape {
+            m: cmp::max(max.m, shape.m), n: cmp::max(max.n, shape.n), p: cmp::max(max.p, shape.p)
+        })
+    }
+
+    pub fn flops(&self) -> u64 {
+        self.shapes.iter().map(|shape| shape.flops()).sum()
+    }
+
+    /* Shapes and offsets as uint4s for the kernel's per-batch arguments */
+    pub fn kernel_tables(&self) -> (Vec<u32>, Vec<u32>) {
+        let shapes = self.shapes.iter().flat_map(|s| vec![s.m, s.n, s.p, 0]).collect();
+        let offsets = self.offsets.iter().flat_map(|o| vec![o[0], o[1], o[2], 0]).collect();
+        (shapes, offsets)
+    }
+}
+
+/* Deterministic inputs in [-1, 1), so runs are reproducible without matrix files */
+pub fn generate_matrix(len: usize, seed: u32) -> Vec<f32> {
+    let mut state = seed.wrapping_mul(2654435761).wrapping_add(1);
+    (0..len).map(|_| {
+        state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+        (state >> 8) as f32 / (1u32 << 23) as f32 - 1.0
+    }).collect()
+}
+
+pub fn cpu_gemm(a: &[f32], b: &[f32], shape: BatchShape) -> Vec<f32> {
+    let (m, n, p) = (shape.m as usize, shape.n as usize, shape.p as usize);
+    let mut c = vec![0.0f32; m * p];
+    for i in 0..m {
+        for k in 0..n {
+            let a_ik = a[i * n + k];
+            for j in 0..p {
+                c[i * p + j] += a_ik * b[k * p + j];
+            }
+        }
+    }
+    c
+}
@@ -0,0 +1,340 @@
+use std::{fs, fs::File, io::prelude::*, path::Path};
+use ocl::{Device, Program};
+use ocl::enums::{ProgramInfo, ProgramInfoResult};
+use gen_error::{GenResult, GenError};
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const SHT_SYMTAB: u32 = 2;
+const SHT_NOTE: u32 = 7;
+/* Type of the note holding AMD code object v3+ metadata, owned by "AMDGPU" */
+const NT_AMDGPU_METADATA: u32 = 32;
+const STT_FUNC: u8 = 2;
+/* Kernel symbols of AMD code object v2 point at an amd_kernel_code_t header */
+const STT_AMDGPU_HSA_KERNEL: u8 = 10;
+/* Offsets of wavefront_sgpr_count and workitem_vgpr_count within amd_kernel_code_t */
+const AMD_KERNEL_CODE_SGPR_OFFSET: usize = 84;
+const AMD_KERNEL_CODE_VGPR_OFFSET: usize = 86;
+const AMD_KERNEL_CODE_SIZE: u64 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryFormat { Elf, SpirV, Ptx, Unknown }
+
+impl BinaryFormat {
+    pub fn detect(binary: &[u8]) -> BinaryFormat {
+        if binary.starts_with(ELF_MAGIC) {
+            BinaryFormat::Elf
+        }
+        else if binary.len() >= 4 && (read_u32(binary, 0, true) == SPIRV_MAGIC || read_u32(binary, 0, false) == SPIRV_MAGIC) {
+            BinaryFormat::SpirV
+        }
+        else if String::from_utf8_lossy(&binary[..binary.len().min(4096)]).contains(".target") {
+            BinaryFormat::Ptx
+        }
+        else {
+            BinaryFormat::Unknown
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match *self {
+            BinaryFormat::Elf => "elf",
+            BinaryFormat::SpirV => "spv",
+            BinaryFormat::Ptx => "ptx",
+            BinaryFormat::Unknown => "bin"
+        }
+    }
+}
+
+/* Register usage and code size of one kernel in a binary; unknown values are None */
+#[derive(Debug, Default)]
+pub struct KernelStats {
+    pub name: String,
+    pub sgprs: Option<u32>,
+    pub vgprs: Option<u32>,
+    /* PTX virtual registers; ptxas allocates physical ones when the driver finalizes the binary */
+    pub registers: Option<u32>,
+    pub code_bytes: Option<u64>,
+    pub instructions: Option<u32>
+}
+
+/* Writes the binary the driver built for each device of the program to `<dir>/<stem>_<device>.<ext>`,
+ * plus the code sections of ELF binaries, and prints per-kernel statistics where the format is known */
+pub fn dump_program(program: &Program, dir: &str, stem: &str) -> GenResult<()> {
+    let devices = match program.info(ProgramInfo::Devices)? {
+        ProgramInfoResult::Devices(devices) => devices,
+        _ => return gen_error_format!("Unable to query the program's devices")
+    };
+    let binaries = match program.info(ProgramInfo::Binaries)? {
+        ProgramInfoResult::Binaries(binaries) => binaries,
+        _ => return gen_error_format!("Unable to query the program's binaries")
+    };
+    fs::create_dir_all(dir)?;
+
+    for (device_id, binary) in devices.into_iter().zip(binaries.iter()) {
+        let device_name = Device::from(device_id).name()?;
+        let format = BinaryFormat::detect(binary);
+        let path = Path::new(dir).join(format!("{}_{}.{}", stem, sanitize(&device_name), format.extension()));
+        File::create(&path)?.write_all(binary)?;
+        println!("Binary for {}: {:?}, {} bytes, written to {}", device_name, format, binary.len(), path.display());
+
+        let stats = match format {
+            BinaryFormat::Elf => elf_stats(binary, &path)?,
+            BinaryFormat::SpirV => spirv_stats(binary),
+            BinaryFormat::Ptx => ptx_stats(&String::from_utf8_lossy(binary)),
+            BinaryFormat::Unknown => Vec::new()
+        };
+        for kernel in stats.iter() {
+            print_kernel_stats(kernel);
+        }
+    }
+    Ok(())
+}
+
+fn print_kernel_stats(kernel: &KernelStats) {
+    let mut parts = Vec::new();
+    if let Some(vgprs) = kernel.vgprs { parts.push(format!("{} VGPRs", vgprs)); }
+    if let Some(sgprs) = kernel.sgprs { parts.push(format!("{} SGPRs", sgprs)); }
+    if let Some(registers) = kernel.registers { parts.push(format!("{} virtual registers", registers)); }
+    if let Some(code_bytes) = kernel.code_bytes { parts.push(format!("{} bytes of code", code_bytes)); }
+    if let Some(instructions) = kernel.instructions { parts.push(format!("{} instructions", instructions)); }
+    println!("    {}: {}", kernel.name, if parts.is_empty() { "no statistics available".to_owned() } else { parts.join(", ") });
+}
+
+struct ElfSection {
+    name: String,
+    kind: u32,
+    address: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    entry_size: u64
+}
+
+/* Only 64-bit ELF is handled, which covers current AMD, Intel and POCL device binaries */
+fn parse_elf_sections(elf: &[u8]) -> GenResult<(Vec<ElfSection>, bool)> {
+    if elf.len() < 64 || elf[4] != 2 {
+        return gen_error_format!("Not a 64-bit ELF binary");
+    }
+    let le = elf[5] == 1;
+    let (sh_offset, sh_entry_size, sh_count, sh_names_index) =
+        (read_u64(elf, 0x28, le) as usize, read_u16(elf, 0x3a, le) as usize, read_u16(elf, 0x3c, le) as usize, read_u16(elf, 0x3e, le) as usize);
+    if sh_entry_size < 0x40 || sh_offset.checked_add(sh_count * sh_entry_size).map(|end| end > elf.len()).unwrap_or(true) || sh_names_index >= sh_count {
+        return gen_error_format!("Truncated ELF section header table");
+    }
+
+    let mut sections = (0..sh_count).map(|i| {
+        let header = sh_offset + i * sh_entry_size;
+        ElfSection {
+            name: String::new(),
+            kind: read_u32(elf, header + 4, le),
+            address: read_u64(elf, header + 0x10, le),
+            offset: read_u64(elf, header + 0x18, le),
+            size: read_u64(elf, header + 0x20, le),
+            link: read_u32(elf, header + 0x28, le),
+            entry_size: read_u64(elf, header + 0x38, le)
+        }
+    }).collect::<Vec<_>>();
+    let names_offset = sections[sh_names_index].offset as usize;
+    for i in 0..sh_count {
+        let name_index = read_u32(elf, sh_offset + i * sh_entry_size, le) as usize;
+        sections[i].name = read_c_string(elf, names_offset.saturating_add(name_index));
+    }
+    Ok((sections, le))
+}
+
+fn elf_stats(elf: &[u8], path: &Path) -> GenResult<Vec<KernelStats>> {
+    let (sections, le) = parse_elf_sections(elf)?;
+    /* None for sections reaching past the end of the file, or past the end of the address space */
+    let section_data = |section: &ElfSection| section.offset.checked_add(section.size).and_then(|end| elf.get(section.offset as usize..end as usize));
+
+    /* The ISA lives in .text on AMD and POCL, and in .text.<kernel> sections on Intel */
+    for section in sections.iter().filter(|s| s.name.starts_with(".text") && s.size > 0) {
+        if let Some(code) = section_data(section) {
+            let code_path = path.with_extension(format!("{}.isa", sanitize(section.name.trim_start_matches('.'))));
+            File::create(&code_path)?.write_all(code)?;
+            println!("    section {} ({} bytes) written to {}", section.name, section.size, code_path.display());
+        }
+    }
+
+    let mut kernels = Vec::new();
+    for symtab in sections.iter().filter(|s| s.kind == SHT_SYMTAB && s.entry_size >= 24) {
+        let (symbols, names) = match (section_data(symtab), sections.get(symtab.link as usize)) {
+            (Some(symbols), Some(names)) => (symbols, names.offset as usize),
+            _ => continue
+        };
+        for symbol in symbols.chunks(symtab.entry_size as usize).filter(|s| s.len() >= 24) {
+            let kind = symbol[4] & 0xf;
+            if kind != STT_FUNC && kind != STT_AMDGPU_HSA_KERNEL { continue; }
+            let name = read_c_string(elf, names.saturating_add(read_u32(symbol, 0, le) as usize));
+            let (section_index, value, size) = (read_u16(symbol, 6, le) as usize, read_u64(symbol, 8, le), read_u64(symbol, 16, le));
+            let mut stats = KernelStats { name, code_bytes: Some(size), ..KernelStats::default() };
+
+            if kind == STT_AMDGPU_HSA_KERNEL {
+                /* Code object v2: the symbol covers amd_kernel_code_t followed by the code */
+                stats.code_bytes = Some(size.saturating_sub(AMD_KERNEL_CODE_SIZE));
+                if let Some(section) = sections.get(section_index) {
+                    /* Symbol values are load addresses in executables and section offsets in relocatable objects */
+                    let header = section.offset.saturating_add(value.saturating_sub(section.address)) as usize;
+                    if header.saturating_add(AMD_KERNEL_CODE_VGPR_OFFSET + 2) <= elf.len() {
+                        stats.sgprs = Some(read_u16(elf, header + AMD_KERNEL_CODE_SGPR_OFFSET, le) as u32);
+                        stats.vgprs = Some(read_u16(elf, header + AMD_KERNEL_CODE_VGPR_OFFSET, le) as u32);
+                    }
+                }
+            }
+            kernels.push(stats);
+        }
+    }
+
+    /* Code object v3 and later list register counts per kernel in msgpack metadata instead */
+    let metadata = sections.iter().filter(|s| s.kind == SHT_NOTE)
+        .filter_map(|s| section_data(s)).filter_map(|notes| amdgpu_metadata_note(notes, le)).next();
+    if let Some(metadata) = metadata {
+        for (name, sgprs, vgprs) in amdgpu_metadata_counts(metadata) {
+            match kernels.iter_mut().find(|k| k.name == name) {
+                Some(kernel) => { kernel.sgprs = sgprs; kernel.vgprs = vgprs; },
+                None => kernels.push(KernelStats { name, sgprs, vgprs, ..KernelStats::default() })
+            }
+        }
+    }
+    Ok(kernels)
+}
+
+/* The descriptor of the NT_AMDGPU_METADATA note in a note section, if there is one */
+fn amdgpu_metadata_note(notes: &[u8], le: bool) -> Option<&[u8]> {
+    let mut at = 0;
+    while at + 12 <= notes.len() {
+        let (name_size, desc_size, kind) = (read_u32(notes, at, le) as usize, read_u32(notes, at + 4, le) as usize, read_u32(notes, at + 8, le));
+        /* Name and descriptor are each padded to 4 bytes */
+        let desc_start = (at + 12).checked_add(name_size.checked_add(3)? & !3)?;
+        let desc_end = desc_start.checked_add(desc_size)?;
+        let (name, desc) = (notes.get(at + 12..at + 12 + name_size)?, notes.get(desc_start..desc_end)?);
+        if kind == NT_AMDGPU_METADATA && name.starts_with(b"AMDGPU") {
+            return Some(desc);
+        }
+        at = desc_end.checked_add(3)? & !3;
+    }
+    None
+}
+
+/* Scans the NT_AMDGPU_METADATA msgpack blob for the .name, .sgpr_count and .vgpr_count keys of each kernel;
+ * keys of one kernel map are adjacent, and .name may come before or after the counts */
+fn amdgpu_metadata_counts(elf: &[u8]) -> Vec<(String, Option<u32>, Option<u32>)> {
+    let mut kernels: Vec<(String, Option<u32>, Option<u32>)> = Vec::new();
+    let (mut name, mut sgprs, mut vgprs) = (None, None, None);
+    let mut i = 0;
+    while i < elf.len() {
+        if let Some((key, value_start)) = msgpack_str(elf, i).filter(|&(ref key, _)| key.starts_with('.')) {
+            match key.as_str() {
+                ".name" => if let Some((value, _)) = msgpack_str(elf, value_start) { name = Some(value); },
+                ".sgpr_count" => sgprs = msgpack_uint(elf, value_start),
+                ".vgpr_count" => vgprs = msgpack_uint(elf, value_start),
+                _ => ()
+            }
+            if let (&Some(_), &Some(_), &Some(_)) = (&name, &sgprs, &vgprs) {
+                kernels.push((name.take().unwrap(), sgprs.take(), vgprs.take()));
+            }
+            i = value_start;
+        }
+        else {
+            i += 1;
+        }
+    }
+    kernels
+}
+
+/* A printable msgpack fixstr/str8 at `at`; returns it and the offset past it */
+fn msgpack_str(data: &[u8], at: usize) -> Option<(String, usize)> {
+    let (len, start) = match *data.get(at)? {
+        tag @ 0xa2..=0xbf => ((tag & 0x1f) as usize, at + 1),
+        0xd9 => (*data.get(at + 1)? as usize, at + 2),
+        _ => return None
+    };
+    let text = data.get(start..start + len)?;
+    if !text.iter().all(|&c| c.is_ascii_graphic()) {
+        return None;
+    }
+    Some((String::from_utf8_lossy(text).into_owned(), start + len))
+}
+
+fn msgpack_uint(data: &[u8], at: usize) -> Option<u32> {
+    match *data.get(at)? {
+        value @ 0x00..=0x7f => Some(value as u32),
+        0xcc => data.get(at + 1).map(|&v| v as u32),
+        0xcd if at + 3 <= data.len() => Some(read_u16(data, at + 1, false) as u32),
+        0xce if at + 5 <= data.len() => Some(read_u32(data, at + 1, false)),
+        _ => None
+    }
+}
+
+/* Registers are declared per entry as `.reg .f32 %f<42>;` */
+fn ptx_stats(ptx: &str) -> Vec<KernelStats> {
+    let mut kernels: Vec<KernelStats> = Vec::new();
+    for line in ptx.lines().map(|l| l.trim()) {
+        if let Some(i) = line.find(".entry ") {
+            let name = line[i + ".entry ".len()..].split(|c: char| c == '(' || c.is_whitespace()).next().unwrap_or("").to_owned();
+            kernels.push(KernelStats { name, registers: Some(0), instructions: Some(0), ..KernelStats::default() });
+            continue;
+        }
+        let kernel = match kernels.last_mut() {
+            Some(kernel) => kernel,
+            None => continue
+        };
+        if line.starts_with(".reg ") {
+            let count = line.rfind('<').and_then(|i| line[i + 1..].split('>').next()).and_then(|n| n.parse::<u32>().ok()).unwrap_or(1);
+            kernel.registers = kernel.registers.map(|r| r + count);
+        }
+        else if line.ends_with(';') && !line.starts_with('.') && !line.starts_with("//") {
+            kernel.instructions = kernel.instructions.map(|n| n + 1);
+        }
+    }
+    kernels
+}
+
+/* SPIR-V carries no ISA; entry point names and the instruction count are all there is to report */
+fn spirv_stats(spirv: &[u8]) -> Vec<KernelStats> {
+    const OP_ENTRY_POINT: u32 = 15;
+    let le = read_u32(spirv, 0, true) == SPIRV_MAGIC;
+    let words = (0..spirv.len() / 4).map(|i| read_u32(spirv, i * 4, le)).collect::<Vec<_>>();
+    let (mut entry_points, mut instructions, mut i) = (Vec::new(), 0, 5);
+    while i < words.len() {
+        let (word_count, opcode) = ((words[i] >> 16) as usize, words[i] & 0xffff);
+        if word_count == 0 { break; }
+        if opcode == OP_ENTRY_POINT && i + 3 < words.len() {
+            let name_bytes = words[i + 3..(i + word_count).min(words.len())].iter()
+                .flat_map(|w| vec![*w as u8, (*w >> 8) as u8, (*w >> 16) as u8, (*w >> 24) as u8])
+                .take_while(|&b| b != 0).collect::<Vec<_>>();
+            entry_points.push(String::from_utf8_lossy(&name_bytes).into_owned());
+        }
+        instructions += 1;
+        i += word_count;
+    }
+    entry_points.into_iter()
+        .map(|name| KernelStats { name, instructions: Some(instructions), code_bytes: Some(spirv.len() as u64), ..KernelStats::default() })
+        .collect()
+}
+
+fn sanitize(s: &str) -> String {
+    s.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '.' { c } else { '_' }).collect()
+}
+
+fn read_c_string(data: &[u8], at: usize) -> String {
+    let bytes = data.get(at..).unwrap_or(&[]);
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+fn read_u16(data: &[u8], at: usize, le: bool) -> u16 {
+    let bytes = [data[at], data[at + 1]];
+    if le { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) }
+}
+
+fn read_u32(data: &[u8], at: usize, le: bool) -> u32 {
+    let bytes = [data[at], data[at + 1], data[at + 2], data[at + 3]];
+    if le { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) }
+}
+
+fn read_u64(data: &[u8], at: usize, le: bool) -> u64 {
+    let (low, high) = (read_u32(data, at, le) as u64, read_u32(data, at + 4, le) as u64);
+    if le { low | (high << 32) } else { (low << 32) | high }
+}
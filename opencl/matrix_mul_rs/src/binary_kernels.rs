@@ -0,0 +1,128 @@
+use std::{path::Path, io::BufReader, io::prelude::*};
+use gen_error::{GenResult, GenError};
+use open_file;
+
+/* Values a precompiled kernel can take as arguments, in the order it declares them */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KernelArg {
+    /* Input matrices as read from the matrix files */
+    A, B,
+    /* Inputs with columns zero-padded to a multiple of tile_size, as used by wideloads.cl */
+    APadded, BPadded,
+    C,
+    M, N, P,
+    /* N and P rounded up to a multiple of tile_size */
+    NPadded, PPadded,
+    TileSize
+}
+
+impl KernelArg {
+    fn parse(s: &str) -> GenResult<KernelArg> {
+        Ok(match s {
+            "a" => KernelArg::A,
+            "b" => KernelArg::B,
+            "a_padded" => KernelArg::APadded,
+            "b_padded" => KernelArg::BPadded,
+            "c" => KernelArg::C,
+            "m" => KernelArg::M,
+            "n" => KernelArg::N,
+            "p" => KernelArg::P,
+            "n_padded" => KernelArg::NPadded,
+            "p_padded" => KernelArg::PPadded,
+            "tile_size" => KernelArg::TileSize,
+            _ => return gen_error_format!("Unknown kernel argument \"{}\"; expected one of a, b, a_padded, b_padded, c, m, n, p, n_padded, p_padded, tile_size", s)
+        })
+    }
+}
+
+/* A GEMM kernel loaded from a device binary (e.g. a code object produced by an assembler) instead of source */
+#[derive(Debug, Clone)]
+pub struct BinaryKernel {
+    /* Label in reports */
+    pub name: String,
+    pub binary_path: String,
+    pub kernel_name: String,
+    pub args: Vec<KernelArg>,
+    pub local_size: [u32; 2],
+    /* Rows and columns of C computed by each work item */
+    pub block: [u32; 2],
+    pub options: String
+}
+
+impl BinaryKernel {
+    /* One work item per block of C, rounded up to whole work groups */
+    pub fn global_size(&self, m: u32, p: u32) -> [u32; 2] {
+        let items = [(m + self.block[0] - 1) / self.block[0], (p + self.block[1] - 1) / self.block[1]];
+        [(items[0] + self.local_size[0] - 1) / self.local_size[0] * self.local_size[0],
+         (items[1] + self.local_size[1] - 1) / self.local_size[1] * self.local_size[1]]
+    }
+
+    pub fn read_binary(&self) -> GenResult<Vec<u8>> {
+        let mut binary = Vec::new();
+        open_file(&self.binary_path)?.read_to_end(&mut binary)?;
+        Ok(binary)
+    }
+}
+
+/* Manifest format, one setting per line:
+ *   # comment
+ *   [gemm_asm]                  -- starts a kernel, named gemm_asm in reports
+ *   binary gemm_gfx900.co       -- device binary, relative to the manifest
+ *   kernel gemm                 -- kernel symbol, the section name by default
+ *   args a b c m n p            -- arguments in declaration order
+ *   local 16x16                 -- work group size
+ *   block 1x4                   -- rows x cols of C per work item, 1x1 by default
+ *   options -cl-std=CL2.0       -- passed to clBuildProgram */
+pub fn load_manifest(filename: &str) -> GenResult<Vec<BinaryKernel>> {
+    let base_dir = Path::new(filename).parent().unwrap_or(Path::new(""));
+    let mut kernels: Vec<BinaryKernel> = Vec::new();
+
+    for (line_i, line) in BufReader::new(open_file(filename)?).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let location_error = |e: GenError| GenError::from(format!("{}:{}: {}", filename, line_i + 1, e));
+
+        if line.starts_with('[') && line.ends_with(']') {
+            let name = line[1..line.len() - 1].trim().to_owned();
+            kernels.push(BinaryKernel {
+                kernel_name: name.clone(), name, binary_path: String::new(), args: Vec::new(),
+                local_size: [0, 0], block: [1, 1], options: String::new()
+            });
+            continue;
+        }
+        let kernel = match kernels.last_mut() {
+            Some(kernel) => kernel,
+            None => return gen_error_format!("{}:{}: \"{}\" outside of a [kernel] section", filename, line_i + 1, line)
+        };
+        let (key, value) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, "")
+        };
+        match key {
+            "binary" => kernel.binary_path = base_dir.join(value).to_string_lossy().into_owned(),
+            "kernel" => kernel.kernel_name = value.to_owned(),
+            "args" => kernel.args = value.split_whitespace().map(KernelArg::parse).collect::<GenResult<_>>().map_err(location_error)?,
+            "local" => kernel.local_size = parse_pair(value, 1).map_err(location_error)?,
+            "block" => kernel.block = parse_pair(value, 1).map_err(location_error)?,
+            "options" => kernel.options = value.to_owned(),
+            _ => return gen_error_format!("{}:{}: unrecognized manifest entry \"{}\"", filename, line_i + 1, line)
+        }
+    }
+
+    for kernel in kernels.iter() {
+        if kernel.binary_path.is_empty() || kernel.args.is_empty() || kernel.local_size[0] == 0 {
+            return gen_error_format!("{}: [{}] needs at least a binary, args and a local size", filename, kernel.name);
+        }
+    }
+    Ok(kernels)
+}
+
+/* Parses "16x16", requiring both values to be at least min */
+pub fn parse_pair(s: &str, min: u32) -> GenResult<[u32; 2]> {
+    let values = s.split('x').map(|v| v.trim().parse::<u32>()).collect::<Result<Vec<_>, _>>()?;
+    match values.as_slice() {
+        &[a, b] if a >= min && b >= min => Ok([a, b]),
+        _ => gen_error_format!("Expected ROWSxCOLS (each at least {}), got \"{}\"", min, s)
+    }
+}
@@ -0,0 +1,187 @@
+use std::{fmt, fmt::{Display, Formatter}, io::BufReader, io::prelude::*};
+use gen_error::{GenResult, GenError};
+use open_file;
+
+/* Preprocessor defines and compiler options a single kernel program is built with */
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildVariant {
+    pub defines: Vec<(String, String)>,
+    pub options: String
+}
+
+impl BuildVariant {
+    pub fn source_defines(&self) -> String {
+        self.defines.iter().map(|&(ref name, ref value)| format!("#define {} {}\n", name, value)).collect()
+    }
+
+    fn with_define(&self, name: &str, value: &str) -> BuildVariant {
+        let mut variant = self.clone();
+        variant.defines.push((name.to_owned(), value.to_owned()));
+        variant
+    }
+}
+
+impl Display for BuildVariant {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let defines = self.defines.iter().map(|&(ref name, ref value)| format!("{}={}", name, value)).collect::<Vec<_>>();
+        write!(f, "defines: [{}], build options: [{}]", defines.join(" "), self.options)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct KernelBuildSettings {
+    /* Each define maps to the list of values it is swept over */
+    defines: Vec<(String, Vec<String>)>,
+    /* Alternative compiler option strings, e.g. "-cl-fast-relaxed-math -cl-mad-enable" */
+    options: Vec<String>
+}
+
+impl KernelBuildSettings {
+    fn set_define(&mut self, name: &str, values: Vec<String>) {
+        match self.defines.iter_mut().find(|&&mut (ref n, _)| n == name) {
+            Some(define) => { define.1 = values; return; }
+            None => ()
+        }
+        self.defines.push((name.to_owned(), values));
+    }
+}
+
+/* Build settings collected from the command line and manifest files. Settings scoped to a kernel
+ * (`tiled:NAME=1` on the command line, a `[tiled]` section in a manifest) take precedence over
+ * global ones: a scoped define replaces the global one with the same name, and scoped option
+ * strings replace the global list altogether. */
+#[derive(Debug, Clone, Default)]
+pub struct BuildConfig {
+    global: KernelBuildSettings,
+    per_kernel: Vec<(String, KernelBuildSettings)>,
+    pub sweep: bool
+}
+
+impl BuildConfig {
+    /* Accepts [kernel:]NAME=VALUE[,VALUE...]; a define without a value expands to 1 */
+    pub fn add_define(&mut self, spec: &str) -> GenResult<()> {
+        let (kernel, define) = split_kernel_scope(spec);
+        let (name, values) = match define.find('=') {
+            Some(i) => (&define[..i], define[i + 1..].split(',').map(|v| v.trim().to_owned()).collect::<Vec<_>>()),
+            None => (define, vec!["1".to_owned()])
+        };
+        let name = name.trim();
+        if name.is_empty() || values.iter().any(|v| v.is_empty()) {
+            return gen_error_format!("Malformed define \"{}\", expected [kernel:]NAME=VALUE[,VALUE...]", spec);
+        }
+        if name == "TILE_SIZE" {
+            return gen_error_format!("TILE_SIZE is set from the tile_size argument and cannot be redefined");
+        }
+        self.settings_mut(kernel).set_define(name, values);
+        Ok(())
+    }
+
+    /* Accepts [kernel:]OPTIONS; each call adds another alternative to sweep over */
+    pub fn add_options(&mut self, spec: &str) {
+        let (kernel, options) = split_kernel_scope(spec);
+        self.settings_mut(kernel).options.push(options.trim().to_owned());
+    }
+
+    /* Manifest format, one setting per line:
+     *   # comment
+     *   define NAME=VALUE[,VALUE...]
+     *   options -cl-mad-enable -cl-std=CL2.0
+     *   [tiled]                 -- settings below only apply to tiled.cl
+     *   [*]                     -- back to settings shared by all kernels */
+    pub fn load_manifest(&mut self, filename: &str) -> GenResult<()> {
+        let mut section: Option<String> = None;
+        for (line_i, line) in BufReader::new(open_file(filename)?).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue; }
+
+            let scope = section.as_ref().map(|s| format!("{}:", s)).unwrap_or_default();
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = line[1..line.len() - 1].trim();
+                section = if name == "*" { None } else { Some(name.to_owned()) };
+            }
+            else if line.starts_with("define ") {
+                self.add_define(&(scope + line["define ".len()..].trim()))
+                    .or_else(|e| gen_error_format!("{}:{}: {}", filename, line_i + 1, e))?;
+            }
+            else if line == "options" || line.starts_with("options ") {
+                self.add_options(&(scope + &line["options".len()..]));
+            }
+            else {
+                return gen_error_format!("{}:{}: unrecognized manifest entry \"{}\"", filename, line_i + 1, line);
+            }
+        }
+        Ok(())
+    }
+
+    /* Without --sweep, ambiguous settings are rejected instead of silently picking one value */
+    pub fn validate(&self) -> GenResult<()> {
+        if self.sweep { return Ok(()); }
+
+        let all_settings = Some(&self.global).into_iter().chain(self.per_kernel.iter().map(|&(_, ref s)| s));
+        for settings in all_settings {
+            if let Some(&(ref name, _)) = settings.defines.iter().find(|&&(_, ref values)| values.len() > 1) {
+                return gen_error_format!("Define {} has several values; pass --sweep to run all combinations", name);
+            }
+            if settings.options.len() > 1 {
+                return gen_error_format!("Several build option sets given; pass --sweep to run all of them");
+            }
+        }
+        Ok(())
+    }
+
+    /* Returns the cartesian product of all define values and option sets applicable to the kernel */
+    pub fn variants_for(&self, kernel_name: &str) -> Vec<BuildVariant> {
+        let settings = self.merged_settings(kernel_name);
+
+        let mut variants = vec![BuildVariant { defines: Vec::new(), options: String::new() }];
+        for &(ref name, ref values) in settings.defines.iter() {
+            variants = variants.iter()
+                .flat_map(|variant| values.iter().map(move |value| variant.with_define(name, value)))
+                .collect();
+        }
+        if settings.options.is_empty() {
+            return variants;
+        }
+        variants.iter()
+            .flat_map(|variant| settings.options.iter().map(move |options| BuildVariant { options: options.clone(), ..variant.clone() }))
+            .collect()
+    }
+
+    fn merged_settings(&self, kernel_name: &str) -> KernelBuildSettings {
+        let mut settings = self.global.clone();
+        if let Some(&(_, ref scoped)) = self.per_kernel.iter().find(|&&(ref k, _)| k == kernel_name) {
+            for &(ref name, ref values) in scoped.defines.iter() {
+                settings.set_define(name, values.clone());
+            }
+            if !scoped.options.is_empty() {
+                settings.options = scoped.options.clone();
+            }
+        }
+        settings
+    }
+
+    fn settings_mut(&mut self, kernel: Option<&str>) -> &mut KernelBuildSettings {
+        let kernel = match kernel {
+            Some(kernel) => kernel,
+            None => return &mut self.global
+        };
+        let index = match self.per_kernel.iter().position(|&(ref k, _)| k == kernel) {
+            Some(index) => index,
+            None => {
+                self.per_kernel.push((kernel.to_owned(), KernelBuildSettings::default()));
+                self.per_kernel.len() - 1
+            }
+        };
+        &mut self.per_kernel[index].1
+    }
+}
+
+/* "tiled:FOO=1" -> (Some("tiled"), "FOO=1"); option strings start with a dash and are never scoped by accident */
+fn split_kernel_scope(spec: &str) -> (Option<&str>, &str) {
+    match spec.find(':') {
+        Some(i) if i > 0 && spec[..i].chars().all(|c| c.is_alphanumeric() || c == '_') =>
+            (Some(&spec[..i]), &spec[i + 1..]),
+        _ => (None, spec)
+    }
+}
@@ -0,0 +1,72 @@
+use std::time::Instant;
+use gen_error::GenResult;
+use memory::{self, MemStrategy};
+use accuracy::{self, ErrorStats};
+use gemm::{DeviceMatrix, DeviceGemm};
+use workload::{self, Peaks};
+use {cli, batch, OclEnv};
+
+/* Multiplies a chain of matrices (matrix i is dims[i] x dims[i + 1]) left to right twice: once round-tripping every
+ * product through the host, as the GEMM workload does, then with device-resident intermediates and one sync at the end */
+pub fn run_chain(args: &cli::Args, ocl_env: &OclEnv, strategy: MemStrategy, peaks: &Peaks) -> GenResult<()> {
+    let dims = &args.chain_dims;
+    let shapes = dims.windows(2).map(|w| format!("{}x{}", w[0], w[1])).collect::<Vec<_>>();
+    println!("===\nRunning a chain of {} multiplications, {}, memory strategy: {}", dims.len() - 2, shapes.join(" * "), strategy);
+    if args.tile_size * args.tile_size > ocl_env.max_work_group_size {
+        println!("Local work size exceeds device limits; skipping the chain.");
+        return Ok(());
+    }
+
+    let matrices = dims.windows(2).enumerate()
+        .map(|(i, w)| batch::generate_matrix((w[0] * w[1]) as usize, 31 + i as u32)).collect::<Vec<_>>();
+    let reference_start = Instant::now();
+    let mut expected = matrices[0].iter().map(|&v| v as f64).collect::<Vec<_>>();
+    for (i, matrix) in matrices.iter().enumerate().skip(1) {
+        let rounded = expected.iter().map(|&v| v as f32).collect::<Vec<_>>();
+        expected = accuracy::cpu_gemm_f64(&rounded, matrix, dims[0], dims[i], dims[i + 1]);
+    }
+    println!("f64 CPU reference took {:.3} [ms]", memory::duration_ms(reference_start.elapsed()));
+    let gemm = DeviceGemm::new(ocl_env, strategy, args.tile_size)?;
+    let flops = (1..dims.len() - 1).map(|i| (2 * dims[i] as u64 - 1) * dims[0] as u64 * dims[i + 1] as u64).sum::<u64>();
+    let bytes = 4 * (dims.windows(2).map(|w| w[0] as u64 * w[1] as u64).sum::<u64>() + dims[0] as u64 * dims[dims.len() - 1] as u64);
+
+    println!("---\nRound trip through the host after every product");
+    let wall_start = Instant::now();
+    let mut product = DeviceMatrix::upload(ocl_env, strategy, &matrices[0], dims[0], dims[1], false, "A1")?;
+    let mut round_trip_ns = 0;
+    for (i, matrix) in matrices.iter().enumerate().skip(1) {
+        let operand = DeviceMatrix::upload(ocl_env, strategy, matrix, dims[i], dims[i + 1], false, &format!("A{}", i + 1))?;
+        let mut result = gemm.multiply(ocl_env, &product, &operand, &format!("round trip, product {}", i))?;
+        round_trip_ns += result.synchronize(ocl_env, &format!("round trip, product {}", i))?;
+        let host_copy = result.read(ocl_env, "product")?;
+        product = DeviceMatrix::upload(ocl_env, strategy, &host_copy, result.rows, result.cols, false, "product")?;
+    }
+    let round_trip = product.read(ocl_env, "result")?;
+    let round_trip_ms = memory::duration_ms(wall_start.elapsed());
+    println!("Wall time {:.3} [ms] with {} host syncs", round_trip_ms, dims.len() - 2);
+    workload::print_perf(peaks, round_trip_ns, Some(flops), bytes);
+
+    println!("---\nDevice-resident intermediates, synchronized once");
+    let wall_start = Instant::now();
+    let operands = matrices.iter().enumerate()
+        .map(|(i, matrix)| DeviceMatrix::upload(ocl_env, strategy, matrix, dims[i], dims[i + 1], false, &format!("A{}", i + 1)))
+        .collect::<GenResult<Vec<_>>>()?;
+    let enqueue_start = Instant::now();
+    let mut chained = gemm.multiply(ocl_env, &operands[0], &operands[1], "chained, product 1")?;
+    for (i, operand) in operands.iter().enumerate().skip(2) {
+        chained = gemm.multiply(ocl_env, &chained, operand, &format!("chained, product {}", i))?;
+    }
+    let enqueue_ms = memory::duration_ms(enqueue_start.elapsed());
+    let chained_ns = chained.synchronize(ocl_env, "chained products")?;
+    let result = chained.read(ocl_env, "result")?;
+    let chained_ms = memory::duration_ms(wall_start.elapsed());
+    println!("Wall time {:.3} [ms] with 1 host sync; enqueueing the chain took {:.3} [ms]", chained_ms, enqueue_ms);
+    workload::print_perf(peaks, chained_ns, Some(flops), bytes);
+
+    ErrorStats::measure(&expected, &result).print();
+    /* Same kernels on the same inputs: anything but identical results means a dependency was missed */
+    println!("Chained against round trip:");
+    workload::verify_exact(&round_trip, &result);
+    println!("Speedup of device-resident chaining: {:.2}x", round_trip_ms / chained_ms);
+    Ok(())
+}
@@ -0,0 +1,380 @@
+use std::slice::Iter;
+use gen_error::{GenResult, GenError};
+use build_config::BuildConfig;
+use subgroup::BlockShape;
+use matrix_io;
+use memory::MemStrategy;
+use epilogue::Epilogue;
+use batch::BatchShape;
+use binary_kernels::{self, BinaryKernel, parse_pair};
+use conv::{ConvParams, Layout, TensorShape};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Workload { Gemm, Pool, Conv, Reduce, Scan, Transpose, Histogram, Spmv, Spmm, Strassen, Precision, Chain }
+
+impl Workload {
+    /* "primitives" stands for reduce, scan, transpose and histogram, "sparse" for spmv and spmm */
+    fn parse_list(s: &str) -> GenResult<Vec<Workload>> {
+        let mut workloads = Vec::new();
+        for name in s.split(',').map(|name| name.trim()) {
+            match name {
+                "gemm" => workloads.push(Workload::Gemm),
+                "pool" => workloads.push(Workload::Pool),
+                "conv" => workloads.push(Workload::Conv),
+                "reduce" => workloads.push(Workload::Reduce),
+                "scan" => workloads.push(Workload::Scan),
+                "transpose" => workloads.push(Workload::Transpose),
+                "histogram" => workloads.push(Workload::Histogram),
+                "spmv" => workloads.push(Workload::Spmv),
+                "spmm" => workloads.push(Workload::Spmm),
+                "primitives" => workloads.extend_from_slice(&[Workload::Reduce, Workload::Scan, Workload::Transpose, Workload::Histogram]),
+                "sparse" => workloads.extend_from_slice(&[Workload::Spmv, Workload::Spmm]),
+                "strassen" => workloads.push(Workload::Strassen),
+                "precision" => workloads.push(Workload::Precision),
+                "chain" => workloads.push(Workload::Chain),
+                _ => return gen_error_format!("Unknown workload \"{}\"; expected gemm, pool, conv, reduce, scan, transpose, histogram, \
+                                               primitives, spmv, spmm, sparse, strassen, precision or chain", name)
+            }
+        }
+        Ok(workloads)
+    }
+}
+
+/* Input matrices: A and B are multiplied, C holds the expected result before any epilogue */
+pub struct MatrixFiles {
+    pub a: String,
+    pub b: String,
+    pub c: String,
+    /* 1-by-p epilogue bias; generated if not given */
+    pub bias: Option<String>
+}
+
+pub struct Args {
+    pub platform_name: String,
+    pub tile_size: u32,
+    pub m: u32,
+    pub n: u32,
+    pub p: u32,
+    pub device_max_gflops: f64,
+    pub build_config: BuildConfig,
+    /* Subgroup sizes to run subgroups.cl with; empty means the ones reported by the device */
+    pub subgroup_sizes: Vec<u32>,
+    pub subgroup_block: BlockShape,
+    /* Count executed work items per work group to detect partially executed NDRanges */
+    pub instrument: bool,
+    /* Write error maps of failed verifications to files starting with this prefix */
+    pub heatmap_prefix: Option<String>,
+    pub matrix_files: MatrixFiles,
+    /* Write each computed result to this path, numbered by kernel and run; the extension picks the format */
+    pub save_result: Option<String>,
+    /* Each strategy runs the whole kernel set once */
+    pub mem_strategies: Vec<MemStrategy>,
+    /* Write a Chrome trace of all OpenCL commands and host-side spans to this file */
+    pub trace_file: Option<String>,
+    /* Bias and activation fused into the GEMM store */
+    pub epilogue: Option<Epilogue>,
+    /* Also run the quantized int8 GEMM family */
+    pub int8: bool,
+    /* Batched mode: number of multiplications and their shapes (cycled); empty shapes mean m x n x p */
+    pub batch_count: u32,
+    pub batch_shapes: Vec<BatchShape>,
+    /* Precompiled GEMM kernels run after the ones built from source */
+    pub binary_kernels: Vec<BinaryKernel>,
+    /* Write the driver-generated binary of each built GEMM program to this directory */
+    pub dump_binaries: Option<String>,
+    /* Run in order; m-by-n is also the transpose shape */
+    pub workloads: Vec<Workload>,
+    pub conv_params: ConvParams,
+    /* Input length of reduce, scan and histogram */
+    pub elements: u32,
+    /* Memory bandwidth in GB/s for efficiency figures; measured with a copy kernel if not given */
+    pub peak_bandwidth: Option<f64>,
+    /* Matrix Market file for spmv and spmm; otherwise an m-by-n matrix with this fraction of nonzeros is generated */
+    pub sparse_matrix: Option<String>,
+    pub density: f64,
+    /* Strassen-Winograd splits until the largest block dimension is at most the cutoff; one run per cutoff */
+    pub strassen_cutoffs: Vec<u32>,
+    /* Inner dimensions the precision study sweeps over, with m-by-n times n-by-p products */
+    pub precision_sizes: Vec<u32>,
+    /* Write the precision study's error growth chart (SVG) to this file */
+    pub precision_plot: Option<String>,
+    /* Dimensions of the matrices the chain workload multiplies: matrix i is chain_dims[i] x chain_dims[i + 1] */
+    pub chain_dims: Vec<u32>,
+    /* Write the environment record (device, driver, programs, command line) as JSON for `replay` */
+    pub record_file: Option<String>,
+    /* Watchdog budget in ms for every kernel, replacing the estimate from the problem size */
+    pub kernel_timeout: Option<u64>,
+    /* Multiplies the estimated budgets, for devices slower than the watchdog assumes */
+    pub timeout_scale: f64,
+    /* Also print each GEMM result as a JSON line for the agent to forward (see remote::print_result) */
+    pub result_lines: bool,
+    /* Kernel sources and the files they include are read from here */
+    pub kernel_dir: String
+}
+
+const POSITIONAL_ARGS: usize = 6;
+
+/* Whether agent jobs may pass an option. Jobs come from unauthenticated peers, so nothing that writes files,
+ * picks the code to run or passes compiler flags is allowed, and input files must be in the agent's data directory. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobUse { Allowed, DataFile, Denied }
+
+/* Every option, whether it takes a value and whether jobs may pass it; parse_args rejects anything else */
+pub const OPTIONS: [(&str, bool, JobUse); 42] = [
+    ("-D", true, JobUse::Allowed), ("--define", true, JobUse::Allowed), ("--options", true, JobUse::Denied),
+    ("--manifest", true, JobUse::Denied), ("--sweep", false, JobUse::Allowed), ("--subgroup-size", true, JobUse::Allowed),
+    ("--subgroup-block", true, JobUse::Allowed), ("--instrument", false, JobUse::Allowed), ("--heatmap", true, JobUse::Denied),
+    ("--matrix-a", true, JobUse::DataFile), ("--matrix-b", true, JobUse::DataFile), ("--matrix-c", true, JobUse::DataFile),
+    ("--save-result", true, JobUse::Denied), ("--mem-strategy", true, JobUse::Allowed), ("--trace", true, JobUse::Denied),
+    ("--epilogue", true, JobUse::Allowed), ("--int8", false, JobUse::Allowed), ("--batch", true, JobUse::Allowed),
+    ("--batch-shapes", true, JobUse::Allowed), ("--binary-kernels", true, JobUse::Denied), ("--dump-binaries", true, JobUse::Denied),
+    ("--workload", true, JobUse::Allowed), ("--tensor", true, JobUse::Allowed), ("--layout", true, JobUse::Allowed),
+    ("--window", true, JobUse::Allowed), ("--stride", true, JobUse::Allowed), ("--padding", true, JobUse::Allowed),
+    ("--filters", true, JobUse::Allowed), ("--elements", true, JobUse::Allowed), ("--peak-bandwidth", true, JobUse::Allowed),
+    ("--sparse-matrix", true, JobUse::DataFile), ("--density", true, JobUse::Allowed), ("--strassen-cutoff", true, JobUse::Allowed),
+    ("--precision-sizes", true, JobUse::Allowed), ("--precision-plot", true, JobUse::Denied), ("--chain-dims", true, JobUse::Allowed),
+    ("--record", true, JobUse::Denied), ("--kernel-timeout", true, JobUse::Allowed), ("--timeout-scale", true, JobUse::Allowed),
+    ("--result-lines", false, JobUse::Denied), ("--kernel-dir", true, JobUse::Denied), ("--bias", true, JobUse::DataFile)
+];
+
+pub fn print_usage() {
+    println!("Usage: ./matrix_mul_rs platform tile_size m n p device_gflops [options]");
+    println!("   or: ./matrix_mul_rs replay RECORD [options]   to rerun the command line of a --record or --trace file");
+    println!("                                                  and report how the environment differs");
+    println!("   or: ./matrix_mul_rs agent [ADDRESS [PLATFORM [DATA_DIR]]]");
+    println!("                                                  to run jobs received as JSON on ADDRESS (default 127.0.0.1:7878),");
+    println!("                                                  on PLATFORM unless the job names one, reading the input files jobs");
+    println!("                                                  name from DATA_DIR; there is no authentication, so only listen on");
+    println!("                                                  trusted networks");
+    println!("   or: ./matrix_mul_rs client JOB_FILE AGENT...   to run the JSON job in JOB_FILE on every AGENT (HOST:PORT) at once");
+    println!("                                                  and compare their results, where:");
+    println!("    platform is the OpenCL platform used, e.g. \"Intel Gen OCL Driver\"");
+    println!("    tile_size is the size of the tiles input matrices are split into during computation (matches the number of work items)");
+    println!("    m-by-n specifies the dimensions of matrix A");
+    println!("    n-by-p specifies the dimensions of matrix B");
+    println!("    device_gflops is the max GFLOPS of the device, used for profiling");
+    println!("Options:");
+    println!("    -D, --define [kernel:]NAME[=VALUE[,VALUE...]]  add a #define to kernel sources (all kernels unless scoped)");
+    println!("    --options [kernel:]\"OPTIONS\"                   compiler options, e.g. \"-cl-fast-relaxed-math -cl-mad-enable\"");
+    println!("    --manifest FILE                               read defines and options from FILE");
+    println!("    --sweep                                       run every combination of define values and option sets");
+    println!("    --subgroup-size N[,N...]                      subgroup sizes for subgroups.cl (queried from the device by default)");
+    println!("    --subgroup-block ROWSxCOLS                    block of C computed per work item in subgroups.cl, default 8x4");
+    println!("    --instrument                                  report work groups that never ran (e.g. due to driver timeouts)");
+    println!("    --heatmap PREFIX                              on verification errors, write PREFIX<kernel>_<run>_{{abs,rel}}.pgm error maps");
+    println!("                                                  and print which tiles contain errors");
+    println!("    --matrix-a FILE, --matrix-b FILE, --matrix-c FILE");
+    println!("                                                  input matrices (text, .npy or .mtx; detected by magic bytes or extension),");
+    println!("                                                  matrix_x, matrix_x.npy or matrix_x.mtx by default");
+    println!("    --save-result FILE                            write computed results to FILE (text, .npy or .mtx by extension),");
+    println!("                                                  adding _<kernel>_<run> to the name");
+    println!("    --mem-strategy S[,S...]                       buffer allocation: alloc_host_ptr (default), use_host_ptr, device_local,");
+    println!("                                                  map_unmap, or all; transfer and kernel timings are compared");
+    println!("    --trace FILE                                  write a Chrome trace (chrome://tracing, ui.perfetto.dev) of program builds,");
+    println!("                                                  transfers and kernels to FILE");
+    println!("    --epilogue STEP[,STEP]                        fuse a per-column bias and/or an activation into the GEMM store:");
+    println!("                                                  bias, relu, gelu, sigmoid; timed against a separate elementwise pass");
+    println!("    --int8                                        also run int8 GEMM kernels on the quantized inputs, verified exactly");
+    println!("    --batch COUNT                                 batched mode: COUNT m-by-n times n-by-p multiplications of generated");
+    println!("                                                  matrices in one launch, verified against the CPU");
+    println!("    --batch-shapes MxNxP[,MxNxP...]               batched mode with per-batch dimensions, repeated up to COUNT if given");
+    println!("    --binary-kernels FILE                         also run precompiled kernels (e.g. assembled code objects) declared in FILE");
+    println!("    --dump-binaries DIR                           write each built program's device binary and ISA sections to DIR");
+    println!("                                                  and print register counts and code size where the format is known");
+    println!("    --workload W[,W...]                           workloads to run: gemm (default), pool (max pooling), conv (direct and");
+    println!("                                                  im2col + GEMM), reduce, scan, transpose (m-by-n), histogram, or primitives");
+    println!("                                                  for the last four, spmv, spmm (sparse m-by-n A times a dense n-by-p B),");
+    println!("                                                  sparse for both, strassen (Strassen-Winograd over tiled and wideloads,");
+    println!("                                                  on m-by-n times n-by-p), or precision (float, Kahan and double");
+    println!("                                                  accumulation in tiled over a sweep of n), or chain (a product of several");
+    println!("                                                  matrices kept on the device against a round trip through the host)");
+    println!("    --tensor NxCxHxW                              pooling/convolution input, default 1x16x64x64");
+    println!("    --layout nchw|nhwc                            input and output tensor layout, default nchw");
+    println!("    --window KHxKW, --stride SHxSW, --padding PHxPW");
+    println!("                                                  pooling window or filter size (3x3), stride (1x1), zero padding (0x0)");
+    println!("    --filters K                                   convolution output channels, default 16");
+    println!("    --elements N                                  input length of reduce, scan and histogram, default 16777216");
+    println!("    --peak-bandwidth GBPS                         memory bandwidth for efficiency figures (measured with a copy kernel");
+    println!("                                                  by default)");
+    println!("    --sparse-matrix FILE                          Matrix Market file for spmv and spmm");
+    println!("    --density D                                   fraction of nonzeros of the generated sparse matrix, default 0.01");
+    println!("    --strassen-cutoff N[,N...]                    split Strassen-Winograd blocks until no dimension exceeds N, default 256");
+    println!("    --precision-sizes N[,N...]                    values of n the precision study sweeps over, default 256,1024,4096,16384");
+    println!("    --precision-plot FILE                         write the precision study's error growth chart to FILE as SVG");
+    println!("    --chain-dims D0,D1,D2[,D3...]                 chain of D0-by-D1 times D1-by-D2 times ... matrices, default m,n,p,m,n");
+    println!("    --record FILE                                 write the environment (driver, clocks, kernel source hashes, git revision,");
+    println!("                                                  host CPU, command line) to FILE as JSON; also embedded in traces and results");
+    println!("    --kernel-timeout MS                           abandon kernels still running after MS milliseconds; by default the budget");
+    println!("                                                  is estimated from the problem size. Timed out kernels are reported and");
+    println!("                                                  the remaining ones run once the queue drains, if the driver allows it");
+    println!("    --timeout-scale X                             multiply the estimated budgets by X, default 1");
+    println!("    --result-lines                                also print each GEMM result as a JSON line prefixed by @result");
+    println!("    --kernel-dir DIR                              read kernel sources from DIR, default the working directory;");
+    println!("                                                  #include \"FILE\" [NAME=VALUE...] is resolved relative to it");
+    println!("    --bias FILE                                   1-by-p bias vector for the epilogue (generated by default)");
+}
+
+/* Returns None if the positional arguments are missing, in which case usage should be printed */
+pub fn parse_args(args: &[String]) -> GenResult<Option<Args>> {
+    if args.len() < POSITIONAL_ARGS + 1 {
+        return Ok(None);
+    }
+
+    let mut build_config = BuildConfig::default();
+    let mut subgroup_sizes = Vec::new();
+    let mut subgroup_block = BlockShape::default();
+    let mut instrument = false;
+    let mut heatmap_prefix = None;
+    let (mut matrix_a, mut matrix_b, mut matrix_c) = (None, None, None);
+    let mut save_result = None;
+    let mut mem_strategies = vec![MemStrategy::AllocHostPtr];
+    let mut trace_file = None;
+    let (mut epilogue, mut bias_file) = (None, None);
+    let mut int8 = false;
+    let (mut batch_count, mut batch_shapes) = (0, Vec::new());
+    let mut binary_kernels = Vec::new();
+    let mut dump_binaries = None;
+    let mut workloads = vec![Workload::Gemm];
+    let mut conv_params = ConvParams::default();
+    let (mut elements, mut peak_bandwidth) = (1 << 24, None);
+    let (mut sparse_matrix, mut density) = (None, 0.01);
+    let mut strassen_cutoffs = vec![256];
+    let (mut precision_sizes, mut precision_plot) = (vec![256, 1024, 4096, 16384], None);
+    let mut chain_dims = None;
+    let mut record_file = None;
+    let (mut kernel_timeout, mut timeout_scale) = (None, 1.0);
+    let mut result_lines = false;
+    let mut kernel_dir = ".".to_owned();
+    let mut flags = args[POSITIONAL_ARGS + 1..].iter();
+    while let Some(flag) = flags.next() {
+        let value = match OPTIONS.iter().find(|&&(name, _, _)| name == flag) {
+            Some(&(_, true, _)) => next_value(&mut flags, flag)?,
+            Some(_) => "",
+            None => return gen_error_format!("Unrecognized option {}", flag)
+        };
+        match flag.as_str() {
+            "-D" | "--define" => build_config.add_define(value)?,
+            "--options" => build_config.add_options(value),
+            "--manifest" => build_config.load_manifest(value)?,
+            "--sweep" => build_config.sweep = true,
+            "--subgroup-size" => subgroup_sizes = value.split(',').map(|s| s.trim().parse()).collect::<Result<_, _>>()?,
+            "--subgroup-block" => subgroup_block = BlockShape::parse(value)?,
+            "--instrument" => instrument = true,
+            "--heatmap" => heatmap_prefix = Some(value.to_owned()),
+            "--matrix-a" => matrix_a = Some(value.to_owned()),
+            "--matrix-b" => matrix_b = Some(value.to_owned()),
+            "--matrix-c" => matrix_c = Some(value.to_owned()),
+            "--save-result" => save_result = Some(value.to_owned()),
+            "--mem-strategy" => mem_strategies = MemStrategy::parse_list(value)?,
+            "--trace" => trace_file = Some(value.to_owned()),
+            "--epilogue" => epilogue = Some(Epilogue::parse(value)?),
+            "--int8" => int8 = true,
+            "--batch" => batch_count = value.parse()?,
+            "--batch-shapes" => batch_shapes = value.split(',').map(BatchShape::parse).collect::<GenResult<_>>()?,
+            "--binary-kernels" => binary_kernels.extend(binary_kernels::load_manifest(value)?),
+            "--dump-binaries" => dump_binaries = Some(value.to_owned()),
+            "--workload" => workloads = Workload::parse_list(value)?,
+            "--tensor" => conv_params.input = TensorShape::parse(value)?,
+            "--layout" => conv_params.layout = Layout::parse(value)?,
+            "--window" => conv_params.window.size = parse_pair(value, 1)?,
+            "--stride" => conv_params.window.stride = parse_pair(value, 1)?,
+            "--padding" => conv_params.window.padding = parse_pair(value, 0)?,
+            "--filters" => conv_params.filters = value.parse()?,
+            "--elements" => elements = value.parse()?,
+            "--peak-bandwidth" => peak_bandwidth = Some(value.parse()?),
+            "--sparse-matrix" => sparse_matrix = Some(value.to_owned()),
+            "--density" => density = value.parse()?,
+            "--strassen-cutoff" => strassen_cutoffs = value.split(',').map(|s| s.trim().parse()).collect::<Result<_, _>>()?,
+            "--precision-sizes" => precision_sizes = value.split(',').map(|s| s.trim().parse()).collect::<Result<_, _>>()?,
+            "--precision-plot" => precision_plot = Some(value.to_owned()),
+            "--chain-dims" => chain_dims = Some(value.split(',').map(|s| s.trim().parse()).collect::<Result<Vec<_>, _>>()?),
+            "--record" => record_file = Some(value.to_owned()),
+            "--kernel-timeout" => kernel_timeout = Some(value.parse()?),
+            "--timeout-scale" => timeout_scale = value.parse()?,
+            "--result-lines" => result_lines = true,
+            "--kernel-dir" => kernel_dir = value.to_owned(),
+            "--bias" => bias_file = Some(value.to_owned()),
+            _ => return gen_error_format!("Unrecognized option {}", flag)
+        }
+    }
+    build_config.validate()?;
+    if bias_file.is_some() && !epilogue.map(|e| e.bias).unwrap_or(false) {
+        return gen_error_format!("--bias requires an epilogue with a bias step, e.g. --epilogue bias,relu");
+    }
+    if workloads.contains(&Workload::Pool) || workloads.contains(&Workload::Conv) {
+        conv_params.output_shape(conv_params.filters)?;
+    }
+    if elements == 0 {
+        return gen_error_format!("--elements must be positive");
+    }
+    if density <= 0.0 || density > 1.0 {
+        return gen_error_format!("--density must be in (0, 1]");
+    }
+    if strassen_cutoffs.contains(&0) {
+        return gen_error_format!("--strassen-cutoff must be positive");
+    }
+    if precision_sizes.contains(&0) {
+        return gen_error_format!("--precision-sizes must be positive");
+    }
+    if let Some(ref dims) = chain_dims {
+        if dims.len() < 3 || dims.contains(&0) {
+            return gen_error_format!("--chain-dims needs at least three positive dimensions (two matrices)");
+        }
+    }
+    if kernel_timeout == Some(0) || timeout_scale <= 0.0 {
+        return gen_error_format!("--kernel-timeout and --timeout-scale must be positive");
+    }
+
+    let tile_size = args[2].parse()?;
+    if tile_size == 0 {
+        return gen_error_format!("tile_size must be positive");
+    }
+    let (m, n, p) = (args[3].parse()?, args[4].parse()?, args[5].parse()?);
+    Ok(Some(Args {
+        platform_name: args[1].to_owned(),
+        tile_size,
+        m,
+        n,
+        p,
+        device_max_gflops: args[6].parse()?,
+        build_config,
+        subgroup_sizes,
+        subgroup_block,
+        instrument,
+        heatmap_prefix,
+        matrix_files: MatrixFiles {
+            a: matrix_a.unwrap_or_else(|| matrix_io::default_matrix_file("matrix_a")),
+            b: matrix_b.unwrap_or_else(|| matrix_io::default_matrix_file("matrix_b")),
+            c: matrix_c.unwrap_or_else(|| matrix_io::default_matrix_file("matrix_c")),
+            bias: bias_file
+        },
+        save_result,
+        mem_strategies,
+        trace_file,
+        epilogue,
+        int8,
+        batch_count,
+        batch_shapes,
+        binary_kernels,
+        dump_binaries,
+        workloads,
+        conv_params,
+        elements,
+        peak_bandwidth,
+        sparse_matrix,
+        density,
+        strassen_cutoffs,
+        precision_sizes,
+        precision_plot,
+        chain_dims: chain_dims.unwrap_or_else(|| vec![m, n, p, m, n]),
+        record_file,
+        kernel_timeout,
+        timeout_scale,
+        result_lines,
+        kernel_dir
+    }))
+}
+
+fn next_value<'a>(flags: &mut Iter<'a, String>, flag: &str) -> GenResult<&'a str> {
+    flags.next().map(|v| v.as_str()).ok_or(GenError::from(format!("{} expects a value", flag)))
+}
@@ -0,0 +1,353 @@
+use std::{fmt, fmt::{Display, Formatter}};
+use ocl::{Kernel, Event};
+use gen_error::{GenResult, GenError};
+use memory::{MemStrategy, MatrixBuffer, Access};
+use epilogue::Epilogue;
+use workload::{self, Peaks};
+use {cli, coverage, batch, OclEnv, build_ocl_program, build_gemm_kernel, get_execution_time_ns, ceil_divisible_by, ERROR_TOLERANCE, MAX_PRINT_ERRORS};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Layout { Nchw, Nhwc }
+
+impl Layout {
+    pub fn parse(s: &str) -> GenResult<Layout> {
+        match s {
+            "nchw" => Ok(Layout::Nchw),
+            "nhwc" => Ok(Layout::Nhwc),
+            _ => gen_error_format!("Unknown layout \"{}\"; expected nchw or nhwc", s)
+        }
+    }
+
+    fn index(&self, shape: TensorShape, n: u32, c: u32, h: u32, w: u32) -> usize {
+        let (n, c, h, w) = (n as usize, c as usize, h as usize, w as usize);
+        let (cs, hs, ws) = (shape.c as usize, shape.h as usize, shape.w as usize);
+        match *self {
+            Layout::Nchw => ((n * cs + c) * hs + h) * ws + w,
+            Layout::Nhwc => ((n * hs + h) * ws + w) * cs + c
+        }
+    }
+
+    /* Inverse of `index` */
+    fn coordinates(&self, shape: TensorShape, index: usize) -> (u32, u32, u32, u32) {
+        let index = index as u32;
+        match *self {
+            Layout::Nchw => (index / (shape.c * shape.h * shape.w), index / (shape.h * shape.w) % shape.c, index / shape.w % shape.h, index % shape.w),
+            Layout::Nhwc => (index / (shape.h * shape.w * shape.c), index % shape.c, index / (shape.w * shape.c) % shape.h, index / shape.c % shape.w)
+        }
+    }
+
+    fn source_defines(&self) -> &'static str {
+        match *self {
+            Layout::Nchw => "",
+            Layout::Nhwc => "#define LAYOUT_NHWC\n"
+        }
+    }
+}
+
+impl Display for Layout {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", match *self { Layout::Nchw => "NCHW", Layout::Nhwc => "NHWC" })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TensorShape {
+    pub n: u32,
+    pub c: u32,
+    pub h: u32,
+    pub w: u32
+}
+
+impl TensorShape {
+    /* Parses "NxCxHxW" */
+    pub fn parse(s: &str) -> GenResult<TensorShape> {
+        let dims = s.split('x').map(|d| d.trim().parse::<u32>()).collect::<Result<Vec<_>, _>>()?;
+        match dims.as_slice() {
+            &[n, c, h, w] if n > 0 && c > 0 && h > 0 && w > 0 => Ok(TensorShape { n, c, h, w }),
+            _ => gen_error_format!("Expected tensor dimensions as NxCxHxW, got \"{}\"", s)
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        (self.n * self.c * self.h * self.w) as usize
+    }
+}
+
+impl Display for TensorShape {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}x{}x{}x{}", self.n, self.c, self.h, self.w)
+    }
+}
+
+/* Pooling window or convolution filter extent, stride and padding; all pairs are (height, width) */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    pub size: [u32; 2],
+    pub stride: [u32; 2],
+    pub padding: [u32; 2]
+}
+
+/* Everything that describes a pooling or convolution problem */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConvParams {
+    pub input: TensorShape,
+    pub layout: Layout,
+    pub window: Window,
+    /* Output channels of a convolution */
+    pub filters: u32
+}
+
+impl Default for ConvParams {
+    fn default() -> ConvParams {
+        ConvParams {
+            input: TensorShape { n: 1, c: 16, h: 64, w: 64 },
+            layout: Layout::Nchw,
+            window: Window { size: [3, 3], stride: [1, 1], padding: [0, 0] },
+            filters: 16
+        }
+    }
+}
+
+impl ConvParams {
+    pub fn output_shape(&self, channels: u32) -> GenResult<TensorShape> {
+        let window = &self.window;
+        let padded = [self.input.h + 2 * window.padding[0], self.input.w + 2 * window.padding[1]];
+        if window.size[0] > padded[0] || window.size[1] > padded[1] || window.stride[0] == 0 || window.stride[1] == 0 {
+            return gen_error_format!("A {}x{} window with stride {}x{} does not fit a padded {}x{} input",
+                                     window.size[0], window.size[1], window.stride[0], window.stride[1], padded[0], padded[1]);
+        }
+        Ok(TensorShape {
+            n: self.input.n,
+            c: channels,
+            h: (padded[0] - window.size[0]) / window.stride[0] + 1,
+            w: (padded[1] - window.size[1]) / window.stride[1] + 1
+        })
+    }
+
+    /* Input coordinates covered by output position (oh, ow) and window offset (kh, kw), if not in the padding */
+    fn input_position(&self, oh: u32, ow: u32, kh: u32, kw: u32) -> Option<(u32, u32)> {
+        let h = (oh * self.window.stride[0] + kh) as i64 - self.window.padding[0] as i64;
+        let w = (ow * self.window.stride[1] + kw) as i64 - self.window.padding[1] as i64;
+        if h < 0 || w < 0 || h >= self.input.h as i64 || w >= self.input.w as i64 { None } else { Some((h as u32, w as u32)) }
+    }
+
+    /* Shape and window arguments shared by the kernels, after the buffers */
+    fn window_args(&self) -> [u32; 6] {
+        let window = &self.window;
+        [window.size[0], window.size[1], window.stride[0], window.stride[1], window.padding[0], window.padding[1]]
+    }
+}
+
+impl Display for ConvParams {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let window = &self.window;
+        write!(f, "input {} {}, window {}x{}, stride {}x{}, padding {}x{}", self.input, self.layout,
+               window.size[0], window.size[1], window.stride[0], window.stride[1], window.padding[0], window.padding[1])
+    }
+}
+
+pub fn cpu_max_pool(input: &[f32], params: &ConvParams) -> GenResult<Vec<f32>> {
+    let out_shape = params.output_shape(params.input.c)?;
+    let mut output = vec![0.0f32; out_shape.len()];
+    for n in 0..out_shape.n {
+        for c in 0..out_shape.c {
+            for oh in 0..out_shape.h {
+                for ow in 0..out_shape.w {
+                    let mut max_value = ::std::f32::NEG_INFINITY;
+                    for kh in 0..params.window.size[0] {
+                        for kw in 0..params.window.size[1] {
+                            if let Some((h, w)) = params.input_position(oh, ow, kh, kw) {
+                                max_value = max_value.max(input[params.layout.index(params.input, n, c, h, w)]);
+                            }
+                        }
+                    }
+                    output[params.layout.index(out_shape, n, c, oh, ow)] = max_value;
+                }
+            }
+        }
+    }
+    Ok(output)
+}
+
+/* Weights are K x C x KH x KW */
+pub fn cpu_conv(input: &[f32], weights: &[f32], params: &ConvParams) -> GenResult<Vec<f32>> {
+    let out_shape = params.output_shape(params.filters)?;
+    let (c_in, [kh_size, kw_size]) = (params.input.c, params.window.size);
+    let mut output = vec![0.0f32; out_shape.len()];
+    for n in 0..out_shape.n {
+        for k in 0..out_shape.c {
+            for oh in 0..out_shape.h {
+                for ow in 0..out_shape.w {
+                    let mut acc = 0.0f32;
+                    for c in 0..c_in {
+                        for kh in 0..kh_size {
+                            for kw in 0..kw_size {
+                                if let Some((h, w)) = params.input_position(oh, ow, kh, kw) {
+                                    acc += input[params.layout.index(params.input, n, c, h, w)]
+                                        * weights[(((k * c_in + c) * kh_size + kh) * kw_size + kw) as usize];
+                                }
+                            }
+                        }
+                    }
+                    output[params.layout.index(out_shape, n, k, oh, ow)] = acc;
+                }
+            }
+        }
+    }
+    Ok(output)
+}
+
+pub fn run_pooling(args: &cli::Args, ocl_env: &OclEnv, strategy: MemStrategy, peaks: &Peaks) -> GenResult<()> {
+    let (queue, tracer, params) = (&ocl_env.queue, &ocl_env.tracer, &args.conv_params);
+    let out_shape = params.output_shape(params.input.c)?;
+    println!("===\nRunning max_pool, {}, output {}, memory strategy: {}", params, out_shape, strategy);
+
+    let input = batch::generate_matrix(params.input.len(), 3);
+    let expected = cpu_max_pool(&input, params)?;
+    let buffer_input = MatrixBuffer::with_data(queue, strategy, &input, Access::ReadOnly, tracer, "input")?;
+    let buffer_output = MatrixBuffer::new(queue, strategy, out_shape.len(), Access::WriteOnly)?;
+
+    let kernel_defs = format!("#define TILE_SIZE {}\n{}", args.tile_size, params.layout.source_defines());
+    let program = build_ocl_program(ocl_env, kernel_defs, "", "pooling.cl")?;
+    let window = params.window_args();
+    let kernel = Kernel::builder()
+        .queue(queue.clone())
+        .program(&program).name("max_pool")
+        .arg(&buffer_input.buffer).arg(&buffer_output.buffer)
+        .arg(params.input.n).arg(params.input.c).arg(params.input.h).arg(params.input.w)
+        .arg(out_shape.h).arg(out_shape.w)
+        .arg(window[0]).arg(window[1]).arg(window[2]).arg(window[3]).arg(window[4]).arg(window[5])
+        .build()?;
+
+    let exec_event = launch(ocl_env, &kernel, "max_pool", [out_shape.w, out_shape.h, out_shape.n * out_shape.c], args.tile_size)?;
+    let mut actual = vec![0.0f32; out_shape.len()];
+    buffer_output.read(queue, &mut actual, tracer, "output")?;
+    verify_tensor(&expected, &actual, out_shape, params.layout);
+
+    /* A comparison per window element; the minimum traffic is reading the input and writing the output once */
+    let comparisons = out_shape.len() as u64 * (params.window.size[0] * params.window.size[1]) as u64;
+    workload::print_perf(peaks, get_execution_time_ns(&exec_event)?, Some(comparisons), (params.input.len() + out_shape.len()) as u64 * 4);
+    Ok(())
+}
+
+pub fn run_convolution(args: &cli::Args, ocl_env: &OclEnv, strategy: MemStrategy, peaks: &Peaks) -> GenResult<()> {
+    let (queue, tracer, params) = (&ocl_env.queue, &ocl_env.tracer, &args.conv_params);
+    let out_shape = params.output_shape(params.filters)?;
+    println!("===\nRunning convolution, {}, {} filters, output {}, memory strategy: {}", params, params.filters, out_shape, strategy);
+
+    let reduction = params.input.c * params.window.size[0] * params.window.size[1];
+    let input = batch::generate_matrix(params.input.len(), 4);
+    let weights = batch::generate_matrix((params.filters * reduction) as usize, 5);
+    let expected = cpu_conv(&input, &weights, params)?;
+
+    let buffer_input = MatrixBuffer::with_data(queue, strategy, &input, Access::ReadOnly, tracer, "input")?;
+    let buffer_weights = MatrixBuffer::with_data(queue, strategy, &weights, Access::ReadOnly, tracer, "weights")?;
+    let buffer_output = MatrixBuffer::new(queue, strategy, out_shape.len(), Access::ReadWrite)?;
+
+    let flops = 2 * out_shape.len() as u64 * reduction as u64;
+    let min_bytes = (params.input.len() + weights.len() + out_shape.len()) as u64 * 4;
+    let kernel_defs = format!("#define TILE_SIZE {}\n{}", args.tile_size, params.layout.source_defines());
+    let program = build_ocl_program(ocl_env, kernel_defs, "", "conv.cl")?;
+    let window = params.window_args();
+
+    println!("---\nDirect convolution");
+    let kernel = Kernel::builder()
+        .queue(queue.clone())
+        .program(&program).name("conv_direct")
+        .arg(&buffer_input.buffer).arg(&buffer_weights.buffer).arg(&buffer_output.buffer)
+        .arg(params.input.n).arg(params.input.c).arg(params.input.h).arg(params.input.w)
+        .arg(params.filters).arg(out_shape.h).arg(out_shape.w)
+        .arg(window[0]).arg(window[1]).arg(window[2]).arg(window[3]).arg(window[4]).arg(window[5])
+        .build()?;
+    buffer_output.write(queue, &vec![0.0f32; out_shape.len()], tracer, "output (reset)")?;
+    let exec_event = launch(ocl_env, &kernel, "conv_direct", [out_shape.w, out_shape.h, out_shape.n * out_shape.c], args.tile_size)?;
+    let mut actual = vec![0.0f32; out_shape.len()];
+    buffer_output.read(queue, &mut actual, tracer, "output")?;
+    verify_tensor(&expected, &actual, out_shape, params.layout);
+    workload::print_perf(peaks, get_execution_time_ns(&exec_event)?, Some(flops), min_bytes);
+
+    println!("---\nim2col + tiled GEMM");
+    let pixels = out_shape.n * out_shape.h * out_shape.w;
+    let columns = MatrixBuffer::new(queue, strategy, (pixels * reduction) as usize, Access::ReadWrite)?;
+    let im2col = Kernel::builder()
+        .queue(queue.clone())
+        .program(&program).name("im2col")
+        .arg(&buffer_input.buffer).arg(&columns.buffer)
+        .arg(params.input.n).arg(params.input.c).arg(params.input.h).arg(params.input.w)
+        .arg(out_shape.h).arg(out_shape.w)
+        .arg(window[0]).arg(window[1]).arg(window[2]).arg(window[3]).arg(window[4]).arg(window[5])
+        .build()?;
+
+    /* The GEMM multiplies the lowered input by the transposed weights, producing NHWC directly */
+    let weights_t = transpose(&weights, params.filters, reduction);
+    let buffer_weights_t = MatrixBuffer::with_data(queue, strategy, &weights_t, Access::ReadOnly, tracer, "weights (transposed)")?;
+    let gemm_output = if params.layout == Layout::Nhwc { None }
+                      else { Some(MatrixBuffer::new(queue, strategy, out_shape.len(), Access::ReadWrite)?) };
+    let gemm_defs = format!("#define TILE_SIZE {}\n{}{}", args.tile_size, coverage::source_defines(false), Epilogue::source_defines(None));
+    let gemm_program = build_ocl_program(ocl_env, gemm_defs, "", "tiled.cl")?;
+    let gemm = build_gemm_kernel(queue, &gemm_program, "tiled",
+                                 [&columns.buffer, &buffer_weights_t.buffer, &gemm_output.as_ref().unwrap_or(&buffer_output).buffer],
+                                 [pixels, reduction, params.filters], None, None)?;
+
+    buffer_output.write(queue, &vec![0.0f32; out_shape.len()], tracer, "output (reset)")?;
+    let mut events = vec![("im2col", launch(ocl_env, &im2col, "im2col", [reduction, pixels, 1], args.tile_size)?)];
+    let gemm_global = [ceil_divisible_by(pixels, args.tile_size), ceil_divisible_by(params.filters, args.tile_size)];
+    events.push(("GEMM", workload::enqueue_timed(ocl_env, &gemm, "im2col GEMM", gemm_global, [args.tile_size, args.tile_size])?));
+    if let Some(ref gemm_output) = gemm_output {
+        let reorder = Kernel::builder()
+            .queue(queue.clone())
+            .program(&program).name("nhwc_to_nchw")
+            .arg(&gemm_output.buffer).arg(&buffer_output.buffer)
+            .arg(out_shape.n).arg(out_shape.c).arg(out_shape.h).arg(out_shape.w)
+            .build()?;
+        events.push(("NHWC to NCHW", launch(ocl_env, &reorder, "nhwc_to_nchw", [out_shape.h * out_shape.w, out_shape.c, out_shape.n], args.tile_size)?));
+    }
+    buffer_output.read(queue, &mut actual, tracer, "output")?;
+    verify_tensor(&expected, &actual, out_shape, params.layout);
+
+    let mut total_time_ns = 0;
+    for &(name, ref event) in events.iter() {
+        let time_ns = get_execution_time_ns(event)?;
+        println!("{}: {:.3} [ms]", name, time_ns as f64 / 1_000_000.0);
+        total_time_ns += time_ns;
+    }
+    workload::print_perf(peaks, total_time_ns, Some(flops), min_bytes);
+    Ok(())
+}
+
+/* Runs a kernel over a 3-dimensional NDRange rounded up to tile_size x tile_size x 1 work groups */
+fn launch(ocl_env: &OclEnv, kernel: &Kernel, name: &str, work_items: [u32; 3], tile_size: u32) -> GenResult<Event> {
+    let global_size = [ceil_divisible_by(work_items[0], tile_size), ceil_divisible_by(work_items[1], tile_size), work_items[2]];
+    workload::enqueue_timed(ocl_env, kernel, name, global_size, [tile_size, tile_size, 1])
+}
+
+fn verify_tensor(expected: &[f32], actual: &[f32], shape: TensorShape, layout: Layout) -> u32 {
+    let mut errors_encountered = 0;
+    for (i, (expected, actual)) in expected.iter().zip(actual.iter()).enumerate() {
+        if (expected - actual).abs() > ERROR_TOLERANCE {
+            errors_encountered += 1;
+            if errors_encountered < MAX_PRINT_ERRORS {
+                let (n, c, h, w) = layout.coordinates(shape, i);
+                println!("n {}, c {}, h {}, w {}: expected {:.8}, got {:.8}", n, c, h, w, expected, actual);
+            }
+        }
+    }
+    if errors_encountered > MAX_PRINT_ERRORS {
+        println!("...\n({} errors omitted)", errors_encountered - MAX_PRINT_ERRORS);
+    }
+    else if errors_encountered == 0 {
+        println!("Result verified, no errors found");
+    }
+    errors_encountered
+}
+
+fn transpose(data: &[f32], rows: u32, cols: u32) -> Vec<f32> {
+    let (rows, cols) = (rows as usize, cols as usize);
+    let mut transposed = vec![0.0f32; rows * cols];
+    for row in 0..rows {
+        for col in 0..cols {
+            transposed[col * rows + row] = data[row * cols + col];
+        }
+    }
+    transposed
+}
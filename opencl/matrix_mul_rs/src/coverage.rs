@@ -0,0 +1,132 @@
+use std::fs;
+use ocl::{flags, Buffer, Queue};
+use gen_error::GenResult;
+
+const MAX_PRINT_GROUPS: usize = 10;
+const I915_HANGCHECK_PARAM: &str = "/sys/module/i915/parameters/enable_hangcheck";
+
+/* GEMM kernels declare `COVERAGE_PARAM` after their last argument and start with `COVERAGE_RECORD();`.
+ * When instrumented, every work item increments the counter of its work group, so a group that never ran
+ * keeps a zero and a partially executed one ends up below the work group size. */
+pub fn source_defines(instrument: bool) -> &'static str {
+    if instrument {
+        "#define COVERAGE_PARAM , __global volatile uint* coverage\n\
+         #define COVERAGE_RECORD() atomic_inc(&coverage[get_group_id(1) * get_num_groups(0) + get_group_id(0)])\n"
+    }
+    else {
+        "#define COVERAGE_PARAM\n#define COVERAGE_RECORD()\n"
+    }
+}
+
+pub struct CoverageCounters {
+    buffer: Buffer<u32>,
+    num_groups: [u32; 2],
+    group_size: u32
+}
+
+impl CoverageCounters {
+    pub fn new(queue: &Queue, global_size: [u32; 2], local_size: [u32; 2]) -> GenResult<CoverageCounters> {
+        let num_groups = [global_size[0] / local_size[0], global_size[1] / local_size[1]];
+        let len = (num_groups[0] * num_groups[1]) as usize;
+        let buffer = Buffer::<u32>::builder().queue(queue.clone()).flags(flags::MemFlags::new().alloc_host_ptr().read_write()).len(len).build()?;
+        buffer.cmd().queue(queue).offset(0).write(&vec![0u32; len]).enq()?;
+        Ok(CoverageCounters { buffer, num_groups, group_size: local_size[0] * local_size[1] })
+    }
+
+    pub fn buffer(&self) -> &Buffer<u32> {
+        &self.buffer
+    }
+
+    pub fn read_report(&self, queue: &Queue) -> GenResult<CoverageReport> {
+        let mut counters = vec![0u32; self.buffer.len()];
+        self.buffer.cmd().queue(queue).offset(0).read(&mut counters).enq()?;
+
+        let group_coords = |i: usize| (i as u32 % self.num_groups[0], i as u32 / self.num_groups[0]);
+        let missing_groups = counters.iter().enumerate().filter(|&(_, &c)| c == 0).map(|(i, _)| i).collect::<Vec<_>>();
+        let partial_groups = counters.iter().enumerate()
+            .filter(|&(_, &c)| c != 0 && c != self.group_size)
+            .map(|(i, &c)| (group_coords(i), c)).collect::<Vec<_>>();
+        /* Groups are dispatched in linear order (x varies fastest); a run cut short by the driver
+         * typically loses a contiguous tail of that order */
+        let missing_tail = missing_groups.first().map(|&first| missing_groups.len() == counters.len() - first).unwrap_or(false);
+
+        Ok(CoverageReport {
+            total_groups: counters.len() as u32,
+            group_size: self.group_size,
+            executed_items: counters.iter().map(|&c| c as u64).sum(),
+            missing_groups: missing_groups.into_iter().map(group_coords).collect(),
+            partial_groups,
+            missing_tail
+        })
+    }
+}
+
+pub struct CoverageReport {
+    pub total_groups: u32,
+    pub group_size: u32,
+    pub executed_items: u64,
+    /* (x, y) work group ids */
+    pub missing_groups: Vec<(u32, u32)>,
+    pub partial_groups: Vec<((u32, u32), u32)>,
+    pub missing_tail: bool
+}
+
+impl CoverageReport {
+    pub fn is_complete(&self) -> bool {
+        self.missing_groups.is_empty() && self.partial_groups.is_empty()
+    }
+
+    pub fn print(&self, verification_errors: u32) {
+        let expected_items = self.total_groups as u64 * self.group_size as u64;
+        println!("Coverage: {} of {} work groups ran, {} of {} work items",
+                 self.total_groups as usize - self.missing_groups.len(), self.total_groups, self.executed_items, expected_items);
+
+        for &(x, y) in self.missing_groups.iter().take(MAX_PRINT_GROUPS) {
+            println!("Work group ({}, {}) never ran", x, y);
+        }
+        if self.missing_groups.len() > MAX_PRINT_GROUPS {
+            println!("...\n({} more missing groups omitted)", self.missing_groups.len() - MAX_PRINT_GROUPS);
+        }
+        for &((x, y), count) in self.partial_groups.iter().take(MAX_PRINT_GROUPS) {
+            println!("Work group ({}, {}) ran {} of {} work items", x, y, count, self.group_size);
+        }
+        if self.partial_groups.len() > MAX_PRINT_GROUPS {
+            println!("...\n({} more partially executed groups omitted)", self.partial_groups.len() - MAX_PRINT_GROUPS);
+        }
+
+        println!("Diagnosis: {}", self.diagnosis(verification_errors));
+    }
+
+    fn diagnosis(&self, verification_errors: u32) -> String {
+        if self.is_complete() {
+            return if verification_errors == 0 {
+                "the whole NDRange was executed".to_owned()
+            }
+            else {
+                "the whole NDRange was executed, so the verification errors come from the kernel itself".to_owned()
+            };
+        }
+
+        let mut diagnosis = if !self.missing_groups.is_empty() && self.missing_tail {
+            format!("the last {} work groups in dispatch order never ran. This is what a driver timeout looks like: \
+                     the kernel is terminated midway rather than computing wrong values", self.missing_groups.len())
+        }
+        else if !self.missing_groups.is_empty() {
+            format!("{} work groups never ran. Whole groups being dropped points at the driver \
+                     (e.g. a timeout or a GPU reset) rather than at the kernel", self.missing_groups.len())
+        }
+        else {
+            "some work groups ran only partially, which points at the driver or the compiler rather than at the kernel's arithmetic".to_owned()
+        };
+
+        match fs::read_to_string(I915_HANGCHECK_PARAM) {
+            Ok(ref value) if value.trim() != "0" && value.trim() != "N" => diagnosis += &format!(
+                ".\ni915 hangcheck is enabled ({} = {}); run `dmesg | grep 'timed out'` to confirm, and consider disabling it \
+                 with `echo -n 0 > {}` (a genuinely hung kernel will then require a reboot)",
+                I915_HANGCHECK_PARAM, value.trim(), I915_HANGCHECK_PARAM),
+            Ok(_) => diagnosis += ".\ni915 hangcheck is already disabled; check `dmesg` for GPU resets",
+            Err(_) => diagnosis += ".\nCheck `dmesg` for GPU hang or timeout messages"
+        }
+        diagnosis
+    }
+}
@@ -0,0 +1,206 @@
+use std::{fs, fs::File, io::BufWriter, io::prelude::*, cell::RefCell, process::Command, time::{SystemTime, UNIX_EPOCH}};
+use ocl::{Platform, Device};
+use ocl::enums::DeviceInfo;
+use gen_error::{GenResult, GenError};
+use json;
+use open_file;
+
+/* Keys of Environment::fields, in report order; replay compares all but the timestamp */
+const FIELD_KEYS: [&str; 14] = ["platform", "platform_version", "device", "vendor", "driver_version", "device_version",
+                                "opencl_c_version", "max_clock_mhz", "compute_units", "global_memory_bytes",
+                                "host_cpu", "host_os", "git_revision", "timestamp"];
+
+/* A program as it was handed to the driver */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramRecord {
+    pub source_file: String,
+    pub options: String,
+    /* FNV-1a of the complete source with the host-side defines, or of the binary for precompiled kernels */
+    pub source_hash: u64
+}
+
+/* Everything needed to reproduce a run: printed at startup and embedded in every file a run writes */
+pub struct Environment {
+    /* Values for FIELD_KEYS */
+    pub fields: Vec<(&'static str, String)>,
+    /* Effective arguments, program name included; a replay stores the expanded command line */
+    pub command_line: Vec<String>,
+    /* Filled in by build_ocl_program as programs get built */
+    programs: RefCell<Vec<ProgramRecord>>
+}
+
+impl Environment {
+    /* A query the driver fails is recorded as unknown rather than failing the run */
+    pub fn collect(platform: &Platform, device: &Device) -> Environment {
+        let known = |value: ocl::Result<String>| value.unwrap_or_else(|_| "unknown".to_owned());
+        let device_info = |info| known(device.info(info).map(|result| result.to_string()));
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let values = vec![
+            known(platform.name()),
+            known(platform.version()),
+            known(device.name()),
+            device_info(DeviceInfo::Vendor),
+            device_info(DeviceInfo::DriverVersion),
+            device_info(DeviceInfo::Version),
+            device_info(DeviceInfo::OpenclCVersion),
+            device_info(DeviceInfo::MaxClockFrequency),
+            device_info(DeviceInfo::MaxComputeUnits),
+            device_info(DeviceInfo::GlobalMemSize),
+            host_cpu(),
+            host_os(),
+            git_revision(),
+            timestamp.to_string()
+        ];
+        Environment {
+            fields: FIELD_KEYS.iter().cloned().zip(values.into_iter().map(|v| v.trim().to_owned())).collect(),
+            command_line: Vec::new(),
+            programs: RefCell::new(Vec::new())
+        }
+    }
+
+    pub fn record_program(&self, source_file: &str, options: &str, contents: &[u8]) {
+        let record = ProgramRecord { source_file: source_file.to_owned(), options: options.to_owned(), source_hash: fnv1a(contents) };
+        let mut programs = self.programs.borrow_mut();
+        if !programs.contains(&record) {
+            programs.push(record);
+        }
+    }
+
+    pub fn print(&self) {
+        println!("===\nEnvironment");
+        for &(key, ref value) in self.fields.iter() {
+            println!("{}: {}", key, value);
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut fields = self.fields.iter().map(|&(key, ref value)| (key, json::string(value))).collect::<Vec<_>>();
+        fields.push(("command_line", json::array(&self.command_line.iter().map(|arg| json::string(arg)).collect::<Vec<_>>())));
+        let programs = self.programs.borrow().iter().map(|program| json::object(&[
+            ("source_file", json::string(&program.source_file)),
+            ("options", json::string(&program.options)),
+            ("source_hash", json::string(&format!("{:016x}", program.source_hash)))
+        ])).collect::<Vec<_>>();
+        fields.push(("programs", json::array(&programs)));
+        json::object(&fields)
+    }
+
+    /* "key: value" lines for formats that only allow comments (the caller adds the comment marker) */
+    pub fn comment_lines(&self) -> Vec<String> {
+        let mut lines = self.fields.iter().map(|&(key, ref value)| format!("{}: {}", key, value)).collect::<Vec<_>>();
+        lines.push(format!("command_line: {}", self.command_line.join(" ")));
+        for program in self.programs.borrow().iter() {
+            lines.push(format!("program: {} [{}] {:016x}", program.source_file, program.options, program.source_hash));
+        }
+        lines
+    }
+
+    pub fn write_record(&self, filename: &str) -> GenResult<()> {
+        let mut out = BufWriter::new(File::create(filename).or(gen_error_format!("Unable to open {} for writing", filename))?);
+        writeln!(out, "{}", self.to_json())?;
+        println!("Environment record written to {}", filename);
+        Ok(())
+    }
+
+    /* Reads a record written by --record, or the environment embedded in a --trace file */
+    pub fn load(filename: &str) -> GenResult<Environment> {
+        let mut contents = String::new();
+        open_file(filename)?.read_to_string(&mut contents)?;
+        let document = json::parse(&contents).map_err(|e| GenError::from(format!("{}: {}", filename, e)))?;
+        let record = document.get("otherData").unwrap_or(&document);
+
+        let command_line = match record.get("command_line").and_then(|c| c.as_array()) {
+            Some(args) => args.iter().filter_map(|arg| arg.as_str().map(|s| s.to_owned())).collect::<Vec<_>>(),
+            None => return gen_error_format!("{}: no command line recorded", filename)
+        };
+        let programs = record.get("programs").and_then(|p| p.as_array()).unwrap_or(&[]).iter().filter_map(|program| {
+            Some(ProgramRecord {
+                source_file: program.get("source_file")?.as_str()?.to_owned(),
+                options: program.get("options")?.as_str()?.to_owned(),
+                source_hash: u64::from_str_radix(program.get("source_hash")?.as_str()?, 16).ok()?
+            })
+        }).collect();
+        Ok(Environment {
+            fields: FIELD_KEYS.iter().map(|&key| (key, record.get(key).and_then(|v| v.as_str()).unwrap_or("").to_owned())).collect(),
+            command_line,
+            programs: RefCell::new(programs)
+        })
+    }
+
+    /* Reports what differs from a recorded run; programs are matched by source file and options */
+    pub fn compare(&self, recorded: &Environment) {
+        println!("===\nReplay differences from the recorded environment");
+        let mut differences = 0;
+        for (&(key, ref value), &(_, ref recorded_value)) in self.fields.iter().zip(recorded.fields.iter()) {
+            if key != "timestamp" && value != recorded_value {
+                println!("{}: {} (recorded: {})", key, value, recorded_value);
+                differences += 1;
+            }
+        }
+        for recorded_program in recorded.programs.borrow().iter() {
+            let current = self.programs.borrow().iter()
+                .find(|p| p.source_file == recorded_program.source_file && p.options == recorded_program.options)
+                .map(|p| p.source_hash);
+            match current {
+                Some(hash) if hash == recorded_program.source_hash => (),
+                Some(hash) => {
+                    println!("{} [{}]: source hash {:016x} (recorded: {:016x})", recorded_program.source_file, recorded_program.options, hash, recorded_program.source_hash);
+                    differences += 1;
+                },
+                None => {
+                    println!("{} [{}]: recorded but not built in this run", recorded_program.source_file, recorded_program.options);
+                    differences += 1;
+                }
+            }
+        }
+        if differences == 0 {
+            println!("None, the run matches the record");
+        }
+    }
+}
+
+/* `replay RECORD [options]`: the recorded command line with the program name and any extra options of this invocation */
+pub fn replay_command_line(args: &[String]) -> GenResult<(Vec<String>, Environment)> {
+    let filename = match args.get(2) {
+        Some(filename) => filename,
+        None => return gen_error_format!("replay expects a record file")
+    };
+    let recorded = Environment::load(filename)?;
+    let mut command_line = vec![args[0].clone()];
+    command_line.extend(recorded.command_line.iter().skip(1).cloned());
+    command_line.extend(args[3..].iter().cloned());
+    println!("Replaying {}: {:?}", filename, command_line);
+    Ok((command_line, recorded))
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325u64, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+fn host_cpu() -> String {
+    let model = fs::read_to_string("/proc/cpuinfo").ok().and_then(|cpuinfo| {
+        cpuinfo.lines().find(|line| line.starts_with("model name")).and_then(|line| line.splitn(2, ':').nth(1)).map(|s| s.to_owned())
+    });
+    model.unwrap_or_else(|| ::std::env::consts::ARCH.to_owned())
+}
+
+fn host_os() -> String {
+    match fs::read_to_string("/proc/sys/kernel/osrelease") {
+        Ok(release) => format!("{} {}", ::std::env::consts::OS, release.trim()),
+        Err(_) => ::std::env::consts::OS.to_owned()
+    }
+}
+
+/* Revision of the checkout the kernels are loaded from, marked dirty if it has local changes */
+fn git_revision() -> String {
+    let git = |args: &[&str]| Command::new("git").args(args).output().ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_owned());
+    match git(&["rev-parse", "HEAD"]) {
+        Some(revision) => match git(&["status", "--porcelain", "--untracked-files=no"]) {
+            Some(ref status) if !status.is_empty() => revision + "-dirty",
+            _ => revision
+        },
+        None => "unknown".to_owned()
+    }
+}
@@ -0,0 +1,100 @@
+use std::{fmt, fmt::{Display, Formatter}};
+use gen_error::{GenResult, GenError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Activation { Identity, Relu, Gelu, Sigmoid }
+
+impl Activation {
+    /* OpenCL C body of `float epilogue_activation(float x)` */
+    fn source(&self) -> &'static str {
+        match *self {
+            Activation::Identity => "return x;",
+            Activation::Relu => "return fmax(x, 0.0f);",
+            /* tanh approximation, as used by most inference frameworks */
+            Activation::Gelu => "return 0.5f * x * (1.0f + tanh(0.7978845608f * (x + 0.044715f * x * x * x)));",
+            Activation::Sigmoid => "return 1.0f / (1.0f + exp(-x));"
+        }
+    }
+
+    fn apply(&self, x: f32) -> f32 {
+        match *self {
+            Activation::Identity => x,
+            Activation::Relu => x.max(0.0),
+            Activation::Gelu => 0.5 * x * (1.0 + (0.7978845608 * (x + 0.044715 * x * x * x)).tanh()),
+            Activation::Sigmoid => 1.0 / (1.0 + (-x).exp())
+        }
+    }
+}
+
+/* A per-column bias add and/or activation applied to each element of C as it is stored */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Epilogue {
+    pub bias: bool,
+    pub activation: Activation
+}
+
+impl Epilogue {
+    /* Accepts a comma-separated list of steps: bias, relu, gelu, sigmoid */
+    pub fn parse(s: &str) -> GenResult<Epilogue> {
+        let mut epilogue = Epilogue { bias: false, activation: Activation::Identity };
+        for step in s.split(',').map(|step| step.trim()) {
+            let activation = match step {
+                "bias" => { epilogue.bias = true; continue; },
+                "relu" => Activation::Relu,
+                "gelu" => Activation::Gelu,
+                "sigmoid" => Activation::Sigmoid,
+                _ => return gen_error_format!("Unknown epilogue step \"{}\"; expected bias, relu, gelu or sigmoid", step)
+            };
+            if epilogue.activation != Activation::Identity {
+                return gen_error_format!("Only one activation can be fused, got \"{}\"", s);
+            }
+            epilogue.activation = activation;
+        }
+        Ok(epilogue)
+    }
+
+    /* GEMM kernels declare `EPILOGUE_PARAM` after their last argument and store `EPILOGUE(value, col)`;
+     * with a bias, the bias vector is the kernel argument following P */
+    pub fn source_defines(epilogue: Option<&Epilogue>) -> String {
+        match epilogue {
+            Some(epilogue) => format!(
+                "#define EPILOGUE_PARAM {}\n\
+                 #define EPILOGUE(value, col) epilogue_activation({})\n\
+                 inline float epilogue_activation(float x) {{ {} }}\n",
+                if epilogue.bias { ", const __global float* bias" } else { "" },
+                if epilogue.bias { "(value) + bias[col]" } else { "(value)" },
+                epilogue.activation.source()),
+            None => "#define EPILOGUE_PARAM\n#define EPILOGUE(value, col) (value)\n".to_owned()
+        }
+    }
+
+    /* CPU reference of the epilogue, applied to a row-major rows-by-`bias.len()` matrix */
+    pub fn apply(&self, matrix: &mut [f32], bias: &[f32]) {
+        let cols = bias.len();
+        for (i, value) in matrix.iter_mut().enumerate() {
+            let biased = if self.bias { *value + bias[i % cols] } else { *value };
+            *value = self.activation.apply(biased);
+        }
+    }
+}
+
+impl Display for Epilogue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let activation = match self.activation {
+            Activation::Identity => "",
+            Activation::Relu => "relu",
+            Activation::Gelu => "gelu",
+            Activation::Sigmoid => "sigmoid"
+        };
+        match (self.bias, activation.is_empty()) {
+            (true, true) => write!(f, "bias"),
+            (true, false) => write!(f, "bias + {}", activation),
+            (false, _) => write!(f, "{}", activation)
+        }
+    }
+}
+
+/* Bias used when none is given: small values of both signs so that every activation has work to do */
+pub fn default_bias(cols: u32) -> Vec<f32> {
+    (0..cols).map(|col| ((col % 7) as f32 - 3.0) * 0.25).collect()
+}
@@ -0,0 +1,203 @@
+use std::{mem, rc::Rc, cell::{Cell, RefCell}};
+use ocl::{Event, EventList, Program};
+use gen_error::{GenResult, GenError};
+use memory::{MemStrategy, MatrixBuffer, Access};
+use trace::Tracer;
+use epilogue::Epilogue;
+use {coverage, watchdog, OclEnv, build_ocl_program, build_gemm_kernel, get_execution_time_ns, ceil_divisible_by};
+
+/* A command enqueued on the way to a matrix's contents, traced and timed once the host synchronizes */
+#[derive(Clone)]
+struct PendingCommand {
+    name: String,
+    event: Event,
+    ops: u64,
+    /* Buffers the command reads and writes, so that dropping an intermediate handle doesn't free them (nor,
+     * under use_host_ptr, their host backing) while the command may still be running */
+    buffers: Vec<Rc<MatrixBuffer>>
+}
+
+/* Opaque handle to a matrix in device memory. Operations on handles enqueue their commands behind the events
+ * producing their inputs and return at once, so intermediate results of a chain never go through the host;
+ * the host blocks only in synchronize and read. */
+pub struct DeviceMatrix {
+    pub rows: u32,
+    pub cols: u32,
+    buffer: Rc<MatrixBuffer>,
+    /* The buffer holds the transpose (a cols x rows row-major matrix), as uploaded from column-major data */
+    transposed: bool,
+    /* Completes once the contents are in place; None if they already were when the handle was made */
+    ready: Option<Event>,
+    /* Everything the contents still depend on, including the commands producing the inputs */
+    pending: Vec<PendingCommand>,
+    /* Set once a product of this handle has been enqueued: that handle carries the pending commands on */
+    depended_on: Cell<bool>
+}
+
+impl DeviceMatrix {
+    /* Uploads are blocking, so the handle starts out ready */
+    pub(crate) fn upload(ocl_env: &OclEnv, strategy: MemStrategy, data: &[f32], rows: u32, cols: u32, transposed: bool, name: &str) -> GenResult<DeviceMatrix> {
+        if data.len() != (rows * cols) as usize {
+            return gen_error_format!("{} has {} elements, expected {}x{}", name, data.len(), rows, cols);
+        }
+        let buffer = MatrixBuffer::with_data(&ocl_env.queue, strategy, data, Access::ReadOnly, &ocl_env.tracer, name)?;
+        Ok(DeviceMatrix { rows, cols, buffer: Rc::new(buffer), transposed, ready: None, pending: Vec::new(), depended_on: Cell::new(false) })
+    }
+
+    /* Waits (under the watchdog) for the commands producing the contents and returns their total execution time */
+    pub(crate) fn synchronize(&mut self, ocl_env: &OclEnv, name: &str) -> GenResult<u64> {
+        if let Some(ref ready) = self.ready {
+            let ops = self.pending.iter().map(|command| command.ops).sum();
+            ocl_env.watchdog.wait_or_fail(&ocl_env.queue, ready, name, ops)?;
+        }
+        let mut time_ns = 0;
+        for command in self.pending.drain(..) {
+            ocl_env.tracer.command(&command.name, "kernel", &command.event)?;
+            time_ns += get_execution_time_ns(&command.event)?;
+        }
+        Ok(time_ns)
+    }
+
+    /* Row-major, whichever way the matrix is stored */
+    pub(crate) fn read(&mut self, ocl_env: &OclEnv, name: &str) -> GenResult<Vec<f32>> {
+        self.synchronize(ocl_env, name)?;
+        let mut data = vec![0.0f32; (self.rows * self.cols) as usize];
+        self.buffer.read(&ocl_env.queue, &mut data, &ocl_env.tracer, name)?;
+        if self.transposed {
+            let (rows, cols) = (self.rows as usize, self.cols as usize);
+            data = (0..rows * cols).map(|i| data[i % cols * rows + i / cols]).collect();
+        }
+        Ok(data)
+    }
+}
+
+impl Drop for DeviceMatrix {
+    /* A product of this handle holds on to its pending commands, so dropping an intermediate is fine. Dropping a
+     * handle nothing depends on before synchronizing (e.g. on an error) leaks the buffers of its commands rather
+     * than freeing them under a running kernel; waiting instead could hang on a kernel the watchdog abandoned. */
+    fn drop(&mut self) {
+        let running = self.ready.as_ref().map(|ready| !ready.is_complete().unwrap_or(false)).unwrap_or(false);
+        if running && !self.depended_on.get() {
+            for command in self.pending.iter_mut() {
+                mem::forget(mem::take(&mut command.buffers));
+            }
+        }
+    }
+}
+
+/* GEMM on device matrix handles with tiled.cl, built once for every combination of transposed operands it gets */
+pub struct DeviceGemm {
+    strategy: MemStrategy,
+    tile_size: u32,
+    programs: RefCell<Vec<((bool, bool), Program)>>
+}
+
+impl DeviceGemm {
+    pub fn new(ocl_env: &OclEnv, strategy: MemStrategy, tile_size: u32) -> GenResult<DeviceGemm> {
+        let gemm = DeviceGemm { strategy, tile_size, programs: RefCell::new(Vec::new()) };
+        /* Row-major operands are the common case; building for them now also reports a broken kernel directory early */
+        gemm.program(ocl_env, (false, false))?;
+        Ok(gemm)
+    }
+
+    fn program(&self, ocl_env: &OclEnv, transposed: (bool, bool)) -> GenResult<Program> {
+        if let Some(&(_, ref program)) = self.programs.borrow().iter().find(|&&(key, _)| key == transposed) {
+            return Ok(program.clone());
+        }
+        let kernel_defs = format!("#define TILE_SIZE {}\n{}{}{}{}", self.tile_size, coverage::source_defines(false), Epilogue::source_defines(None),
+                                  if transposed.0 { "#define TRANSPOSE_A\n" } else { "" }, if transposed.1 { "#define TRANSPOSE_B\n" } else { "" });
+        let program = build_ocl_program(ocl_env, kernel_defs, "", "tiled.cl")?;
+        self.programs.borrow_mut().push((transposed, program.clone()));
+        Ok(program)
+    }
+
+    /* Enqueues C = A * B once A and B are ready, without waiting for it */
+    pub fn multiply(&self, ocl_env: &OclEnv, a: &DeviceMatrix, b: &DeviceMatrix, name: &str) -> GenResult<DeviceMatrix> {
+        if a.cols != b.rows {
+            return gen_error_format!("{}: cannot multiply {}x{} by {}x{}", name, a.rows, a.cols, b.rows, b.cols);
+        }
+        let (m, n, p, tile_size) = (a.rows, a.cols, b.cols, self.tile_size);
+        let queue = &ocl_env.queue;
+        let program = self.program(ocl_env, (a.transposed, b.transposed))?;
+        let buffer_c = Rc::new(MatrixBuffer::new(queue, self.strategy, (m * p) as usize, Access::ReadWrite)?);
+        let kernel = build_gemm_kernel(queue, &program, "tiled", [&a.buffer.buffer, &b.buffer.buffer, &buffer_c.buffer], [m, n, p], None, None)?;
+
+        let wait_list = EventList::from(a.ready.iter().chain(b.ready.iter()).cloned().collect::<Vec<_>>());
+        let mut event = Event::empty();
+        let command = kernel.cmd().queue(queue)
+            .global_work_size([ceil_divisible_by(m, tile_size), ceil_divisible_by(p, tile_size)])
+            .local_work_size([tile_size, tile_size]);
+        unsafe {
+            if wait_list.is_empty() { command.enew(&mut event).enq()?; }
+            else { command.ewait(&wait_list).enew(&mut event).enq()?; }
+        }
+        /* Submit now; nothing waits on the queue until the result is read */
+        queue.flush()?;
+
+        /* A handle used for both operands contributes its commands once */
+        let mut pending = Vec::new();
+        for command in a.pending.iter().chain(b.pending.iter()) {
+            if !pending.iter().any(|c: &PendingCommand| c.event == command.event) {
+                pending.push(command.clone());
+            }
+        }
+        pending.push(PendingCommand {
+            name: name.to_owned(),
+            event: event.clone(),
+            ops: 2 * (m as u64) * (n as u64) * (p as u64),
+            buffers: vec![a.buffer.clone(), b.buffer.clone(), buffer_c.clone()]
+        });
+        a.depended_on.set(true);
+        b.depended_on.set(true);
+        Ok(DeviceMatrix { rows: m, cols: p, buffer: buffer_c, transposed: false, ready: Some(event), pending, depended_on: Cell::new(false) })
+    }
+}
+
+/* The tiled GEMM for use from other crates. Matrices are row-major, except for those uploaded with
+ * upload_transposed, which the kernel reads in place. Multiplying DeviceMatrix handles keeps intermediate
+ * products on the device, and only reading a handle waits for the device. E.g.
+ *   let gemm = Gemm::new("NVIDIA CUDA", 16, "path/to/matrix_mul_rs")?;
+ *   let (a, b) = (gemm.upload(&a_data, m, n)?, gemm.upload(&b_data, n, p)?);
+ *   let mut c = gemm.multiply(&gemm.multiply(&a, &b)?, &a_again)?;  // nothing waits yet
+ *   let c_data = gemm.read(&mut c)?; */
+pub struct Gemm {
+    ocl_env: OclEnv,
+    kernel: DeviceGemm
+}
+
+impl Gemm {
+    /* kernel_dir holds tiled.cl and include/, i.e. this crate's root directory */
+    pub fn new(platform_name: &str, tile_size: u32, kernel_dir: &str) -> GenResult<Gemm> {
+        let ocl_env = OclEnv::new(platform_name, Tracer::new(false), watchdog::Watchdog::estimated(), kernel_dir)?;
+        if tile_size == 0 || tile_size * tile_size > ocl_env.max_work_group_size {
+            return gen_error_format!("Tile size {} doesn't fit in a work group of at most {} work items", tile_size, ocl_env.max_work_group_size);
+        }
+        let kernel = DeviceGemm::new(&ocl_env, MemStrategy::AllocHostPtr, tile_size)?;
+        Ok(Gemm { ocl_env, kernel })
+    }
+
+    pub fn upload(&self, data: &[f32], rows: u32, cols: u32) -> GenResult<DeviceMatrix> {
+        DeviceMatrix::upload(&self.ocl_env, MemStrategy::AllocHostPtr, data, rows, cols, false, "input")
+    }
+
+    /* For a rows x cols matrix stored column-major, i.e. data holds its transpose row-major */
+    pub fn upload_transposed(&self, data: &[f32], rows: u32, cols: u32) -> GenResult<DeviceMatrix> {
+        DeviceMatrix::upload(&self.ocl_env, MemStrategy::AllocHostPtr, data, rows, cols, true, "input")
+    }
+
+    /* Enqueues A * B behind whatever produces A and B and returns at once */
+    pub fn multiply(&self, a: &DeviceMatrix, b: &DeviceMatrix) -> GenResult<DeviceMatrix> {
+        self.kernel.multiply(&self.ocl_env, a, b, "GEMM")
+    }
+
+    /* Waits for the commands producing the matrix, then downloads it */
+    pub fn read(&self, matrix: &mut DeviceMatrix) -> GenResult<Vec<f32>> {
+        matrix.read(&self.ocl_env, "result")
+    }
+
+    /* C = A * B for an m x n A and an n x p B, through the host */
+    pub fn gemm(&self, a: &[f32], b: &[f32], m: u32, n: u32, p: u32) -> GenResult<Vec<f32>> {
+        let (a, b) = (self.upload(a, m, n)?, self.upload(b, n, p)?);
+        self.read(&mut self.multiply(&a, &b)?)
+    }
+}
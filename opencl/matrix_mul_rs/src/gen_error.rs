@@ -61,5 +61,6 @@ macro_rules! impl_from_as_to_string {
 
 impl_from_as_to_string!(io::Error);
 impl_from_as_to_string!(num::ParseFloatError);
+impl_from_as_to_string!(num::ParseIntError);
 impl_from_as_to_string!(ocl::Error);
 impl_from_as_to_string!(ocl_core::error::Error);
@@ -0,0 +1,114 @@
+use std::{cmp, fs::File, io::BufWriter, io::prelude::*};
+use gen_error::GenResult;
+
+/* Pixel values: elements within tolerance are black, tile boundaries are dark gray, and erroneous elements
+ * range from ERROR_MIN_SHADE (just above tolerance) to white (the largest error in the matrix) on a log scale */
+const GRID_SHADE: u8 = 48;
+const ERROR_MIN_SHADE: u8 = 96;
+const MAX_PRINT_TILES: usize = 10;
+/* Larger tile grids are not drawn as text */
+const MAX_TILE_MAP_SIZE: u32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ErrorKind { Absolute, Relative }
+
+/* Writes <stem>_abs.pgm and <stem>_rel.pgm, with the comment lines in the header */
+pub fn write_error_maps(stem: &str, expected: &[f32], actual: &[f32], (rows, cols): (u32, u32), tile_size: u32, tolerance: f32,
+                        comments: &[String]) -> GenResult<()> {
+    for &(kind, suffix) in [(ErrorKind::Absolute, "abs"), (ErrorKind::Relative, "rel")].iter() {
+        let filename = format!("{}_{}.pgm", stem, suffix);
+        let errors = expected.iter().zip(actual.iter()).map(|(&e, &a)| element_error(kind, e, a)).collect::<Vec<_>>();
+        let pixels = shade_errors(&errors, rows, cols, tile_size, tolerance);
+
+        let mut out = BufWriter::new(File::create(&filename)?);
+        writeln!(out, "P5")?;
+        for comment in comments.iter() {
+            writeln!(out, "# {}", comment)?;
+        }
+        write!(out, "{} {}\n255\n", cols, rows)?;
+        out.write_all(&pixels)?;
+        println!("Error map written to {}", filename);
+    }
+    Ok(())
+}
+
+fn element_error(kind: ErrorKind, expected: f32, actual: f32) -> f32 {
+    let abs_error = (expected - actual).abs();
+    match kind {
+        ErrorKind::Absolute => abs_error,
+        ErrorKind::Relative => abs_error / expected.abs().max(::std::f32::MIN_POSITIVE)
+    }
+}
+
+fn shade_errors(errors: &[f32], rows: u32, cols: u32, tile_size: u32, tolerance: f32) -> Vec<u8> {
+    let max_error = errors.iter().cloned().fold(0.0f32, f32::max);
+    /* NaNs (e.g. from uninitialized memory) are always shown as the largest error */
+    let log_range = (max_error / tolerance).ln().max(::std::f32::EPSILON);
+
+    (0..rows * cols).map(|i| {
+        let (row, col) = (i / cols, i % cols);
+        let error = errors[i as usize];
+        if error > tolerance || error.is_nan() {
+            let scale = if error.is_nan() { 1.0 } else { ((error / tolerance).ln() / log_range).min(1.0) };
+            ERROR_MIN_SHADE + (scale * (255 - ERROR_MIN_SHADE) as f32) as u8
+        }
+        else if row % tile_size == 0 || col % tile_size == 0 { GRID_SHADE }
+        else { 0 }
+    }).collect()
+}
+
+/* Prints which TILE_SIZE x TILE_SIZE tiles of the result contain errors, classifying fully wrong tiles
+ * (typically work groups that never ran) and tiles on the padded right/bottom edges */
+pub fn print_tile_summary(expected: &[f32], actual: &[f32], rows: u32, cols: u32, tile_size: u32, tolerance: f32) {
+    let (tile_rows, tile_cols) = ((rows + tile_size - 1) / tile_size, (cols + tile_size - 1) / tile_size);
+    let mut tile_errors = vec![0u32; (tile_rows * tile_cols) as usize];
+    for (i, (&e, &a)) in expected.iter().zip(actual.iter()).enumerate() {
+        let error = (e - a).abs();
+        if error > tolerance || error.is_nan() {
+            let (row, col) = (i as u32 / cols, i as u32 % cols);
+            tile_errors[((row / tile_size) * tile_cols + col / tile_size) as usize] += 1;
+        }
+    }
+
+    let tile_elements = |tile_row: u32, tile_col: u32| {
+        cmp::min(tile_size, rows - tile_row * tile_size) * cmp::min(tile_size, cols - tile_col * tile_size)
+    };
+    let (mut full_tiles, mut partial_tiles, mut edge_tiles) = (0, 0, 0);
+    let mut listed = 0;
+    for tile_row in 0..tile_rows {
+        for tile_col in 0..tile_cols {
+            let errors = tile_errors[(tile_row * tile_cols + tile_col) as usize];
+            if errors == 0 { continue; }
+            let elements = tile_elements(tile_row, tile_col);
+            if errors == elements { full_tiles += 1; } else { partial_tiles += 1; }
+
+            let on_edge = (tile_row == tile_rows - 1 && rows % tile_size != 0) || (tile_col == tile_cols - 1 && cols % tile_size != 0);
+            if on_edge { edge_tiles += 1; }
+
+            listed += 1;
+            if listed <= MAX_PRINT_TILES {
+                println!("Tile (row {}, col {}): {} of {} elements wrong{}", tile_row, tile_col, errors, elements,
+                         if on_edge { " (padded edge)" } else { "" });
+            }
+        }
+    }
+    if listed > MAX_PRINT_TILES {
+        println!("...\n({} tiles omitted)", listed - MAX_PRINT_TILES);
+    }
+    println!("Tiles with errors: {} of {} ({} entirely wrong, {} partially wrong, {} on padded edges)",
+             full_tiles + partial_tiles, tile_rows * tile_cols, full_tiles, partial_tiles, edge_tiles);
+
+    if tile_rows <= MAX_TILE_MAP_SIZE && tile_cols <= MAX_TILE_MAP_SIZE {
+        println!("Tile map ('.' correct, '+' some errors, '#' entirely wrong):");
+        for tile_row in 0..tile_rows {
+            let line = (0..tile_cols).map(|tile_col| {
+                match tile_errors[(tile_row * tile_cols + tile_col) as usize] {
+                    0 => '.',
+                    errors if errors == tile_elements(tile_row, tile_col) => '#',
+                    _ => '+'
+                }
+            }).collect::<String>();
+            println!("    {}", line);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use std::fmt::{self, Display, Formatter};
+use gen_error::{GenResult, GenError};
+
+/* Quotes and escapes a string for inclusion in JSON output */
+pub fn string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped += "\\\"",
+            '\\' => escaped += "\\\\",
+            '\n' => escaped += "\\n",
+            '\r' => escaped += "\\r",
+            '\t' => escaped += "\\t",
+            c if (c as u32) < 0x20 => escaped += &format!("\\u{:04x}", c as u32),
+            c => escaped.push(c)
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/* Formats key-value pairs whose values are already JSON as an object */
+pub fn object(fields: &[(&str, String)]) -> String {
+    let fields = fields.iter().map(|&(key, ref value)| format!("{}: {}", string(key), value)).collect::<Vec<_>>();
+    format!("{{{}}}", fields.join(", "))
+}
+
+/* Formats values that are already JSON as an array */
+pub fn array(items: &[String]) -> String {
+    format!("[{}]", items.join(", "))
+}
+
+/* Parsed JSON; objects keep their keys in document order */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>)
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match *self {
+            Value::Object(ref fields) => fields.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self { Value::String(ref s) => Some(s), _ => None }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match *self { Value::Array(ref items) => Some(items), _ => None }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self { Value::Number(n) => Some(n), _ => None }
+    }
+}
+
+/* Compact JSON on a single line */
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Value::Number(_) => write!(f, "null"),
+            Value::String(ref s) => write!(f, "{}", string(s)),
+            Value::Array(ref items) => write!(f, "{}", array(&items.iter().map(|item| item.to_string()).collect::<Vec<_>>())),
+            Value::Object(ref fields) => write!(f, "{}", object(&fields.iter().map(|&(ref k, ref v)| (k.as_str(), v.to_string())).collect::<Vec<_>>()))
+        }
+    }
+}
+
+pub fn parse(s: &str) -> GenResult<Value> {
+    let mut parser = Parser { chars: s.chars().collect(), pos: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return parser.error("trailing characters");
+    }
+    Ok(value)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize
+}
+
+impl Parser {
+    fn error<T>(&self, what: &str) -> GenResult<T> {
+        gen_error_format!("Invalid JSON at character {}: {}", self.pos, what)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    /* Consumes c after optional whitespace */
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.chars.get(self.pos) == Some(&c) {
+            self.pos += 1;
+            true
+        }
+        else { false }
+    }
+
+    fn keyword(&mut self, word: &str, value: Value) -> GenResult<Value> {
+        let end = self.pos + word.len();
+        if end <= self.chars.len() && self.chars[self.pos..end].iter().cloned().eq(word.chars()) {
+            self.pos = end;
+            Ok(value)
+        }
+        else { self.error("unknown literal") }
+    }
+
+    fn value(&mut self) -> GenResult<Value> {
+        self.skip_whitespace();
+        match self.chars.get(self.pos).cloned() {
+            Some('{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                if self.eat('}') { return Ok(Value::Object(fields)); }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    if !self.eat(':') { return self.error("expected ':'"); }
+                    fields.push((key, self.value()?));
+                    if self.eat('}') { return Ok(Value::Object(fields)); }
+                    if !self.eat(',') { return self.error("expected ',' or '}'"); }
+                }
+            },
+            Some('[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                if self.eat(']') { return Ok(Value::Array(items)); }
+                loop {
+                    items.push(self.value()?);
+                    if self.eat(']') { return Ok(Value::Array(items)); }
+                    if !self.eat(',') { return self.error("expected ',' or ']'"); }
+                }
+            },
+            Some('"') => Ok(Value::String(self.string()?)),
+            Some('t') => self.keyword("true", Value::Bool(true)),
+            Some('f') => self.keyword("false", Value::Bool(false)),
+            Some('n') => self.keyword("null", Value::Null),
+            Some(c) if c == '-' || c.is_digit(10) => {
+                let start = self.pos;
+                while self.pos < self.chars.len() && (self.chars[self.pos].is_digit(10) || "+-.eE".contains(self.chars[self.pos])) {
+                    self.pos += 1;
+                }
+                let number = self.chars[start..self.pos].iter().collect::<String>();
+                number.parse::<f64>().map(Value::Number).or(self.error("malformed number"))
+            },
+            _ => self.error("expected a value")
+        }
+    }
+
+    fn string(&mut self) -> GenResult<String> {
+        if self.chars.get(self.pos) != Some(&'"') {
+            return self.error("expected a string");
+        }
+        self.pos += 1;
+        let mut s = String::new();
+        while let Some(c) = self.chars.get(self.pos).cloned() {
+            self.pos += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let escaped = self.chars.get(self.pos).cloned();
+                    self.pos += 1;
+                    match escaped {
+                        Some('n') => s.push('\n'),
+                        Some('r') => s.push('\r'),
+                        Some('t') => s.push('\t'),
+                        Some('b') => s.push('\u{8}'),
+                        Some('f') => s.push('\u{c}'),
+                        Some('u') => {
+                            let hex = self.chars.iter().skip(self.pos).take(4).collect::<String>();
+                            let code = u32::from_str_radix(&hex, 16).or(self.error("malformed \\u escape"))?;
+                            s.push(::std::char::from_u32(code).unwrap_or('\u{fffd}'));
+                            self.pos += 4;
+                        },
+                        Some(c) => s.push(c),
+                        None => break
+                    }
+                },
+                c => s.push(c)
+            }
+        }
+        self.error("unterminated string")
+    }
+}
@@ -0,0 +1,981 @@
+/* The harness as a library: main.rs only hands it the command line. Other crates get the GEMM path through
+ * Gemm and DeviceMatrix, and with the "ndarray" feature through Array2 and ArrayView2. */
+
+extern crate ocl;
+extern crate ocl_core;
+#[cfg(feature = "ndarray")]
+extern crate ndarray;
+
+#[macro_use]
+mod gen_error;
+mod accuracy;
+mod batch;
+mod binary_dump;
+mod binary_kernels;
+mod build_config;
+mod chain;
+mod cli;
+mod conv;
+mod coverage;
+mod environment;
+mod epilogue;
+mod gemm;
+mod heatmap;
+mod matrix_io;
+mod memory;
+#[cfg(feature = "ndarray")]
+mod ndarray_gemm;
+mod occupancy;
+mod precision;
+mod preprocess;
+mod quant;
+mod remote;
+mod subgroup;
+mod primitives;
+mod sparse;
+mod strassen;
+mod json;
+mod trace;
+mod watchdog;
+mod workload;
+
+use std::{process, fs::File, cmp, time::Instant};
+use ocl::{Platform, Device, Context, Queue, Program, Kernel, Event, Buffer};
+use memory::{MemStrategy, MatrixBuffer, Access};
+use trace::Tracer;
+use epilogue::Epilogue;
+use environment::Environment;
+use batch::BatchShape;
+use binary_kernels::KernelArg;
+
+pub use gen_error::{GenError, GenResult};
+pub use gemm::{Gemm, DeviceMatrix};
+pub use batch::generate_matrix;
+
+const MAX_PRINT_ERRORS: u32 = 10;
+const ERROR_TOLERANCE: f32 = 0.02;
+
+/* OpenCL objects shared by all runs */
+struct OclEnv {
+    device: Device,
+    context: Context,
+    queue: Queue,
+    max_work_group_size: u32,
+    subgroup_support: subgroup::SubgroupSupport,
+    tracer: Tracer,
+    environment: Environment,
+    watchdog: watchdog::Watchdog,
+    kernel_dir: String
+}
+
+impl OclEnv {
+    fn new(platform_name: &str, tracer: Tracer, watchdog: watchdog::Watchdog, kernel_dir: &str) -> GenResult<OclEnv> {
+        let (device, context, queue, environment) = init_ocl(platform_name.to_owned())?;
+        tracer.add_queue(&queue, &device.name()?, "queue 0");
+        Ok(OclEnv {
+            max_work_group_size: device.max_wg_size()? as u32,
+            subgroup_support: subgroup::SubgroupSupport::query(&device)?,
+            kernel_dir: kernel_dir.to_owned(),
+            device, context, queue, tracer, environment, watchdog
+        })
+    }
+}
+
+struct HostMatrices {
+    a: Vec<f32>,
+    b: Vec<f32>,
+    /* With the epilogue applied */
+    c_expected: Vec<f32>,
+    /* Epilogue bias, empty without an epilogue */
+    bias: Vec<f32>
+}
+
+/* Timings of one pass over all kernels with a given memory strategy */
+struct StrategyReport {
+    strategy: MemStrategy,
+    upload_ms: f64,
+    padding_ns: u64,
+    readback_ms: Vec<f64>,
+    /* Run label and kernel execution time */
+    kernel_runs: Vec<(String, u64)>,
+    /* Labels of runs abandoned by the watchdog, or skipped while an abandoned kernel blocked the queue */
+    timed_out: Vec<String>
+}
+
+/* Inputs and result buffer shared by all GEMM runs of one memory strategy pass */
+struct GemmRunContext<'a> {
+    args: &'a cli::Args,
+    ocl_env: &'a OclEnv,
+    matrices: &'a HostMatrices,
+    buffer_c: &'a MatrixBuffer,
+    /* Used to reset the result buffer between kernel runs */
+    matrix_c_empty: &'a [f32]
+}
+
+/* NDRange and extra defines a kernel is run with */
+struct KernelLaunch {
+    description: String,
+    kernel_defs: String,
+    global_size: [u32; 2],
+    local_size: [u32; 2],
+    /* Subgroup size the kernel is written for; checked against the driver after the build */
+    subgroup_size: Option<u32>
+}
+
+/* Everything the binary does, given its command line (including the program name) */
+pub fn run_command_line(mut command_line: Vec<String>) {
+    match command_line.get(1).map(|arg| arg.as_str()) {
+        Some("agent") => { unwrap!(remote::run_agent(&command_line)); return; },
+        Some("client") => { unwrap!(remote::run_client(&command_line)); return; },
+        _ => ()
+    }
+    let mut recorded_environment = None;
+    if command_line.get(1).map(|arg| arg == "replay").unwrap_or(false) {
+        let (replayed, recorded) = unwrap!(environment::replay_command_line(&command_line));
+        command_line = replayed;
+        recorded_environment = Some(recorded);
+    }
+    let args = match unwrap!(cli::parse_args(&command_line)) {
+        Some(args) => args,
+        None => { cli::print_usage(); return; }
+    };
+
+    let mut ocl_env = unwrap!(OclEnv::new(&args.platform_name, Tracer::new(args.trace_file.is_some()), watchdog::Watchdog::new(&args),
+                                          &args.kernel_dir));
+    ocl_env.environment.command_line = command_line;
+    ocl_env.environment.print();
+    /* Shared by the non-GEMM workloads, so that the copy bandwidth is measured once per memory strategy */
+    let mut peaks = Vec::new();
+    for &workload in args.workloads.iter() {
+        if workload == cli::Workload::Gemm {
+            run_gemm_workload(&args, &ocl_env);
+        }
+        else {
+            for &strategy in args.mem_strategies.iter() {
+                if !unwrap!(ocl_env.watchdog.queue_available(&format!("{:?} with memory strategy {}", workload, strategy))) {
+                    continue;
+                }
+                match workload::run(&args, &ocl_env, workload, strategy, &mut peaks) {
+                    /* A timed out kernel leaves the rest of the workload unverifiable, but not the others */
+                    Err(ref err) if ocl_env.watchdog.tripped() => println!("{}", err),
+                    result => unwrap!(result)
+                }
+            }
+        }
+    }
+    if let Some(ref recorded) = recorded_environment {
+        ocl_env.environment.compare(recorded);
+    }
+    remote::print_result(&args, "environment", &[("environment", ocl_env.environment.to_json())]);
+    if let Some(ref filename) = args.trace_file {
+        unwrap!(ocl_env.tracer.write(filename, &ocl_env.environment.to_json()));
+    }
+    if let Some(ref filename) = args.record_file {
+        unwrap!(ocl_env.environment.write_record(filename));
+    }
+}
+
+/* Batched mode, or every GEMM kernel once per memory strategy followed by the int8 family */
+fn run_gemm_workload(args: &cli::Args, ocl_env: &OclEnv) {
+    if args.batch_count > 0 || !args.batch_shapes.is_empty() {
+        let shapes = if args.batch_shapes.is_empty() { vec![BatchShape { m: args.m, n: args.n, p: args.p }] } else { args.batch_shapes.clone() };
+        let layout = batch::BatchLayout::new(args.batch_count, &shapes);
+        for &strategy in args.mem_strategies.iter() {
+            if unwrap!(ocl_env.watchdog.queue_available(&format!("batched with memory strategy {}", strategy))) {
+                unwrap!(run_batched_kernel(args, ocl_env, &layout, strategy));
+            }
+        }
+    }
+    else {
+        let matrices = unwrap!(read_matrices(&args.matrix_files, args.m, args.n, args.p, args.epilogue.as_ref()));
+
+        /* Numbers output files so that repeated runs of a kernel (e.g. in a sweep) don't overwrite each other */
+        let mut run_index = 0;
+        let reports = args.mem_strategies.iter().filter_map(|&strategy| {
+            if !unwrap!(ocl_env.watchdog.queue_available(&format!("memory strategy {}", strategy))) {
+                return None;
+            }
+            match run_gemm_kernels(args, ocl_env, &matrices, strategy, &mut run_index) {
+                /* E.g. padding timed out; the other strategies may still run */
+                Err(ref err) if ocl_env.watchdog.tripped() => { println!("{}", err); None },
+                result => Some(unwrap!(result))
+            }
+        }).collect::<Vec<_>>();
+        if reports.len() > 1 || reports.iter().any(|report| !report.timed_out.is_empty()) {
+            print_strategy_summary(&reports);
+        }
+        if args.int8 && unwrap!(ocl_env.watchdog.queue_available("int8.cl")) {
+            let best_fp32_ns = reports.iter().flat_map(|report| report.kernel_runs.iter().map(|&(_, time_ns)| time_ns)).min();
+            unwrap!(run_int8_kernels(args, ocl_env, &matrices, best_fp32_ns));
+        }
+    }
+}
+
+/* Runs every GEMM kernel (and build variant) once, with buffers allocated according to the memory strategy */
+fn run_gemm_kernels(args: &cli::Args, ocl_env: &OclEnv, matrices: &HostMatrices, strategy: MemStrategy, run_index: &mut u32) -> GenResult<StrategyReport> {
+    let (device, queue, tracer) = (&ocl_env.device, &ocl_env.queue, &ocl_env.tracer);
+    let (tile_size, m, n, p) = (args.tile_size, args.m, args.n, args.p);
+    println!("===\nMemory strategy: {}", strategy);
+    let strategy_start = tracer.now();
+
+    let upload_start = Instant::now();
+    let buffer_a = MatrixBuffer::with_data(queue, strategy, &matrices.a, Access::ReadOnly, tracer, "A")?;
+    let buffer_b = MatrixBuffer::with_data(queue, strategy, &matrices.b, Access::ReadOnly, tracer, "B")?;
+    /* The separate epilogue pass updates C in place */
+    let access_c = if args.epilogue.is_some() { Access::ReadWrite } else { Access::WriteOnly };
+    let buffer_c = MatrixBuffer::new(queue, strategy, (m * p) as usize, access_c)?;
+    let buffer_bias = match args.epilogue {
+        Some(ref epilogue) if epilogue.bias => Some(MatrixBuffer::with_data(queue, strategy, &matrices.bias, Access::ReadOnly, tracer, "bias")?),
+        _ => None
+    };
+    queue.finish()?;
+    let mut report = StrategyReport {
+        strategy,
+        upload_ms: memory::duration_ms(upload_start.elapsed()),
+        padding_ns: 0,
+        readback_ms: Vec::new(),
+        kernel_runs: Vec::new(),
+        timed_out: Vec::new()
+    };
+    println!("Input upload took {:.3} [ms]", report.upload_ms);
+
+    /* Used to reset the result buffer between kernel runs to ensure correct results */
+    let matrix_c_empty = vec![0.0f32; (m * p) as usize];
+
+    /* wideloads.cl setup */
+    let n_wide = ceil_divisible_by(n, tile_size);
+    let p_wide = ceil_divisible_by(p, tile_size);
+    let wide_buffer_a = if n_wide != n {
+        let (buffer, time_ns) = run_pad_cols_kernel(ocl_env, &buffer_a, m, n, tile_size, strategy)?;
+        report.padding_ns += time_ns;
+        Some(buffer)
+    }
+    else { None };
+    let ref_wide_buffer_a = wide_buffer_a.as_ref().unwrap_or(&buffer_a);
+    let wide_buffer_b = if p_wide != p {
+        let (buffer, time_ns) = run_pad_cols_kernel(ocl_env, &buffer_b, n, p, tile_size, strategy)?;
+        report.padding_ns += time_ns;
+        Some(buffer)
+    }
+    else { None };
+    let ref_wide_buffer_b = wide_buffer_b.as_ref().unwrap_or(&buffer_b);
+
+    let run_context = GemmRunContext { args, ocl_env, matrices, buffer_c: &buffer_c, matrix_c_empty: &matrix_c_empty };
+
+    /* Separate elementwise pass the fused epilogue is compared against */
+    let epilogue_kernel = match args.epilogue {
+        Some(ref epilogue) => Some(build_epilogue_kernel(ocl_env, epilogue, &buffer_c, buffer_bias.as_ref(), m, p, tile_size)?),
+        None => None
+    };
+
+    for &src_filename in ["tiled.cl", "wideloads.cl", "subgroups.cl"].iter() {
+        let (kernel_name, _ext) = src_filename.split_at(src_filename.len() - 3);
+        if kernel_name == "wideloads" && tile_size % 4 != 0 {
+            println!("===\ntile_size is not divisible by 4; skipping wideloads");
+            continue;
+        }
+        if kernel_name == "subgroups" && tile_size % args.subgroup_block.cols != 0 {
+            println!("===\ntile_size is not divisible by the block width ({}); skipping subgroups", args.subgroup_block.cols);
+            continue;
+        }
+        println!("===\nRunning {}", kernel_name);
+
+        let launches = if kernel_name == "subgroups" {
+            println!("Subgroup support: {:?}, sizes to try: {:?}", ocl_env.subgroup_support.mode, ocl_env.subgroup_support.sizes);
+            let sizes = if args.subgroup_sizes.is_empty() { &ocl_env.subgroup_support.sizes } else { &args.subgroup_sizes };
+            sizes.iter().filter_map(|&size| {
+                let description = format!("subgroup size {}, {}x{} block per work item", size, args.subgroup_block.rows, args.subgroup_block.cols);
+                match subgroup::plan_launch(&ocl_env.subgroup_support, size, args.subgroup_block, ocl_env.max_work_group_size, m, p_wide) {
+                    Some(launch) => Some(KernelLaunch {
+                        description,
+                        kernel_defs: launch.kernel_defs,
+                        global_size: launch.global_size,
+                        local_size: launch.local_size,
+                        subgroup_size: Some(size)
+                    }),
+                    None => {
+                        println!("---\n{}: the emulated subgroup exchange exceeds local memory; skipping", description);
+                        None
+                    }
+                }
+            }).collect()
+        }
+        else {
+            let mut global_size = [ceil_divisible_by(m, tile_size), ceil_divisible_by(p, tile_size)];
+            let mut local_size = [tile_size, tile_size];
+            if kernel_name == "wideloads" { global_size[1] /= 4; local_size[1] /= 4; }
+            vec![KernelLaunch { description: String::new(), kernel_defs: String::new(), global_size, local_size, subgroup_size: None }]
+        };
+
+        for launch in launches {
+            let (global_size, local_size) = (launch.global_size, launch.local_size);
+            if !launch.description.is_empty() {
+                println!("---\nUsing {}", launch.description);
+            }
+            if local_size[0] * local_size[1] > ocl_env.max_work_group_size {
+                println!("Local work size exceeds device limits; skipping this kernel.");
+                println!("You may want to choose a smaller value for tile_size");
+                continue;
+            }
+
+            println!("Global work size: {} x {}, local work size: {} x {}", global_size[0], global_size[1], local_size[0], local_size[1]);
+
+            for variant in args.build_config.variants_for(kernel_name) {
+                println!("---\nBuild with {}", variant);
+                let run_label = if launch.description.is_empty() { format!("{}, {}", kernel_name, variant) }
+                                else { format!("{}, {}, {}", kernel_name, launch.description, variant) };
+                let gemm_defs = |instrument: bool, epilogue: Option<&Epilogue>| {
+                    format!("#define TILE_SIZE {}\n{}{}{}{}", tile_size, coverage::source_defines(instrument), Epilogue::source_defines(epilogue),
+                            launch.kernel_defs, variant.source_defines())
+                };
+                let run_start = tracer.now();
+                let program = match build_ocl_program(ocl_env, gemm_defs(args.instrument, args.epilogue.as_ref()), &variant.options, src_filename) {
+                    Ok(program) => program,
+                    /* A single bad option set shouldn't abort the rest of the sweep */
+                    Err(err) => { println!("Build failed, skipping this variant:\n{}", err); continue; }
+                };
+                dump_program_binaries(args, &program, &format!("{}_{}", kernel_name, *run_index + 1));
+                if !ocl_env.watchdog.queue_available(&run_label)? {
+                    report.timed_out.push(run_label);
+                    continue;
+                }
+
+                let coverage_counters = if args.instrument {
+                    Some(coverage::CoverageCounters::new(queue, global_size, local_size)?)
+                }
+                else { None };
+
+                let gemm_buffers = if kernel_name == "wideloads" || kernel_name == "subgroups" {
+                    [&ref_wide_buffer_a.buffer, &ref_wide_buffer_b.buffer, &buffer_c.buffer]
+                }
+                else { [&buffer_a.buffer, &buffer_b.buffer, &buffer_c.buffer] };
+                let kernel = build_gemm_kernel(queue, &program, kernel_name, gemm_buffers, [m, n, p],
+                                               buffer_bias.as_ref().map(|b| &b.buffer), coverage_counters.as_ref().map(|c| c.buffer()))?;
+
+                if let Some(expected_size) = launch.subgroup_size {
+                    match subgroup::kernel_subgroup_size(&kernel, device, local_size) {
+                        Some(actual_size) if actual_size != expected_size => {
+                            println!("The driver runs this kernel with subgroups of {} instead of {}; skipping", actual_size, expected_size);
+                            continue;
+                        },
+                        Some(_) => (),
+                        None => println!("Unable to query the kernel's subgroup size; assuming {}", expected_size)
+                    }
+                }
+
+                /* Not every driver implements all of the queries; the run is still useful without the estimate */
+                let occupancy = occupancy::Occupancy::estimate(&kernel, device, global_size, local_size)
+                    .map_err(|err| println!("Unable to estimate occupancy: {}", err)).ok();
+
+                let (total_time_ns, verification_errors) = match execute_gemm(&run_context, &kernel, kernel_name, &run_label,
+                                                                              (global_size, local_size), run_index, &mut report)? {
+                    Some(result) => result,
+                    None => {
+                        tracer.host_span_with_args(&run_label, "run", run_start, vec![("run", run_index.to_string()), ("status", json::string("timed out"))]);
+                        report.timed_out.push(run_label);
+                        continue;
+                    }
+                };
+                if let Some(ref occupancy) = occupancy {
+                    occupancy.print();
+                }
+                if let Some(ref counters) = coverage_counters {
+                    counters.read_report(queue)?.print(verification_errors);
+                    println!("(timings include the coverage counter atomics)");
+                }
+                if let Some(ref epilogue_kernel) = epilogue_kernel {
+                    let program = build_ocl_program(ocl_env, gemm_defs(false, None), &variant.options, src_filename)?;
+                    let gemm_kernel = build_gemm_kernel(queue, &program, kernel_name, gemm_buffers, [m, n, p], None, None)?;
+                    run_separate_epilogue(&run_context, &gemm_kernel, epilogue_kernel, (global_size, local_size), strategy, total_time_ns)?;
+                }
+                tracer.host_span_with_args(&run_label, "run", run_start, vec![("run", run_index.to_string())]);
+                report.kernel_runs.push((run_label, total_time_ns));
+            }
+        }
+    }
+
+    for binary_kernel in args.binary_kernels.iter() {
+        println!("===\nRunning {} from {}", binary_kernel.name, binary_kernel.binary_path);
+        if args.epilogue.is_some() {
+            println!("Precompiled kernels have no fused epilogue; skipping");
+            continue;
+        }
+        let (global_size, local_size) = (binary_kernel.global_size(m, p), binary_kernel.local_size);
+        if local_size[0] * local_size[1] > ocl_env.max_work_group_size {
+            println!("Local work size exceeds device limits; skipping this kernel.");
+            continue;
+        }
+        println!("Global work size: {} x {}, local work size: {} x {}", global_size[0], global_size[1], local_size[0], local_size[1]);
+
+        let run_label = format!("{} (binary)", binary_kernel.name);
+        if !ocl_env.watchdog.queue_available(&run_label)? {
+            report.timed_out.push(run_label);
+            continue;
+        }
+        let run_start = tracer.now();
+        let program = match build_ocl_binary_program(ocl_env, binary_kernel) {
+            Ok(program) => program,
+            /* Code objects are specific to a GPU architecture, so other devices are expected to reject them */
+            Err(err) => { println!("Unable to load the binary, skipping this kernel:\n{}", err); continue; }
+        };
+
+        let mut kernel_builder = Kernel::builder();
+        kernel_builder.queue(queue.clone()).program(&program).name(binary_kernel.kernel_name.as_str());
+        for &arg in binary_kernel.args.iter() {
+            match arg {
+                KernelArg::A => kernel_builder.arg(&buffer_a.buffer),
+                KernelArg::B => kernel_builder.arg(&buffer_b.buffer),
+                KernelArg::APadded => kernel_builder.arg(&ref_wide_buffer_a.buffer),
+                KernelArg::BPadded => kernel_builder.arg(&ref_wide_buffer_b.buffer),
+                KernelArg::C => kernel_builder.arg(&buffer_c.buffer),
+                KernelArg::M => kernel_builder.arg(m),
+                KernelArg::N => kernel_builder.arg(n),
+                KernelArg::P => kernel_builder.arg(p),
+                KernelArg::NPadded => kernel_builder.arg(n_wide),
+                KernelArg::PPadded => kernel_builder.arg(p_wide),
+                KernelArg::TileSize => kernel_builder.arg(tile_size)
+            };
+        }
+        let kernel = kernel_builder.build()?;
+        let occupancy = occupancy::Occupancy::estimate(&kernel, device, global_size, local_size)
+            .map_err(|err| println!("Unable to estimate occupancy: {}", err)).ok();
+
+        let total_time_ns = match execute_gemm(&run_context, &kernel, &binary_kernel.name, &run_label, (global_size, local_size), run_index, &mut report)? {
+            Some((total_time_ns, _)) => total_time_ns,
+            None => {
+                tracer.host_span_with_args(&run_label, "run", run_start, vec![("run", run_index.to_string()), ("status", json::string("timed out"))]);
+                report.timed_out.push(run_label);
+                continue;
+            }
+        };
+        if let Some(ref occupancy) = occupancy {
+            occupancy.print();
+        }
+        tracer.host_span_with_args(&run_label, "run", run_start, vec![("run", run_index.to_string())]);
+        report.kernel_runs.push((run_label, total_time_ns));
+    }
+    tracer.host_span(&format!("memory strategy {}", strategy), "run", strategy_start);
+    Ok(report)
+}
+
+fn print_strategy_summary(reports: &[StrategyReport]) {
+    println!("===\nMemory strategy summary");
+    for report in reports.iter() {
+        let mean_readback_ms = report.readback_ms.iter().sum::<f64>() / cmp::max(1, report.readback_ms.len()) as f64;
+        println!("{}: upload {:.3} [ms], padding {:.3} [ms], mean readback {:.3} [ms]",
+                 report.strategy, report.upload_ms, report.padding_ns as f64 / 1_000_000.0, mean_readback_ms);
+        for &(ref label, time_ns) in report.kernel_runs.iter() {
+            println!("    {}: {:.3} [ms]", label, time_ns as f64 / 1_000_000.0);
+        }
+        for label in report.timed_out.iter() {
+            println!("    {}: timed out", label);
+        }
+    }
+}
+
+/* Runs a built GEMM kernel on the strategy's buffers, then verifies, saves and reports the result.
+ * Returns the execution time and the number of wrong elements, or None if the watchdog abandoned the kernel. */
+fn execute_gemm(ctx: &GemmRunContext, kernel: &Kernel, kernel_name: &str, run_label: &str, (global_size, local_size): ([u32; 2], [u32; 2]),
+                run_index: &mut u32, report: &mut StrategyReport) -> GenResult<Option<(u64, u32)>> {
+    let (args, ocl_env, matrices) = (ctx.args, ctx.ocl_env, ctx.matrices);
+    let (queue, tracer) = (&ocl_env.queue, &ocl_env.tracer);
+    let (tile_size, m, n, p) = (args.tile_size, args.m, args.n, args.p);
+    let mut exec_event = Event::empty();
+
+    /* Important! We need to reset the result buffer between running the next kernel to avoid
+     * cases where the kernel doesn't compute some tiles and still reports a correct result */
+    ctx.buffer_c.write(queue, ctx.matrix_c_empty, tracer, "C (reset)")?;
+
+    unsafe {
+        kernel.cmd()
+            .queue(queue)
+            .global_work_size(global_size)
+            .local_work_size(local_size)
+            .enew(&mut exec_event)
+            .enq()?;
+    }
+
+    let total_flops = 2 * (n as u64) * (m as u64) * (p as u64);
+    if !ocl_env.watchdog.wait(queue, &exec_event, run_label, total_flops)? {
+        remote::print_result(args, "timeout", &[("kernel", json::string(kernel_name)), ("label", json::string(run_label)),
+                                                ("strategy", json::string(&report.strategy.to_string()))]);
+        return Ok(None);
+    }
+    tracer.command(run_label, "kernel", &exec_event)?;
+
+    let mut matrix_c_actual = vec![0.0f32; (m * p) as usize];
+    let readback_start = Instant::now();
+    ctx.buffer_c.read(queue, &mut matrix_c_actual, tracer, "C")?;
+    report.readback_ms.push(memory::duration_ms(readback_start.elapsed()));
+
+    *run_index += 1;
+    let verify_start = tracer.now();
+    let verification_errors = verify_results(&matrices.c_expected, &matrix_c_actual, p);
+    tracer.host_span("verify", "host", verify_start);
+    if let Some(ref prefix) = args.heatmap_prefix.as_ref().filter(|_| verification_errors > 0) {
+        heatmap::print_tile_summary(&matrices.c_expected, &matrix_c_actual, m, p, tile_size, ERROR_TOLERANCE);
+        let stem = format!("{}{}_{}", prefix, kernel_name, *run_index);
+        if let Err(err) = heatmap::write_error_maps(&stem, &matrices.c_expected, &matrix_c_actual, (m, p), tile_size, ERROR_TOLERANCE,
+                                                    &ocl_env.environment.comment_lines()) {
+            println!("Unable to write error maps: {}", err);
+        }
+    }
+    if let Some(ref path) = args.save_result {
+        let filename = matrix_io::numbered_filename(path, kernel_name, *run_index);
+        let written = matrix_io::write_matrix(&filename, &matrix_c_actual, m, p, &ocl_env.environment.comment_lines()).and_then(|()| {
+            /* .npy files get the environment record next to them instead */
+            if matrix_io::MatrixFormat::from_extension(&filename) == matrix_io::MatrixFormat::Npy {
+                ocl_env.environment.write_record(&format!("{}.json", filename))?;
+            }
+            Ok(())
+        });
+        match written {
+            Ok(()) => println!("Result written to {}", filename),
+            Err(err) => println!("Unable to write the result: {}", err)
+        }
+    }
+    let total_time_ns = get_execution_time_ns(&exec_event)?;
+    println!("Execution time is {} [ms]", total_time_ns as f64 / 1_000_000.0);
+    let total_flops_theory = (2 * (n as u64) - 1) * (m as u64) * (p as u64);
+    let exec_gflops = (total_flops_theory as f64 / total_time_ns as f64) / /* nano */ 1_000_000_000.0 * /* giga */ 1_000_000_000.0;
+    println!("Measured perf: {:.3} [GFLOPS], efficiency: {:.1}%", exec_gflops, exec_gflops / args.device_max_gflops * 100.0);
+    remote::print_result(args, "run", &[("kernel", json::string(kernel_name)), ("label", json::string(run_label)),
+                                        ("strategy", json::string(&report.strategy.to_string())), ("time_ns", total_time_ns.to_string()),
+                                        ("flops", total_flops_theory.to_string()), ("errors", verification_errors.to_string())]);
+    Ok(Some((total_time_ns, verification_errors)))
+}
+
+/* Arguments: A, B, C, M, N, P, then the epilogue bias and coverage counters if the kernel was built with them */
+fn build_gemm_kernel(queue: &Queue, program: &Program, kernel_name: &str, buffers: [&Buffer<f32>; 3], dims: [u32; 3],
+                     bias: Option<&Buffer<f32>>, coverage: Option<&Buffer<u32>>) -> GenResult<Kernel> {
+    let mut kernel_builder = Kernel::builder();
+    kernel_builder
+        .queue(queue.clone())
+        .program(program).name(kernel_name)
+        .arg(buffers[0]).arg(buffers[1]).arg(buffers[2])
+        .arg(dims[0]).arg(dims[1]).arg(dims[2]);
+    if let Some(bias) = bias {
+        kernel_builder.arg(bias);
+    }
+    if let Some(coverage) = coverage {
+        kernel_builder.arg(coverage);
+    }
+    Ok(kernel_builder.build()?)
+}
+
+fn build_epilogue_kernel(ocl_env: &OclEnv, epilogue: &Epilogue, buffer_c: &MatrixBuffer, bias: Option<&MatrixBuffer>, m: u32, p: u32, tile_size: u32) -> GenResult<Kernel> {
+    let kernel_defs = format!("#define TILE_SIZE {}\n{}", tile_size, Epilogue::source_defines(Some(epilogue)));
+    let program = build_ocl_program(ocl_env, kernel_defs, "", "epilogue.cl")?;
+
+    let mut kernel_builder = Kernel::builder();
+    kernel_builder
+        .queue(ocl_env.queue.clone())
+        .program(&program).name("epilogue")
+        .arg(&buffer_c.buffer).arg(m).arg(p);
+    if let Some(bias) = bias {
+        kernel_builder.arg(&bias.buffer);
+    }
+    Ok(kernel_builder.build()?)
+}
+
+/* Times the unfused GEMM followed by the elementwise epilogue kernel and compares it with the fused run */
+fn run_separate_epilogue(ctx: &GemmRunContext, gemm_kernel: &Kernel, epilogue_kernel: &Kernel, (global_size, local_size): ([u32; 2], [u32; 2]),
+                         strategy: MemStrategy, fused_time_ns: u64) -> GenResult<()> {
+    let (queue, tracer, buffer_c) = (&ctx.ocl_env.queue, &ctx.ocl_env.tracer, ctx.buffer_c);
+    let (m, p) = (ctx.args.m, ctx.args.p);
+    println!("---\nSeparate elementwise pass");
+    buffer_c.write(queue, ctx.matrix_c_empty, tracer, "C (reset)")?;
+
+    let run_start = tracer.now();
+    let (mut gemm_event, mut epilogue_event) = (Event::empty(), Event::empty());
+    unsafe {
+        gemm_kernel.cmd()
+            .queue(queue)
+            .global_work_size(global_size)
+            .local_work_size(local_size)
+            .enew(&mut gemm_event)
+            .enq()?;
+        epilogue_kernel.cmd()
+            .queue(queue)
+            .global_work_size([m, p])
+            .enew(&mut epilogue_event)
+            .enq()?;
+    }
+    if !ctx.ocl_env.watchdog.wait(queue, &epilogue_event, "unfused GEMM and epilogue", 2 * m as u64 * ctx.args.n as u64 * p as u64)? {
+        record_timeout(ctx.args, ctx.ocl_env, "epilogue", "unfused GEMM and epilogue", Some(strategy), run_start);
+        return Ok(());
+    }
+    tracer.command("unfused GEMM", "kernel", &gemm_event)?;
+    tracer.command("epilogue", "kernel", &epilogue_event)?;
+
+    let mut matrix_c_actual = vec![0.0f32; (m * p) as usize];
+    buffer_c.read(queue, &mut matrix_c_actual, tracer, "C")?;
+    verify_results(&ctx.matrices.c_expected, &matrix_c_actual, p);
+
+    let (gemm_time_ns, epilogue_time_ns) = (get_execution_time_ns(&gemm_event)?, get_execution_time_ns(&epilogue_event)?);
+    let separate_time_ns = gemm_time_ns + epilogue_time_ns;
+    println!("GEMM {:.3} [ms] + elementwise {:.3} [ms] = {:.3} [ms]; fusing saves {:.3} [ms] ({:.1}%)",
+             gemm_time_ns as f64 / 1_000_000.0, epilogue_time_ns as f64 / 1_000_000.0, separate_time_ns as f64 / 1_000_000.0,
+             (separate_time_ns as f64 - fused_time_ns as f64) / 1_000_000.0,
+             (separate_time_ns as f64 - fused_time_ns as f64) / separate_time_ns as f64 * 100.0);
+    Ok(())
+}
+
+/* Runs all multiplications of the batch in a single launch of batched.cl and verifies each against the CPU */
+fn run_batched_kernel(args: &cli::Args, ocl_env: &OclEnv, layout: &batch::BatchLayout, strategy: MemStrategy) -> GenResult<()> {
+    let (queue, tracer) = (&ocl_env.queue, &ocl_env.tracer);
+    let tile_size = args.tile_size;
+    let batch_size = layout.shapes.len() as u32;
+    println!("===\nRunning batched, {} multiplications of {}, memory strategy: {}", batch_size,
+             if layout.uniform { layout.shapes[0].to_string() } else { "varying shapes".to_owned() }, strategy);
+    if tile_size * tile_size > ocl_env.max_work_group_size {
+        println!("Local work size exceeds device limits; skipping this kernel.");
+        return Ok(());
+    }
+
+    let (a_host, b_host) = (batch::generate_matrix(layout.lens[0], 1), batch::generate_matrix(layout.lens[1], 2));
+    let upload_start = Instant::now();
+    let buffer_a = MatrixBuffer::with_data(queue, strategy, &a_host, Access::ReadOnly, tracer, "A (batch)")?;
+    let buffer_b = MatrixBuffer::with_data(queue, strategy, &b_host, Access::ReadOnly, tracer, "B (batch)")?;
+    let buffer_c = MatrixBuffer::new(queue, strategy, layout.lens[2], Access::WriteOnly)?;
+    /* Per-batch dimensions are only read by the kernel when the shapes differ */
+    let (shapes, offsets) = layout.kernel_tables();
+    let buffer_shapes = Buffer::<u32>::builder().queue(queue.clone()).flags(ocl::flags::MemFlags::new().read_only()).copy_host_slice(&shapes).build()?;
+    let buffer_offsets = Buffer::<u32>::builder().queue(queue.clone()).flags(ocl::flags::MemFlags::new().read_only()).copy_host_slice(&offsets).build()?;
+    println!("Input upload took {:.3} [ms]", memory::duration_ms(upload_start.elapsed()));
+
+    let verify_start = tracer.now();
+    let c_expected = layout.shapes.iter().zip(layout.offsets.iter()).flat_map(|(&shape, offset)| {
+        let (a_offset, b_offset) = (offset[0] as usize, offset[1] as usize);
+        batch::cpu_gemm(&a_host[a_offset..a_offset + (shape.m * shape.n) as usize], &b_host[b_offset..b_offset + (shape.n * shape.p) as usize], shape)
+    }).collect::<Vec<_>>();
+    tracer.host_span("CPU reference (batch)", "host", verify_start);
+
+    let max_shape = layout.max_shape();
+    let global_size = [ceil_divisible_by(max_shape.m, tile_size), ceil_divisible_by(max_shape.p, tile_size), batch_size];
+    let local_size = [tile_size, tile_size, 1];
+    println!("Global work size: {} x {} x {}, local work size: {} x {} x 1", global_size[0], global_size[1], global_size[2], tile_size, tile_size);
+
+    for variant in args.build_config.variants_for("batched") {
+        println!("---\nBuild with {}", variant);
+        let kernel_defs = format!("#define TILE_SIZE {}\n{}{}", tile_size, if layout.uniform { "#define BATCH_UNIFORM\n" } else { "" }, variant.source_defines());
+        let program = match build_ocl_program(ocl_env, kernel_defs, &variant.options, "batched.cl") {
+            Ok(program) => program,
+            Err(err) => { println!("Build failed, skipping this variant:\n{}", err); continue; }
+        };
+        dump_program_binaries(args, &program, &format!("batched_{}", strategy));
+
+        let mut kernel_builder = Kernel::builder();
+        kernel_builder
+            .queue(queue.clone())
+            .program(&program).name("batched")
+            .arg(&buffer_a.buffer).arg(&buffer_b.buffer).arg(&buffer_c.buffer);
+        if layout.uniform {
+            kernel_builder.arg(layout.shapes[0].m).arg(layout.shapes[0].n).arg(layout.shapes[0].p);
+        }
+        else {
+            kernel_builder.arg(&buffer_shapes).arg(&buffer_offsets);
+        }
+        let kernel = kernel_builder.build()?;
+        let run_label = format!("batched with {}", variant);
+        if !ocl_env.watchdog.queue_available(&run_label)? {
+            continue;
+        }
+
+        let run_start = tracer.now();
+        buffer_c.write(queue, &vec![0.0f32; layout.lens[2]], tracer, "C (reset)")?;
+        let mut exec_event = Event::empty();
+        unsafe {
+            kernel.cmd()
+                .queue(queue)
+                .global_work_size(global_size)
+                .local_work_size(local_size)
+                .enew(&mut exec_event)
+                .enq()?;
+        }
+        if !ocl_env.watchdog.wait(queue, &exec_event, &run_label, layout.flops())? {
+            record_timeout(args, ocl_env, "batched", &run_label, Some(strategy), run_start);
+            continue;
+        }
+        tracer.command(&format!("batched x{}", batch_size), "kernel", &exec_event)?;
+
+        let mut c_actual = vec![0.0f32; layout.lens[2]];
+        buffer_c.read(queue, &mut c_actual, tracer, "C (batch)")?;
+
+        let mut failed_entries = 0;
+        for (index, (shape, offset)) in layout.shapes.iter().zip(layout.offsets.iter()).enumerate() {
+            let range = offset[2] as usize..(offset[2] + shape.m * shape.p) as usize;
+            let errors = c_expected[range.clone()].iter().zip(c_actual[range].iter())
+                .filter(|&(expected, actual)| (expected - actual).abs() > ERROR_TOLERANCE).count();
+            if errors > 0 {
+                failed_entries += 1;
+                if failed_entries <= MAX_PRINT_ERRORS {
+                    println!("Batch entry {} ({}): {} wrong elements", index, shape, errors);
+                }
+            }
+        }
+        if failed_entries == 0 {
+            println!("Result verified, no errors found in {} multiplications", batch_size);
+        }
+        else {
+            println!("{} of {} multiplications have errors", failed_entries, batch_size);
+        }
+
+        let total_time_ns = get_execution_time_ns(&exec_event)?;
+        println!("Execution time is {} [ms], {:.3} [us] per multiplication", total_time_ns as f64 / 1_000_000.0,
+                 total_time_ns as f64 / 1000.0 / batch_size as f64);
+        let exec_gflops = layout.flops() as f64 / total_time_ns as f64;
+        println!("Measured perf: {:.3} [GFLOPS], efficiency: {:.1}%, {:.0} multiplications/s", exec_gflops,
+                 exec_gflops / args.device_max_gflops * 100.0, batch_size as f64 / (total_time_ns as f64 / 1_000_000_000.0));
+    }
+    Ok(())
+}
+
+/* Quantizes A and B per tensor and runs every kernel of int8.cl on them */
+fn run_int8_kernels(args: &cli::Args, ocl_env: &OclEnv, matrices: &HostMatrices, best_fp32_ns: Option<u64>) -> GenResult<()> {
+    let (device, queue, tracer) = (&ocl_env.device, &ocl_env.queue, &ocl_env.tracer);
+    let (tile_size, m, n, p) = (args.tile_size, args.m, args.n, args.p);
+    println!("===\nRunning int8.cl");
+
+    let (a_params, b_params) = (quant::QuantParams::for_tensor(&matrices.a), quant::QuantParams::for_tensor(&matrices.b));
+    let (a_quantized, b_quantized) = (quant::quantize(&matrices.a, a_params), quant::quantize(&matrices.b, b_params));
+    for &(name, params, original, quantized) in [("A", a_params, &matrices.a, &a_quantized), ("B", b_params, &matrices.b, &b_quantized)].iter() {
+        let max_error = quant::dequantize(quantized, params).iter().zip(original.iter())
+            .map(|(dequantized, original)| (dequantized - original).abs()).fold(0.0f32, f32::max);
+        println!("{}: {}, max quantization error {:.6}", name, params, max_error);
+    }
+    let c_expected = quant::gemm_reference(&a_quantized, a_params.zero_point, &b_quantized, b_params.zero_point, m, n, p);
+
+    let integer_dot = quant::has_integer_dot_product(device)?;
+    println!("Dot products: {}", if integer_dot { "cl_khr_integer_dot_product" } else { "emulated" });
+    let kernel_defs = format!("#define TILE_SIZE {}\n{}", tile_size, if integer_dot { "#define INT_DOT_KHR\n" } else { "" });
+    let program = build_ocl_program(ocl_env, kernel_defs, "", "int8.cl")?;
+    dump_program_binaries(args, &program, "int8");
+
+    let upload_start = tracer.now();
+    let buffer_a = Buffer::<u32>::builder().queue(queue.clone()).flags(ocl::flags::MemFlags::new().read_only())
+        .copy_host_slice(&quant::pack_rows(&a_quantized, m, n)).build()?;
+    let buffer_bt = Buffer::<u32>::builder().queue(queue.clone()).flags(ocl::flags::MemFlags::new().read_only())
+        .copy_host_slice(&quant::pack_rows(&quant::transpose(&b_quantized, n, p), p, n)).build()?;
+    let buffer_c = Buffer::<i32>::builder().queue(queue.clone()).flags(ocl::flags::MemFlags::new().read_write()).len((m * p) as usize).build()?;
+    tracer.host_span("upload int8 inputs", "transfer", upload_start);
+
+    let global_size = [ceil_divisible_by(m, tile_size), ceil_divisible_by(p, tile_size)];
+    let local_size = [tile_size, tile_size];
+    for &kernel_name in ["int8_naive", "int8_tiled"].iter() {
+        println!("---\nRunning {}", kernel_name);
+        if !ocl_env.watchdog.queue_available(kernel_name)? {
+            continue;
+        }
+        let kernel = Kernel::builder()
+            .queue(queue.clone())
+            .program(&program).name(kernel_name)
+            .arg(&buffer_a).arg(&buffer_bt).arg(&buffer_c).arg(m).arg(n).arg(p)
+            .arg(a_params.zero_point).arg(b_params.zero_point)
+            .build()?;
+
+        let run_start = tracer.now();
+        /* As with the float kernels, stale results must not pass verification */
+        buffer_c.cmd().queue(queue).offset(0).write(&vec![0i32; (m * p) as usize]).enq()?;
+        let mut exec_event = Event::empty();
+        unsafe {
+            kernel.cmd()
+                .queue(queue)
+                .global_work_size(global_size)
+                .local_work_size(local_size)
+                .enew(&mut exec_event)
+                .enq()?;
+        }
+        if !ocl_env.watchdog.wait(queue, &exec_event, kernel_name, 2 * (n as u64) * (m as u64) * (p as u64))? {
+            record_timeout(args, ocl_env, kernel_name, kernel_name, None, run_start);
+            continue;
+        }
+        tracer.command(kernel_name, "kernel", &exec_event)?;
+
+        let mut c_actual = vec![0i32; (m * p) as usize];
+        buffer_c.cmd().queue(queue).offset(0).read(&mut c_actual).enq()?;
+        quant::verify_exact(&c_expected, &c_actual, p, MAX_PRINT_ERRORS);
+        if args.epilogue.is_none() {
+            let max_error = quant::dequantize_accumulators(&c_actual, a_params, b_params).iter().zip(matrices.c_expected.iter())
+                .map(|(dequantized, expected)| (dequantized - expected).abs()).fold(0.0f32, f32::max);
+            println!("Max error of the dequantized result against the float reference: {:.6}", max_error);
+        }
+
+        let total_time_ns = get_execution_time_ns(&exec_event)?;
+        println!("Execution time is {} [ms]", total_time_ns as f64 / 1_000_000.0);
+        let total_ops = 2 * (n as u64) * (m as u64) * (p as u64);
+        println!("Measured perf: {:.3} [TOPS] ({:.3} [GOPS])", total_ops as f64 / total_time_ns as f64 / 1000.0, total_ops as f64 / total_time_ns as f64);
+        if let Some(best_fp32_ns) = best_fp32_ns {
+            println!("Speedup over the fastest float kernel: {:.2}x", best_fp32_ns as f64 / total_time_ns as f64);
+        }
+    }
+    Ok(())
+}
+
+/* For runs outside execute_gemm: the same timeout result line, and a trace span of the attempt marked as timed out */
+fn record_timeout(args: &cli::Args, ocl_env: &OclEnv, kernel_name: &str, run_label: &str, strategy: Option<MemStrategy>, run_start: i64) {
+    let mut fields = vec![("kernel", json::string(kernel_name)), ("label", json::string(run_label))];
+    if let Some(strategy) = strategy {
+        fields.push(("strategy", json::string(&strategy.to_string())));
+    }
+    remote::print_result(args, "timeout", &fields);
+    ocl_env.tracer.host_span_with_args(run_label, "run", run_start, vec![("status", json::string("timed out"))]);
+}
+
+fn run_pad_cols_kernel(ocl_env: &OclEnv, buffer_a: &MatrixBuffer, m: u32, n: u32, tile_size: u32, strategy: MemStrategy) -> GenResult<(MatrixBuffer, u64)> {
+    println!("===\nRunning pad_cols.cl");
+    let (queue, tracer) = (&ocl_env.queue, &ocl_env.tracer);
+    let (m_wide, n_wide) = (ceil_divisible_by(m, tile_size), ceil_divisible_by(n, tile_size));
+    let buffer_a_wide = MatrixBuffer::new(queue, strategy, (m * n_wide) as usize, Access::ReadWrite)?;
+    let program = build_ocl_program(ocl_env, format!("#define TILE_SIZE {}", tile_size), "", "pad_cols.cl")?;
+
+    let max_local_size = (ocl_env.max_work_group_size as f32).sqrt() as u32;
+
+    let kernel = Kernel::builder()
+        .queue(queue.clone())
+        .program(&program).name("pad_cols")
+        .arg(&buffer_a.buffer).arg(&buffer_a_wide.buffer).arg(m).arg(n)
+        .build()?;
+
+    let mut exec_event = Event::empty();
+
+    unsafe {
+        kernel.cmd()
+            .queue(queue)
+            .global_work_size([m_wide, n_wide])
+            .local_work_size([cmp::min(max_local_size, gcd(m_wide, tile_size)),
+                              cmp::min(max_local_size, gcd(n_wide, tile_size))])
+            .enew(&mut exec_event)
+            .enq()?;
+    }
+
+    ocl_env.watchdog.wait_or_fail(queue, &exec_event, "pad_cols", (m * n_wide) as u64)?;
+    tracer.command(&format!("pad_cols {}x{} -> {}x{}", m, n, m, n_wide), "kernel", &exec_event)?;
+    let total_exec_time = get_execution_time_ns(&exec_event)?;
+    println!("Execution time is {} [ms]",total_exec_time as f64 / 1000000.0);
+
+    Ok((buffer_a_wide, total_exec_time))
+}
+
+fn ceil_divisible_by(n: u32, by: u32) -> u32 {
+    (n + by - 1) / by * by
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    let (mut a, mut b, mut rem) = (a, b, 0);
+    while b > 0 {
+        rem = a % b;
+        a = b;
+        b = rem;
+    }
+    a
+}
+
+fn get_execution_time_ns(event: &Event) -> GenResult<u64> {
+    use ocl::enums::{ProfilingInfo, ProfilingInfoResult::{Queued, End}};
+
+    if let (Queued(time_queued), End(time_end)) =
+        (event.profiling_info(ProfilingInfo::Queued)?, event.profiling_info(ProfilingInfo::End)?) {
+        Ok(time_end - time_queued)
+    }
+    else {
+        gen_error_format!("Unable to obtain kernel profiling info")
+    }
+}
+
+fn verify_results(matrix_c_expected: &Vec<f32>, matrix_c_actual: &Vec<f32>, cols: u32) -> u32 {
+    let mut errors_encountered = 0;
+    let matrix_iter = matrix_c_expected.iter().zip(matrix_c_actual.iter());
+
+    for (i, (expected, actual)) in matrix_iter.enumerate() {
+        /* TODO: implement a proper comparison (see https://randomascii.wordpress.com/2012/02/25/comparing-floating-point-numbers-2012-edition) */
+        if (expected - actual).abs() > ERROR_TOLERANCE {
+            errors_encountered += 1;
+            if errors_encountered < MAX_PRINT_ERRORS {
+                println!("Row {}, col {}: expected {:.8}, got {:.8}", i as u32 / cols, i as u32 % cols, expected, actual);
+            }
+        }
+    }
+
+    if errors_encountered > MAX_PRINT_ERRORS {
+        println!("...\n({} errors omitted)", errors_encountered - MAX_PRINT_ERRORS);
+    }
+    else if errors_encountered == 0 {
+        println!("Result verified, no errors found")
+    }
+    errors_encountered
+}
+
+fn read_matrices(files: &cli::MatrixFiles, m: u32, n: u32, p: u32, epilogue: Option<&Epilogue>) -> GenResult<HostMatrices> {
+    let mut matrices = HostMatrices {
+        a: matrix_io::read_matrix(&files.a, m, n)?,
+        b: matrix_io::read_matrix(&files.b, n, p)?,
+        c_expected: matrix_io::read_matrix(&files.c, m, p)?,
+        bias: Vec::new()
+    };
+    if let Some(epilogue) = epilogue {
+        matrices.bias = match files.bias {
+            Some(ref file) => matrix_io::read_matrix(file, 1, p)?,
+            None => epilogue::default_bias(p)
+        };
+        epilogue.apply(&mut matrices.c_expected, &matrices.bias);
+        println!("Epilogue: {}", epilogue);
+    }
+    Ok(matrices)
+}
+
+pub fn open_file(filename: &str) -> GenResult<File> {
+    File::open(filename).or(gen_error_format!("Unable to open {} for reading", filename))
+}
+
+fn init_ocl(platform_name: String) -> GenResult<(Device, Context, Queue, Environment)> {
+    use ocl::flags::CommandQueueProperties as QueueProp;
+
+    let platforms = Platform::list();
+    let platform = platforms.iter()
+        .find(|&&p| p.name().map(|s| s == platform_name).unwrap_or(false))
+        .ok_or("The requested platform could not be found")?;
+        
+    let device = Device::first(platform)?;
+    let context = Context::builder().platform(*platform).devices(device.clone()).build()?;
+    let queue = Queue::new(&context, device, Some(QueueProp::new().profiling()))?;
+    let environment = Environment::collect(platform, &device);
+
+    Ok((device, context, queue, environment))
+}
+
+fn build_ocl_program(ocl_env: &OclEnv, kernel_defs: String, build_opts: &str, src_filename: &str) -> GenResult<Program> {
+    let tracer = &ocl_env.tracer;
+    let source = preprocess::Source::load(&ocl_env.kernel_dir, src_filename, &kernel_defs)?;
+    ocl_env.environment.record_program(src_filename, build_opts, source.text.as_bytes());
+
+    /* Builds run synchronously on the host, so they only show up as host spans */
+    let build_start = tracer.now();
+    let program = Program::builder().devices(ocl_env.device.clone()).src(source.text.clone()).cmplr_opt(build_opts).build(&ocl_env.context)
+        .map_err(|err| GenError::from(source.map_log(&err.to_string())));
+    tracer.host_span_with_args(&format!("build {}", src_filename), "build", build_start,
+                               vec![("options", json::string(build_opts)), ("succeeded", program.is_ok().to_string())]);
+    program
+}
+
+/* With --dump-binaries, writes what the driver generated; a failure here never stops the benchmark */
+fn dump_program_binaries(args: &cli::Args, program: &Program, stem: &str) {
+    if let Some(ref dir) = args.dump_binaries {
+        if let Err(err) = binary_dump::dump_program(program, dir, stem) {
+            println!("Unable to dump the program binary: {}", err);
+        }
+    }
+}
+
+fn build_ocl_binary_program(ocl_env: &OclEnv, binary_kernel: &binary_kernels::BinaryKernel) -> GenResult<Program> {
+    let tracer = &ocl_env.tracer;
+    let binary = binary_kernel.read_binary()?;
+    ocl_env.environment.record_program(&binary_kernel.binary_path, &binary_kernel.options, &binary);
+
+    let build_start = tracer.now();
+    let program = with_gen_error!(Program::builder().devices(ocl_env.device.clone()).binaries(&[&binary]).cmplr_opt(binary_kernel.options.as_str())
+        .build(&ocl_env.context));
+    tracer.host_span_with_args(&format!("load {}", binary_kernel.binary_path), "build", build_start,
+                               vec![("options", json::string(&binary_kernel.options)), ("succeeded", program.is_ok().to_string())]);
+    program
+}
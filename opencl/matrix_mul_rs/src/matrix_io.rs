@@ -0,0 +1,290 @@
+use std::{fs::File, path::Path, io::BufReader, io::BufWriter, io::prelude::*};
+use gen_error::{GenResult, GenError};
+use open_file;
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+const MTX_MAGIC: &[u8] = b"%%MatrixMarket";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatrixFormat {
+    /* One value per line, row-major, as written by mkmatrices */
+    Text,
+    Npy,
+    MatrixMarket
+}
+
+impl MatrixFormat {
+    pub fn from_extension(filename: &str) -> MatrixFormat {
+        match Path::new(filename).extension().and_then(|e| e.to_str()) {
+            Some("npy") => MatrixFormat::Npy,
+            Some("mtx") => MatrixFormat::MatrixMarket,
+            _ => MatrixFormat::Text
+        }
+    }
+
+    /* Magic bytes take precedence over the extension */
+    pub fn detect(filename: &str) -> GenResult<MatrixFormat> {
+        let mut magic = Vec::new();
+        open_file(filename)?.take(MTX_MAGIC.len() as u64).read_to_end(&mut magic)?;
+        Ok(if magic.starts_with(NPY_MAGIC) { MatrixFormat::Npy }
+           else if magic.starts_with(MTX_MAGIC) { MatrixFormat::MatrixMarket }
+           else { MatrixFormat::from_extension(filename) })
+    }
+}
+
+/* Nonzero entries of a Matrix Market file (0-based indices), with symmetry already expanded */
+pub struct MtxEntries {
+    pub rows: u32,
+    pub cols: u32,
+    pub entries: Vec<(u32, u32, f32)>
+}
+
+impl MtxEntries {
+    pub fn densify(&self) -> Vec<f32> {
+        let mut dense = vec![0.0f32; self.rows as usize * self.cols as usize];
+        for &(row, col, value) in self.entries.iter() {
+            dense[row as usize * self.cols as usize + col as usize] = value;
+        }
+        dense
+    }
+}
+
+/* Picks the first existing file among stem, stem.npy and stem.mtx (falling back to stem) */
+pub fn default_matrix_file(stem: &str) -> String {
+    [stem.to_owned(), format!("{}.npy", stem), format!("{}.mtx", stem)].iter()
+        .find(|f| Path::new(f).is_file())
+        .cloned()
+        .unwrap_or(stem.to_owned())
+}
+
+/* "c.npy" -> "c_tiled_3.npy" */
+pub fn numbered_filename(path: &str, kernel_name: &str, run_index: u32) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    let filename = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}_{}_{}.{}", stem, kernel_name, run_index, ext),
+        None => format!("{}_{}_{}", stem, kernel_name, run_index)
+    };
+    path.with_file_name(filename).to_string_lossy().into_owned()
+}
+
+/* Reads a rows x cols matrix in row-major order. One-dimensional .npy arrays and text files are accepted
+ * as long as they hold rows * cols elements. */
+pub fn read_matrix(filename: &str, rows: u32, cols: u32) -> GenResult<Vec<f32>> {
+    let (shape, data) = match MatrixFormat::detect(filename)? {
+        MatrixFormat::Text => (None, read_text(filename)?),
+        MatrixFormat::Npy => {
+            let (shape, data) = read_npy(filename)?;
+            (if shape.len() == 2 { Some((shape[0], shape[1])) } else { None }, data)
+        },
+        MatrixFormat::MatrixMarket => {
+            let mtx = read_mtx(filename)?;
+            (Some((mtx.rows, mtx.cols)), mtx.densify())
+        }
+    };
+
+    match shape {
+        Some(shape) if shape != (rows, cols) =>
+            gen_error_format!("Matrix read from {} is {}x{}; {}x{} expected.", filename, shape.0, shape.1, rows, cols),
+        _ if data.len() != (rows * cols) as usize =>
+            gen_error_format!("Matrix read from {} has {} elements; {} expected.", filename, data.len(), rows * cols),
+        _ => Ok(data)
+    }
+}
+
+/* The format is chosen by the file extension: .npy, .mtx (dense array), text otherwise */
+/* Comment lines go into the header of text and Matrix Market files; .npy headers have no room for them */
+pub fn write_matrix(filename: &str, data: &[f32], rows: u32, cols: u32, comments: &[String]) -> GenResult<()> {
+    let mut out = BufWriter::new(File::create(filename).or(gen_error_format!("Unable to open {} for writing", filename))?);
+    match MatrixFormat::from_extension(filename) {
+        MatrixFormat::Text => {
+            for comment in comments.iter() { writeln!(out, "# {}", comment)?; }
+            for value in data.iter() { writeln!(out, "{:.8}", value)?; }
+        },
+        MatrixFormat::Npy => write_npy(&mut out, data, rows, cols)?,
+        MatrixFormat::MatrixMarket => {
+            writeln!(out, "%%MatrixMarket matrix array real general")?;
+            for comment in comments.iter() { writeln!(out, "% {}", comment)?; }
+            writeln!(out, "{} {}", rows, cols)?;
+            /* Array entries are listed in column-major order */
+            for col in 0..cols {
+                for row in 0..rows { writeln!(out, "{:e}", data[(row * cols + col) as usize])?; }
+            }
+        }
+    }
+    Ok(())
+}
+
+/* One value per line; lines starting with '#' (e.g. the header of a saved result) are skipped */
+fn read_text(filename: &str) -> GenResult<Vec<f32>> {
+    BufReader::new(open_file(filename)?)
+        .lines().into_iter()
+        .filter(|line| line.as_ref().map(|s| !s.starts_with('#')).unwrap_or(true))
+        .map(|line| { with_gen_error!(line).and_then(|s| with_gen_error!(s.parse())) })
+        .collect::<GenResult<Vec<f32>>>()
+}
+
+/* Supports f4 and f8 arrays in either byte order and in C or Fortran order; returns the data in C order */
+pub fn read_npy(filename: &str) -> GenResult<(Vec<u32>, Vec<f32>)> {
+    let mut bytes = Vec::new();
+    open_file(filename)?.read_to_end(&mut bytes)?;
+    if bytes.len() < 10 || !bytes.starts_with(NPY_MAGIC) {
+        return gen_error_format!("{} is not a .npy file", filename);
+    }
+
+    let major_version = bytes[6];
+    let (header_len, header_start) = if major_version == 1 {
+        ((bytes[8] as usize) | (bytes[9] as usize) << 8, 10)
+    }
+    else {
+        if bytes.len() < 12 { return gen_error_format!("{}: truncated .npy header", filename); }
+        (bytes[8..12].iter().rev().fold(0usize, |acc, &b| acc << 8 | b as usize), 12)
+    };
+    if bytes.len() < header_start + header_len {
+        return gen_error_format!("{}: truncated .npy header", filename);
+    }
+    let header = String::from_utf8_lossy(&bytes[header_start..header_start + header_len]).into_owned();
+    let data = &bytes[header_start + header_len..];
+
+    let descr = npy_header_value(&header, "descr")
+        .map(|v| v.trim_matches(|c| c == '\'' || c == '"').to_owned())
+        .ok_or(GenError::from(format!("{}: .npy header lacks 'descr'", filename)))?;
+    let fortran_order = npy_header_value(&header, "fortran_order").map(|v| v == "True").unwrap_or(false);
+    let shape = npy_header_value(&header, "shape")
+        .ok_or(GenError::from(format!("{}: .npy header lacks 'shape'", filename)))?
+        .trim_matches(|c| c == '(' || c == ')')
+        .split(',').map(|d| d.trim()).filter(|d| !d.is_empty())
+        .map(|d| d.trim_end_matches('L').parse::<u32>())
+        .collect::<Result<Vec<u32>, _>>()?;
+    if shape.len() > 2 {
+        return gen_error_format!("{}: {}-dimensional arrays are not supported", filename, shape.len());
+    }
+
+    let little_endian = !descr.starts_with('>');
+    let values = match &descr.trim_start_matches(|c| c == '<' || c == '>' || c == '|' || c == '=')[..] {
+        "f4" => data.chunks(4).filter(|c| c.len() == 4)
+            .map(|c| f32::from_bits(bytes_to_u64(c, little_endian) as u32)).collect::<Vec<_>>(),
+        "f8" => data.chunks(8).filter(|c| c.len() == 8)
+            .map(|c| f64::from_bits(bytes_to_u64(c, little_endian)) as f32).collect::<Vec<_>>(),
+        other => return gen_error_format!("{}: unsupported dtype '{}', expected f4 or f8", filename, other)
+    };
+    let expected_len = shape.iter().fold(1usize, |acc, &d| acc * d as usize);
+    if values.len() != expected_len {
+        return gen_error_format!("{}: expected {} elements for shape {:?}, found {}", filename, expected_len, shape, values.len());
+    }
+
+    if fortran_order && shape.len() == 2 {
+        let (rows, cols) = (shape[0] as usize, shape[1] as usize);
+        let mut c_order = vec![0.0f32; values.len()];
+        for col in 0..cols {
+            for row in 0..rows { c_order[row * cols + col] = values[col * rows + row]; }
+        }
+        return Ok((shape, c_order));
+    }
+    Ok((shape, values))
+}
+
+fn bytes_to_u64(bytes: &[u8], little_endian: bool) -> u64 {
+    if little_endian { bytes.iter().rev().fold(0u64, |acc, &b| acc << 8 | b as u64) }
+    else { bytes.iter().fold(0u64, |acc, &b| acc << 8 | b as u64) }
+}
+
+/* Extracts the raw text of a value from the header dict, e.g. "(3, 4)" for 'shape' */
+fn npy_header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let key_pos = header.find(&format!("'{}'", key)).or_else(|| header.find(&format!("\"{}\"", key)))?;
+    let rest = header[key_pos + key.len() + 2..].trim_start();
+    let rest = rest.trim_start_matches(':').trim_start();
+    let end = if rest.starts_with('(') { rest.find(')').map(|i| i + 1) } else { rest.find(',').or_else(|| rest.find('}')) }?;
+    Some(rest[..end].trim())
+}
+
+fn write_npy<W: Write>(out: &mut W, data: &[f32], rows: u32, cols: u32) -> GenResult<()> {
+    let mut header = format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({}, {}), }}", rows, cols);
+    /* The header is padded with spaces and terminated with a newline so that the data is 64-byte aligned */
+    let unpadded_len = NPY_MAGIC.len() + 4 + header.len() + 1;
+    header += &" ".repeat((64 - unpadded_len % 64) % 64);
+    header.push('\n');
+
+    out.write_all(NPY_MAGIC)?;
+    out.write_all(&[1, 0, (header.len() & 0xff) as u8, (header.len() >> 8) as u8])?;
+    out.write_all(header.as_bytes())?;
+    for value in data.iter() {
+        let bits = value.to_bits();
+        out.write_all(&[bits as u8, (bits >> 8) as u8, (bits >> 16) as u8, (bits >> 24) as u8])?;
+    }
+    Ok(())
+}
+
+/* Reads coordinate (real, integer or pattern) and array files; symmetric and skew-symmetric
+ * storage is expanded to the full matrix */
+pub fn read_mtx(filename: &str) -> GenResult<MtxEntries> {
+    let mut lines = BufReader::new(open_file(filename)?).lines();
+    let banner = lines.next().unwrap_or(Ok(String::new()))?;
+    let banner_fields = banner.split_whitespace().map(|f| f.to_lowercase()).collect::<Vec<_>>();
+    if banner_fields.len() != 5 || banner_fields[0] != "%%matrixmarket" || banner_fields[1] != "matrix" {
+        return gen_error_format!("{}: missing %%MatrixMarket matrix banner", filename);
+    }
+    let (storage, field, symmetry) = (&banner_fields[2][..], &banner_fields[3][..], &banner_fields[4][..]);
+    if field == "complex" {
+        return gen_error_format!("{}: complex matrices are not supported", filename);
+    }
+    if symmetry != "general" && symmetry != "symmetric" && symmetry != "skew-symmetric" {
+        return gen_error_format!("{}: unsupported symmetry '{}'", filename, symmetry);
+    }
+
+    let mut data_lines = lines.filter(|l| l.as_ref().map(|s| !s.trim().is_empty() && !s.starts_with('%')).unwrap_or(true));
+    let size_line = match data_lines.next() {
+        Some(line) => line?,
+        None => return gen_error_format!("{}: missing size line", filename)
+    };
+    let sizes = size_line.split_whitespace().map(|s| s.parse::<u32>()).collect::<Result<Vec<_>, _>>()?;
+    if sizes.len() < 2 {
+        return gen_error_format!("{}: malformed size line", filename);
+    }
+    let (rows, cols) = (sizes[0], sizes[1]);
+
+    let mut entries = Vec::new();
+    match storage {
+        "coordinate" => {
+            /* Errors may go to remote clients, so they point at entries rather than quote the file */
+            for (index, line) in data_lines.enumerate() {
+                let line = line?;
+                let fields = line.split_whitespace().collect::<Vec<_>>();
+                if fields.len() < 2 || (field != "pattern" && fields.len() < 3) {
+                    return gen_error_format!("{}: malformed entry {}", filename, index + 1);
+                }
+                let (row, col) = (fields[0].parse::<u32>()?, fields[1].parse::<u32>()?);
+                if row == 0 || col == 0 || row > rows || col > cols {
+                    return gen_error_format!("{}: entry ({}, {}) is outside of the {}x{} matrix", filename, row, col, rows, cols);
+                }
+                let value = if field == "pattern" { 1.0 } else { fields[2].parse::<f64>()? as f32 };
+                push_mtx_entry(&mut entries, symmetry, row - 1, col - 1, value);
+            }
+        },
+        "array" => {
+            /* Column-major; symmetric matrices only list the lower triangle */
+            let positions = (0..cols).flat_map(|col| {
+                let first_row = if symmetry == "general" { 0 } else if symmetry == "symmetric" { col } else { col + 1 };
+                (first_row..rows).map(move |row| (row, col))
+            });
+            let mut values = data_lines.map(|line| -> GenResult<f32> { Ok(line?.trim().parse::<f64>()? as f32) });
+            for (row, col) in positions {
+                let value = values.next().unwrap_or(gen_error_format!("{}: fewer values than the matrix size requires", filename))?;
+                push_mtx_entry(&mut entries, symmetry, row, col, value);
+            }
+        },
+        _ => return gen_error_format!("{}: unsupported storage '{}'", filename, storage)
+    }
+    Ok(MtxEntries { rows, cols, entries })
+}
+
+fn push_mtx_entry(entries: &mut Vec<(u32, u32, f32)>, symmetry: &str, row: u32, col: u32, value: f32) {
+    entries.push((row, col, value));
+    if row != col {
+        match symmetry {
+            "symmetric" => entries.push((col, row, value)),
+            "skew-symmetric" => entries.push((col, row, -value)),
+            _ => ()
+        }
+    }
+}
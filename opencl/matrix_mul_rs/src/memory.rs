@@ -0,0 +1,147 @@
+use std::{mem, fmt, fmt::{Display, Formatter}, time::Duration};
+use ocl::{flags, Buffer, Queue, Event, OclPrm};
+use gen_error::{GenResult, GenError};
+use trace::Tracer;
+use watchdog;
+
+/* How matrix buffers are allocated and how data gets in and out of them. Zero-copy strategies
+ * (use_host_ptr, map_unmap) tend to win on integrated GPUs, explicit copies on discrete cards. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemStrategy {
+    /* Driver-allocated host-visible memory, transfers via read/write commands */
+    AllocHostPtr,
+    /* Memory owned by the host program, accessed via map/unmap */
+    UseHostPtr,
+    /* No host access flags, transfers via read/write commands */
+    DeviceLocal,
+    /* Driver-allocated host-visible memory, accessed via map/unmap */
+    MapUnmap
+}
+
+const ALL_STRATEGIES: [MemStrategy; 4] = [MemStrategy::AllocHostPtr, MemStrategy::UseHostPtr, MemStrategy::DeviceLocal, MemStrategy::MapUnmap];
+
+impl MemStrategy {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            MemStrategy::AllocHostPtr => "alloc_host_ptr",
+            MemStrategy::UseHostPtr => "use_host_ptr",
+            MemStrategy::DeviceLocal => "device_local",
+            MemStrategy::MapUnmap => "map_unmap"
+        }
+    }
+
+    /* Accepts a comma-separated list of strategy names, or "all" */
+    pub fn parse_list(s: &str) -> GenResult<Vec<MemStrategy>> {
+        if s == "all" {
+            return Ok(ALL_STRATEGIES.to_vec());
+        }
+        s.split(',').map(|name| {
+            ALL_STRATEGIES.iter().find(|strategy| strategy.name() == name.trim()).cloned()
+                .ok_or(GenError::from(format!("Unknown memory strategy \"{}\"; expected one of alloc_host_ptr, use_host_ptr, device_local, map_unmap, all", name)))
+        }).collect()
+    }
+
+    fn uses_mapping(&self) -> bool {
+        *self == MemStrategy::UseHostPtr || *self == MemStrategy::MapUnmap
+    }
+}
+
+impl Display for MemStrategy {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access { ReadOnly, WriteOnly, ReadWrite }
+
+/* A device buffer together with the host memory backing it under use_host_ptr */
+pub struct MatrixBuffer<T: OclPrm = f32> {
+    pub buffer: Buffer<T>,
+    strategy: MemStrategy,
+    /* Must outlive `buffer` and any kernel using it; never touched directly while the buffer exists */
+    host_backing: Option<Vec<T>>
+}
+
+impl<T: OclPrm> MatrixBuffer<T> {
+    pub fn new(queue: &Queue, strategy: MemStrategy, len: usize, access: Access) -> GenResult<MatrixBuffer<T>> {
+        let mut mem_flags = match access {
+            Access::ReadOnly => flags::MemFlags::new().read_only(),
+            Access::WriteOnly => flags::MemFlags::new().write_only(),
+            Access::ReadWrite => flags::MemFlags::new().read_write()
+        };
+        if strategy == MemStrategy::AllocHostPtr || strategy == MemStrategy::MapUnmap {
+            mem_flags = mem_flags.alloc_host_ptr();
+        }
+
+        let builder = Buffer::<T>::builder().queue(queue.clone()).flags(mem_flags).len(len);
+        if strategy == MemStrategy::UseHostPtr {
+            let host_backing = vec![T::default(); len];
+            /* The vector's heap allocation doesn't move when the vector itself is moved into the struct */
+            let buffer = unsafe { builder.use_host_slice(&host_backing).build()? };
+            return Ok(MatrixBuffer { buffer, strategy, host_backing: Some(host_backing) });
+        }
+        Ok(MatrixBuffer { buffer: builder.build()?, strategy, host_backing: None })
+    }
+
+    pub fn with_data(queue: &Queue, strategy: MemStrategy, data: &[T], access: Access, tracer: &Tracer, name: &str) -> GenResult<MatrixBuffer<T>> {
+        let matrix_buffer = MatrixBuffer::new(queue, strategy, data.len(), access)?;
+        matrix_buffer.write(queue, data, tracer, name)?;
+        Ok(matrix_buffer)
+    }
+
+    /* Both transfers block until the data is in place; `name` labels the commands in the trace */
+    pub fn write(&self, queue: &Queue, data: &[T], tracer: &Tracer, name: &str) -> GenResult<()> {
+        let host_start = tracer.now();
+        if self.strategy.uses_mapping() {
+            let (mut map_event, mut unmap_event) = (Event::empty(), Event::empty());
+            let mut mapping = unsafe { self.buffer.map().queue(queue).write_invalidate().enew(&mut map_event).enq()? };
+            mapping.copy_from_slice(data);
+            mapping.unmap().queue(queue).enew(&mut unmap_event).enq()?;
+            queue.finish()?;
+            tracer.command(&format!("map {}", name), "transfer", &map_event)?;
+            tracer.command(&format!("unmap {}", name), "transfer", &unmap_event)?;
+        }
+        else {
+            let mut write_event = Event::empty();
+            self.buffer.cmd().queue(queue).offset(0).write(data).enew(&mut write_event).enq()?;
+            tracer.command(&format!("write {}", name), "transfer", &write_event)?;
+        }
+        tracer.host_span(&format!("upload {}", name), "transfer", host_start);
+        Ok(())
+    }
+
+    pub fn read(&self, queue: &Queue, out: &mut [T], tracer: &Tracer, name: &str) -> GenResult<()> {
+        let host_start = tracer.now();
+        if self.strategy.uses_mapping() {
+            let (mut map_event, mut unmap_event) = (Event::empty(), Event::empty());
+            let mut mapping = unsafe { self.buffer.map().queue(queue).read().enew(&mut map_event).enq()? };
+            out.copy_from_slice(&mapping);
+            mapping.unmap().queue(queue).enew(&mut unmap_event).enq()?;
+            queue.finish()?;
+            tracer.command(&format!("map {}", name), "transfer", &map_event)?;
+            tracer.command(&format!("unmap {}", name), "transfer", &unmap_event)?;
+        }
+        else {
+            let mut read_event = Event::empty();
+            self.buffer.cmd().queue(queue).offset(0).read(out).enew(&mut read_event).enq()?;
+            tracer.command(&format!("read {}", name), "transfer", &read_event)?;
+        }
+        tracer.host_span(&format!("download {}", name), "transfer", host_start);
+        Ok(())
+    }
+}
+
+impl<T: OclPrm> Drop for MatrixBuffer<T> {
+    /* OpenCL keeps the buffer itself until its commands finish, but not the host backing: while a kernel the
+     * watchdog abandoned may still be using it, the backing is leaked rather than freed under the kernel */
+    fn drop(&mut self) {
+        if self.host_backing.is_some() && watchdog::abandoned_kernel_running() {
+            mem::forget(self.host_backing.take());
+        }
+    }
+}
+
+pub fn duration_ms(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1_000_000.0
+}
@@ -0,0 +1,33 @@
+use ndarray::{Array2, ArrayView2};
+use gen_error::{GenResult, GenError};
+use gemm::{Gemm, DeviceMatrix};
+
+/* Gemm on ndarray matrices (the "ndarray" feature). Row-major views are uploaded as they are and column-major
+ * ones (e.g. transposed views) as transposed operands, which tiled.cl reads in place; only views with other
+ * strides (e.g. slices with a step) are packed into a row-major copy first. */
+impl Gemm {
+    pub fn upload_array(&self, matrix: ArrayView2<f32>) -> GenResult<DeviceMatrix> {
+        let (rows, cols) = (matrix.nrows() as u32, matrix.ncols() as u32);
+        if let Some(data) = matrix.as_slice() {
+            return self.upload(data, rows, cols);
+        }
+        if let Some(data) = matrix.t().as_slice() {
+            return self.upload_transposed(data, rows, cols);
+        }
+        /* Iteration is in logical row-major order whatever the strides */
+        self.upload(&matrix.iter().cloned().collect::<Vec<_>>(), rows, cols)
+    }
+
+    pub fn read_array(&self, matrix: &mut DeviceMatrix) -> GenResult<Array2<f32>> {
+        let data = self.read(matrix)?;
+        Array2::from_shape_vec((matrix.rows as usize, matrix.cols as usize), data).map_err(|err| GenError::from(err.to_string()))
+    }
+
+    pub fn multiply_arrays(&self, a: ArrayView2<f32>, b: ArrayView2<f32>) -> GenResult<Array2<f32>> {
+        if a.ncols() != b.nrows() {
+            return gen_error_format!("Cannot multiply {}x{} by {}x{}", a.nrows(), a.ncols(), b.nrows(), b.ncols());
+        }
+        let (a, b) = (self.upload_array(a)?, self.upload_array(b)?);
+        self.read_array(&mut self.multiply(&a, &b)?)
+    }
+}
@@ -0,0 +1,131 @@
+use std::{cmp, fmt, fmt::{Display, Formatter}};
+use ocl::{Device, Kernel};
+use ocl::enums::{DeviceInfo, DeviceInfoResult, KernelWorkGroupInfo, KernelWorkGroupInfoResult};
+use gen_error::{GenResult, GenError};
+
+/* Per-compute-unit limits OpenCL doesn't expose; rough figures for recent architectures of each vendor */
+struct VendorLimits {
+    architecture: &'static str,
+    max_waves: u32,
+    max_groups: u32,
+    /* None when unknown, in which case private memory is not considered a limit */
+    register_file_bytes: Option<u64>,
+    /* Intel reports EUs as compute units while work groups are scheduled on subslices of several EUs */
+    compute_units_per_scheduler: u32
+}
+
+const NVIDIA_LIMITS: VendorLimits = VendorLimits { architecture: "NVIDIA SM", max_waves: 48, max_groups: 16, register_file_bytes: Some(256 * 1024), compute_units_per_scheduler: 1 };
+const AMD_LIMITS: VendorLimits = VendorLimits { architecture: "AMD CU", max_waves: 40, max_groups: 16, register_file_bytes: Some(256 * 1024), compute_units_per_scheduler: 1 };
+const INTEL_LIMITS: VendorLimits = VendorLimits { architecture: "Intel subslice of 8 EUs", max_waves: 56, max_groups: 16, register_file_bytes: Some(56 * 4096), compute_units_per_scheduler: 8 };
+const GENERIC_LIMITS: VendorLimits = VendorLimits { architecture: "unknown", max_waves: 32, max_groups: 16, register_file_bytes: None, compute_units_per_scheduler: 1 };
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limiter { LocalMemory, PrivateMemory, Waves, WorkGroupSlots }
+
+impl Display for Limiter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", match *self {
+            Limiter::LocalMemory => "local memory",
+            Limiter::PrivateMemory => "private memory",
+            Limiter::Waves => "wave slots",
+            Limiter::WorkGroupSlots => "work-group slots"
+        })
+    }
+}
+
+/* Static occupancy estimate of a built kernel for a given NDRange */
+pub struct Occupancy {
+    architecture: &'static str,
+    kernel_local_mem: u64,
+    device_local_mem: u64,
+    private_mem_per_item: u64,
+    wave_size: u32,
+    waves_per_group: u32,
+    /* Fraction of lanes in the group's waves that have a work item */
+    lane_utilization: f64,
+    pub resident_groups: u32,
+    pub resident_waves: u32,
+    max_waves: u32,
+    pub limiter: Limiter,
+    /* How many rounds of resident groups the whole NDRange takes */
+    pub dispatch_rounds: u32
+}
+
+impl Occupancy {
+    pub fn estimate(kernel: &Kernel, device: &Device, global_size: [u32; 2], local_size: [u32; 2]) -> GenResult<Occupancy> {
+        let kernel_local_mem = match kernel.wg_info(*device, KernelWorkGroupInfo::LocalMemSize)? {
+            KernelWorkGroupInfoResult::LocalMemSize(size) => size,
+            _ => return gen_error_format!("Unable to query the kernel's local memory size")
+        };
+        let private_mem_per_item = match kernel.wg_info(*device, KernelWorkGroupInfo::PrivateMemSize)? {
+            KernelWorkGroupInfoResult::PrivateMemSize(size) => size,
+            _ => return gen_error_format!("Unable to query the kernel's private memory size")
+        };
+        let wave_size = match kernel.wg_info(*device, KernelWorkGroupInfo::PreferredWorkGroupSizeMultiple)? {
+            KernelWorkGroupInfoResult::PreferredWorkGroupSizeMultiple(multiple) => cmp::max(1, multiple as u32),
+            _ => return gen_error_format!("Unable to query the kernel's preferred work group size multiple")
+        };
+        let device_local_mem = match device.info(DeviceInfo::LocalMemSize)? {
+            DeviceInfoResult::LocalMemSize(size) => size,
+            _ => return gen_error_format!("Unable to query the device's local memory size")
+        };
+        let compute_units = match device.info(DeviceInfo::MaxComputeUnits)? {
+            DeviceInfoResult::MaxComputeUnits(units) => units,
+            _ => return gen_error_format!("Unable to query the device's compute units")
+        };
+        let vendor = match device.info(DeviceInfo::Vendor)? {
+            DeviceInfoResult::Vendor(vendor) => vendor.to_lowercase(),
+            _ => String::new()
+        };
+        let limits = if vendor.contains("nvidia") { &NVIDIA_LIMITS }
+                     else if vendor.contains("advanced micro devices") || vendor.contains("amd") { &AMD_LIMITS }
+                     else if vendor.contains("intel") { &INTEL_LIMITS }
+                     else { &GENERIC_LIMITS };
+
+        let group_items = local_size[0] * local_size[1];
+        let waves_per_group = (group_items + wave_size - 1) / wave_size;
+
+        /* The smallest of the per-resource limits is the number of groups that fit on a compute unit */
+        let mut candidates = vec![(limits.max_groups, Limiter::WorkGroupSlots),
+                                  (limits.max_waves / waves_per_group, Limiter::Waves)];
+        if kernel_local_mem > 0 {
+            candidates.push(((device_local_mem / kernel_local_mem) as u32, Limiter::LocalMemory));
+        }
+        if let Some(register_file_bytes) = limits.register_file_bytes.filter(|_| private_mem_per_item > 0) {
+            candidates.push(((register_file_bytes / (private_mem_per_item * group_items as u64)) as u32, Limiter::PrivateMemory));
+        }
+        let &(resident_groups, limiter) = candidates.iter().min_by_key(|&&(groups, _)| groups).unwrap();
+
+        let schedulers = cmp::max(1, compute_units / limits.compute_units_per_scheduler);
+        let total_groups = (global_size[0] / local_size[0]) * (global_size[1] / local_size[1]);
+        let device_groups = cmp::max(1, resident_groups * schedulers);
+
+        Ok(Occupancy {
+            architecture: limits.architecture,
+            kernel_local_mem,
+            device_local_mem,
+            private_mem_per_item,
+            wave_size,
+            waves_per_group,
+            lane_utilization: group_items as f64 / (waves_per_group * wave_size) as f64,
+            resident_groups,
+            resident_waves: resident_groups * waves_per_group,
+            max_waves: limits.max_waves,
+            limiter,
+            dispatch_rounds: (total_groups + device_groups - 1) / device_groups
+        })
+    }
+
+    pub fn print(&self) {
+        println!("Resources: {} B local memory per group (device has {} B), {} B private memory per work item, {} waves of {} per group{}",
+                 self.kernel_local_mem, self.device_local_mem, self.private_mem_per_item, self.waves_per_group, self.wave_size,
+                 if self.lane_utilization < 1.0 { format!(" ({:.0}% of lanes used)", self.lane_utilization * 100.0) } else { String::new() });
+        if self.resident_groups == 0 {
+            println!("Occupancy: a single work group exceeds the {} of a compute unit", self.limiter);
+            return;
+        }
+        println!("Occupancy: {} resident groups / {} of {} waves per compute unit ({:.0}%), limited by {}; {} dispatch rounds (assuming {} limits)",
+                 self.resident_groups, self.resident_waves, self.max_waves, self.resident_waves as f64 / self.max_waves as f64 * 100.0,
+                 self.limiter, self.dispatch_rounds, self.architecture);
+    }
+}
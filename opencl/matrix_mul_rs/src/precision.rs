@@ -0,0 +1,189 @@
+use std::{fs::File, io::prelude::*, io::BufWriter, time::Instant};
+use ocl::{Device, enums::{DeviceInfo, DeviceInfoResult}};
+use gen_error::{GenResult, GenError};
+use memory::{self, MemStrategy, MatrixBuffer, Access};
+use accuracy::{self, ErrorStats};
+use epilogue::Epilogue;
+use workload::{self, Peaks};
+use {cli, coverage, batch, OclEnv, build_ocl_program, build_gemm_kernel, get_execution_time_ns, ceil_divisible_by};
+
+/* Accumulation variants of tiled.cl and the defines selecting them */
+const VARIANTS: [(&str, &str); 3] = [("float", ""), ("kahan", "#define ACCUMULATE_KAHAN\n"), ("double", "#define ACCUMULATE_DOUBLE\n")];
+
+/* Per variant, in the order of VARIANTS; None if the variant didn't run */
+struct StudyPoint {
+    n: u32,
+    results: Vec<Option<(ErrorStats, u64)>>
+}
+
+/* Error of each accumulation variant against the f64 CPU reference as n grows, with m and p fixed.
+ * Inputs are uniform in [0, 1), so that sums grow with n and rounding errors don't cancel out.
+ * Built without compiler options: -cl-fast-relaxed-math would be free to optimize the Kahan compensation away. */
+pub fn run_precision_study(args: &cli::Args, ocl_env: &OclEnv, strategy: MemStrategy, peaks: &Peaks) -> GenResult<()> {
+    let (queue, tracer, tile_size) = (&ocl_env.queue, &ocl_env.tracer, args.tile_size);
+    let (m, p) = (args.m, args.p);
+    println!("===\nRunning the precision study on {}x{} results, n = {:?}, memory strategy: {}", m, p, args.precision_sizes, strategy);
+    if tile_size * tile_size > ocl_env.max_work_group_size {
+        println!("Local work size exceeds device limits; skipping the precision study.");
+        return Ok(());
+    }
+
+    let fp64 = has_fp64(&ocl_env.device)?;
+    if !fp64 {
+        println!("The device has no cl_khr_fp64; skipping double accumulation");
+    }
+    let programs = VARIANTS.iter().map(|&(name, defines)| {
+        if name == "double" && !fp64 {
+            return Ok(None);
+        }
+        let kernel_defs = format!("#define TILE_SIZE {}\n{}{}{}", tile_size, defines, coverage::source_defines(false), Epilogue::source_defines(None));
+        build_ocl_program(ocl_env, kernel_defs, "", "tiled.cl").map(Some)
+    }).collect::<GenResult<Vec<_>>>()?;
+
+    let mut points = Vec::new();
+    for &n in args.precision_sizes.iter() {
+        println!("---\nn = {}", n);
+        let uniform = |len: u32, seed: u32| batch::generate_matrix(len as usize, seed).iter().map(|v| (v + 1.0) / 2.0).collect::<Vec<f32>>();
+        let (a, b) = (uniform(m * n, 21), uniform(n * p, 22));
+        let reference_start = Instant::now();
+        let expected = accuracy::cpu_gemm_f64(&a, &b, m, n, p);
+        println!("f64 CPU reference took {:.3} [ms]", memory::duration_ms(reference_start.elapsed()));
+
+        let buffer_a = MatrixBuffer::with_data(queue, strategy, &a, Access::ReadOnly, tracer, "A")?;
+        let buffer_b = MatrixBuffer::with_data(queue, strategy, &b, Access::ReadOnly, tracer, "B")?;
+        let buffer_c = MatrixBuffer::new(queue, strategy, (m * p) as usize, Access::WriteOnly)?;
+        let flops = (2 * (n as u64) - 1) * (m as u64) * (p as u64);
+        let mut results = Vec::new();
+        for (&(name, _), program) in VARIANTS.iter().zip(programs.iter()) {
+            let program = match *program {
+                Some(ref program) => program,
+                None => { results.push(None); continue; }
+            };
+            let kernel = build_gemm_kernel(queue, program, "tiled", [&buffer_a.buffer, &buffer_b.buffer, &buffer_c.buffer], [m, n, p], None, None)?;
+            let global_size = [ceil_divisible_by(m, tile_size), ceil_divisible_by(p, tile_size)];
+            /* Kahan summation takes about twice the operations per product */
+            let event = workload::enqueue_timed_ops(ocl_env, &kernel, &format!("tiled ({} accumulation)", name), global_size, [tile_size, tile_size], 2 * flops)?;
+            let time_ns = get_execution_time_ns(&event)?;
+
+            let mut actual = vec![0.0f32; (m * p) as usize];
+            buffer_c.read(queue, &mut actual, tracer, "C")?;
+            let errors = ErrorStats::measure(&expected, &actual);
+            println!("{:>6}: {}; {:.3} [ms], {:.3} [GFLOPS], efficiency {:.1}%", name, errors, time_ns as f64 / 1_000_000.0,
+                     flops as f64 / time_ns as f64, flops as f64 / time_ns as f64 / peaks.gflops * 100.0);
+            results.push(Some((errors, time_ns)));
+        }
+        points.push(StudyPoint { n, results });
+    }
+
+    print_summary(&points);
+    if let Some(ref filename) = args.precision_plot {
+        match write_plot(filename, &points, &ocl_env.environment.comment_lines()) {
+            Ok(()) => println!("Error growth chart written to {}", filename),
+            Err(err) => println!("Unable to write the error growth chart: {}", err)
+        }
+    }
+    Ok(())
+}
+
+fn has_fp64(device: &Device) -> GenResult<bool> {
+    Ok(match device.info(DeviceInfo::Extensions)? {
+        DeviceInfoResult::Extensions(extensions) => extensions.split_whitespace().any(|e| e == "cl_khr_fp64"),
+        _ => false
+    })
+}
+
+/* Normwise errors and time relative to float accumulation, then how fast each variant's error grows with n */
+fn print_summary(points: &[StudyPoint]) {
+    println!("===\nPrecision study: normwise error (time relative to float accumulation)");
+    println!("{:>8}{}", "n", VARIANTS.iter().map(|&(name, _)| format!("{:>22}", name)).collect::<String>());
+    for point in points.iter() {
+        let float_ns = point.results[0].as_ref().map(|&(_, time_ns)| time_ns);
+        let cells = point.results.iter().map(|result| match (result.as_ref(), float_ns) {
+            (Some(&(ref errors, time_ns)), Some(float_ns)) => format!("{:>22}", format!("{:.3e} ({:.2}x)", errors.normwise, time_ns as f64 / float_ns as f64)),
+            _ => format!("{:>22}", "-")
+        }).collect::<String>();
+        println!("{:>8}{}", point.n, cells);
+    }
+    for (variant_i, &(name, _)) in VARIANTS.iter().enumerate() {
+        let samples = points.iter().filter_map(|point| point.results[variant_i].as_ref().map(|&(ref errors, _)| (point.n, errors.normwise)))
+            .collect::<Vec<_>>();
+        if let Some(exponent) = growth_exponent(&samples) {
+            println!("{}: normwise error grows as n^{:.2}", name, exponent);
+        }
+    }
+}
+
+/* Least squares slope of log(error) over log(n); None without two distinct n with nonzero errors */
+fn growth_exponent(samples: &[(u32, f64)]) -> Option<f64> {
+    let logs = samples.iter().filter(|&&(_, error)| error > 0.0).map(|&(n, error)| ((n as f64).ln(), error.ln())).collect::<Vec<_>>();
+    let count = logs.len() as f64;
+    let (mean_x, mean_y) = (logs.iter().map(|l| l.0).sum::<f64>() / count, logs.iter().map(|l| l.1).sum::<f64>() / count);
+    let variance = logs.iter().map(|&(x, _)| (x - mean_x) * (x - mean_x)).sum::<f64>();
+    if logs.len() < 2 || variance == 0.0 {
+        return None;
+    }
+    Some(logs.iter().map(|&(x, y)| (x - mean_x) * (y - mean_y)).sum::<f64>() / variance)
+}
+
+/* Log-log chart of the normwise error of each variant over n */
+fn write_plot(filename: &str, points: &[StudyPoint], comment_lines: &[String]) -> GenResult<()> {
+    const WIDTH: f64 = 640.0;
+    const HEIGHT: f64 = 400.0;
+    /* Left, right (room for the legend), top, bottom */
+    const MARGINS: (f64, f64, f64, f64) = (80.0, 110.0, 20.0, 50.0);
+    const COLORS: [&str; 3] = ["#d62728", "#1f77b4", "#2ca02c"];
+
+    /* Errors of 0 (exact results) have no place on a log scale */
+    let errors = points.iter().flat_map(|point| point.results.iter().filter_map(|r| r.as_ref().map(|&(ref e, _)| e.normwise)))
+        .filter(|&error| error > 0.0).collect::<Vec<_>>();
+    if errors.is_empty() {
+        return gen_error_format!("all results are exact");
+    }
+    let log_n = points.iter().map(|point| (point.n as f64).log2()).collect::<Vec<_>>();
+    let (mut x_min, mut x_max) = (log_n.iter().cloned().fold(f64::INFINITY, f64::min), log_n.iter().cloned().fold(f64::NEG_INFINITY, f64::max));
+    if x_min == x_max { x_min -= 1.0; x_max += 1.0; }
+    let y_min = errors.iter().map(|e| e.log10()).fold(f64::INFINITY, f64::min).floor();
+    let y_max = (errors.iter().map(|e| e.log10()).fold(f64::NEG_INFINITY, f64::max).ceil()).max(y_min + 1.0);
+    let (plot_width, plot_height) = (WIDTH - MARGINS.0 - MARGINS.1, HEIGHT - MARGINS.2 - MARGINS.3);
+    let x = |log2_n: f64| MARGINS.0 + (log2_n - x_min) / (x_max - x_min) * plot_width;
+    let y = |error: f64| MARGINS.2 + (y_max - error.log10()) / (y_max - y_min) * plot_height;
+
+    let mut svg = BufWriter::new(File::create(filename).or(gen_error_format!("Unable to open {} for writing", filename))?);
+    writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" font-family=\"sans-serif\" font-size=\"12\">", WIDTH, HEIGHT)?;
+    /* "--" may not appear in XML comments */
+    writeln!(svg, "<!--\n{}\n-->", comment_lines.join("\n").replace("--", "- -"))?;
+    writeln!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"black\"/>", MARGINS.0, MARGINS.2, plot_width, plot_height)?;
+    for (point, &log2_n) in points.iter().zip(log_n.iter()) {
+        writeln!(svg, "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>", x(log2_n), HEIGHT - MARGINS.3 + 16.0, point.n)?;
+    }
+    for decade in y_min as i32..=y_max as i32 {
+        let y_decade = y(10f64.powi(decade));
+        writeln!(svg, "<line x1=\"{}\" y1=\"{:.1}\" x2=\"{}\" y2=\"{:.1}\" stroke=\"#ddd\"/>", MARGINS.0, y_decade, WIDTH - MARGINS.1, y_decade)?;
+        writeln!(svg, "<text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">1e{}</text>", MARGINS.0 - 6.0, y_decade + 4.0, decade)?;
+    }
+    writeln!(svg, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">n</text>", MARGINS.0 + plot_width / 2.0, HEIGHT - 10.0)?;
+    writeln!(svg, "<text transform=\"translate(16 {}) rotate(-90)\" text-anchor=\"middle\">normwise error</text>", MARGINS.2 + plot_height / 2.0)?;
+
+    for (variant_i, (&(name, _), color)) in VARIANTS.iter().zip(COLORS.iter()).enumerate() {
+        let coordinates = points.iter().zip(log_n.iter())
+            .filter_map(|(point, &log2_n)| point.results[variant_i].as_ref().map(|&(ref e, _)| (log2_n, e.normwise)))
+            .filter(|&(_, error)| error > 0.0)
+            .map(|(log2_n, error)| (x(log2_n), y(error)))
+            .collect::<Vec<_>>();
+        if coordinates.is_empty() {
+            continue;
+        }
+        let polyline = coordinates.iter().map(|&(cx, cy)| format!("{:.1},{:.1}", cx, cy)).collect::<Vec<_>>().join(" ");
+        writeln!(svg, "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>", polyline, color)?;
+        for &(cx, cy) in coordinates.iter() {
+            writeln!(svg, "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"3\" fill=\"{}\"/>", cx, cy, color)?;
+        }
+        let legend_y = MARGINS.2 + 16.0 + 18.0 * variant_i as f64;
+        writeln!(svg, "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"2\"/>",
+                 WIDTH - MARGINS.1 + 10.0, legend_y - 4.0, WIDTH - MARGINS.1 + 30.0, legend_y - 4.0, color)?;
+        writeln!(svg, "<text x=\"{}\" y=\"{}\">{}</text>", WIDTH - MARGINS.1 + 36.0, legend_y, name)?;
+    }
+    writeln!(svg, "</svg>")?;
+    svg.flush()?;
+    Ok(())
+}
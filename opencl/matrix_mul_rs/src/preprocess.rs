@@ -0,0 +1,169 @@
+use std::{io::prelude::*, io::BufReader, path::Path};
+use gen_error::{GenResult, GenError};
+use open_file;
+
+/* Names compilers give the program source in build logs, besides a plain file number (Intel) */
+const GENERATED_SOURCE_NAMES: [&str; 4] = ["<source>", "<kernel>", "<stdin>", "<program source>"];
+
+/* Kernel source with includes and templates expanded on the host, so that drivers never resolve paths.
+ *   #include "include/tile_load.h" TYPE=float4
+ * pastes the file (relative to the kernel directory, not to the including file) with every ${TYPE} replaced,
+ * e.g. in `${TYPE} load_or_zero_${TYPE}(...)`; vector types can be spelled ${TYPE}${WIDTH}.
+ * Parameters are inherited by nested includes. Including the same file with the same parameters again is a no-op,
+ * so headers need no include guards, while different parameters instantiate the template once more.
+ * `#include <...>` is left to the compiler. */
+pub struct Source {
+    pub text: String,
+    /* File and line (from 1) each line of text came from, to map build logs back to the original files */
+    origins: Vec<(String, u32)>
+}
+
+impl Source {
+    /* Prefix holds the defines set by the host, which come before the file */
+    pub fn load(kernel_dir: &str, filename: &str, prefix: &str) -> GenResult<Source> {
+        let mut source = Source { text: String::new(), origins: Vec::new() };
+        for (line_i, line) in prefix.lines().chain(Some("")).enumerate() {
+            source.push_line(line, "<defines>", line_i as u32 + 1);
+        }
+        let mut expansion = Expansion { kernel_dir: Path::new(kernel_dir), included: Vec::new(), stack: Vec::new() };
+        expansion.expand(&mut source, filename, &[])?;
+        Ok(source)
+    }
+
+    fn push_line(&mut self, line: &str, filename: &str, line_number: u32) {
+        self.text.push_str(line);
+        self.text.push('\n');
+        self.origins.push((filename.to_owned(), line_number));
+    }
+
+    /* Rewrites the locations in a build log, e.g. `<source>:120:5: error: ...` becomes `include/tile_load.h:12:5: error: ...`.
+     * Also handles EDG-style `"/tmp/OCL1234.cl", line 120: error: ...` (older AMD drivers). */
+    pub fn map_log(&self, log: &str) -> String {
+        log.lines().map(|line| self.map_log_line(line)).collect::<Vec<_>>().join("\n")
+    }
+
+    fn map_log_line(&self, line: &str) -> String {
+        let indent = line.len() - line.trim_start().len();
+        let rest = &line[indent..];
+        if let Some(colon) = rest.find(':') {
+            let name = &rest[..colon];
+            let is_generated = GENERATED_SOURCE_NAMES.contains(&name) || (!name.is_empty() && name.bytes().all(|b| b.is_ascii_digit()));
+            let digits = leading_digits(&rest[colon + 1..]);
+            if is_generated && !digits.is_empty() && rest[colon + 1 + digits.len()..].starts_with(':') {
+                return format!("{}{}{}", &line[..indent], self.location(digits), &rest[colon + 1 + digits.len()..]);
+            }
+        }
+        if rest.starts_with('"') {
+            if let Some(position) = rest.find("\", line ") {
+                let digits = leading_digits(&rest[position + "\", line ".len()..]);
+                if !digits.is_empty() {
+                    return format!("{}{}{}", &line[..indent], self.location(digits), &rest[position + "\", line ".len() + digits.len()..]);
+                }
+            }
+        }
+        line.to_owned()
+    }
+
+    fn location(&self, line_number: &str) -> String {
+        let origin = line_number.parse::<usize>().ok().and_then(|n| n.checked_sub(1)).and_then(|i| self.origins.get(i));
+        match origin {
+            Some(&(ref filename, line)) => format!("{}:{}", filename, line),
+            None => format!("<source>:{}", line_number)
+        }
+    }
+}
+
+fn leading_digits(s: &str) -> &str {
+    &s[..s.bytes().take_while(|b| b.is_ascii_digit()).count()]
+}
+
+/* Template parameter names and values */
+type Params = Vec<(String, String)>;
+
+struct Expansion<'a> {
+    kernel_dir: &'a Path,
+    /* Files already pasted, with the parameters they were pasted with */
+    included: Vec<(String, Params)>,
+    /* Files being expanded, to report include cycles */
+    stack: Vec<String>
+}
+
+impl<'a> Expansion<'a> {
+    fn expand(&mut self, source: &mut Source, filename: &str, params: &[(String, String)]) -> GenResult<()> {
+        let path = self.kernel_dir.join(filename);
+        let file = open_file(&path.to_string_lossy())?;
+        self.stack.push(filename.to_owned());
+        for (line_i, line) in BufReader::new(file).lines().enumerate() {
+            let line_number = line_i as u32 + 1;
+            let line = substitute(&line?, params).map_err(|e| GenError::from(format!("{}:{}: {}", filename, line_number, e)))?;
+            match parse_include(&line) {
+                Some(include) => {
+                    let (included_file, args) = include.map_err(|e| GenError::from(format!("{}:{}: {}", filename, line_number, e)))?;
+                    if self.stack.contains(&included_file) {
+                        return gen_error_format!("{}:{}: {} includes itself", filename, line_number, included_file);
+                    }
+                    if !self.kernel_dir.join(&included_file).is_file() {
+                        return gen_error_format!("{}:{}: {} not found in {}", filename, line_number, included_file, self.kernel_dir.display());
+                    }
+                    let mut included_params = params.iter().filter(|&&(ref name, _)| !args.iter().any(|&(ref arg, _)| arg == name))
+                        .cloned().collect::<Vec<_>>();
+                    included_params.extend(args);
+                    included_params.sort();
+                    let key = (included_file, included_params);
+                    if !self.included.contains(&key) {
+                        self.expand(source, &key.0, &key.1)?;
+                        self.included.push(key);
+                    }
+                },
+                None => source.push_line(&line, filename, line_number)
+            }
+        }
+        self.stack.pop();
+        Ok(())
+    }
+}
+
+/* Replaces every ${NAME} with the parameter's value */
+fn substitute(line: &str, params: &[(String, String)]) -> GenResult<String> {
+    let mut result = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return gen_error_format!("unterminated template parameter")
+        };
+        let name = &rest[start + 2..end];
+        match params.iter().find(|&&(ref param, _)| param == name) {
+            Some(&(_, ref value)) => { result.push_str(&rest[..start]); result.push_str(value); },
+            None => return gen_error_format!("no value for template parameter {}", name)
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/* `#include "FILE" [NAME=VALUE...]`; None for any other line, including `#include <FILE>` */
+fn parse_include(line: &str) -> Option<GenResult<(String, Params)>> {
+    let directive = line.trim_start();
+    if !directive.starts_with('#') || !directive[1..].trim_start().starts_with("include") {
+        return None;
+    }
+    let rest = directive[1..].trim_start()["include".len()..].trim_start();
+    if !rest.starts_with('"') {
+        return None;
+    }
+    let end = match rest[1..].find('"') {
+        Some(end) => end + 1,
+        None => return Some(gen_error_format!("unterminated include file name"))
+    };
+    let mut args = Vec::new();
+    for arg in rest[end + 1..].split_whitespace() {
+        if arg.starts_with("//") || arg.starts_with("/*") { break; }
+        match arg.find('=') {
+            Some(equals) if equals > 0 => args.push((arg[..equals].to_owned(), arg[equals + 1..].to_owned())),
+            _ => return Some(gen_error_format!("expected NAME=VALUE template arguments after the file name, got {}", arg))
+        }
+    }
+    Some(Ok((rest[1..end].to_owned(), args)))
+}
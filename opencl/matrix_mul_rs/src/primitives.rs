@@ -0,0 +1,209 @@
+use ocl::{Kernel, Event, Program};
+use gen_error::GenResult;
+use memory::{MemStrategy, MatrixBuffer, Access};
+use subgroup::SubgroupMode;
+use workload::{self, Peaks};
+use {cli, batch, OclEnv, build_ocl_program, get_execution_time_ns, ceil_divisible_by};
+
+const HISTOGRAM_BINS: u32 = 256;
+/* Cap on histogram work groups; the grid-stride loop covers the rest of the input */
+const HISTOGRAM_MAX_GROUPS: u32 = 1024;
+
+fn build_primitives(args: &cli::Args, ocl_env: &OclEnv, local_size: u32, tile_pad: u32) -> GenResult<Program> {
+    let subgroup_define = match ocl_env.subgroup_support.mode {
+        SubgroupMode::Khr => "#define SUBGROUP_KHR\n",
+        SubgroupMode::Intel => "#define SUBGROUP_INTEL\n",
+        SubgroupMode::Emulated => ""
+    };
+    let kernel_defs = format!("#define LOCAL_SIZE {}\n#define TILE_SIZE {}\n#define TILE_PAD {}\n#define HISTOGRAM_BINS {}\n{}",
+                              local_size, args.tile_size, tile_pad, HISTOGRAM_BINS, subgroup_define);
+    build_ocl_program(ocl_env, kernel_defs, "", "primitives.cl")
+}
+
+fn total_time_ns(events: &[Event]) -> GenResult<u64> {
+    events.iter().map(get_execution_time_ns).sum()
+}
+
+/* Values in [0, max) derived from the shared generator */
+fn generate_uints(len: usize, max: u32, seed: u32) -> Vec<u32> {
+    batch::generate_matrix(len, seed).iter().map(|v| ::std::cmp::min(((v + 1.0) / 2.0 * max as f32) as u32, max - 1)).collect()
+}
+
+pub fn run_reduction(args: &cli::Args, ocl_env: &OclEnv, strategy: MemStrategy, peaks: &Peaks) -> GenResult<()> {
+    let (queue, tracer, len) = (&ocl_env.queue, &ocl_env.tracer, args.elements);
+    let local_size = workload::local_size_1d(args, ocl_env);
+    println!("===\nRunning reductions of {} floats, local size {}, memory strategy: {}", len, local_size, strategy);
+
+    let input = batch::generate_matrix(len as usize, 7);
+    let expected = input.iter().map(|&v| v as f64).sum::<f64>();
+    /* A float tree sum is off by at most ~log2(len) roundings of the magnitude sum */
+    let tolerance = input.iter().map(|v| v.abs() as f64).sum::<f64>() * 32.0 * ::std::f32::EPSILON as f64;
+    let buffer_input = MatrixBuffer::with_data(queue, strategy, &input, Access::ReadOnly, tracer, "input")?;
+    let program = build_primitives(args, ocl_env, local_size, 0)?;
+
+    let mut kernel_names = vec!["reduce_tree"];
+    if ocl_env.subgroup_support.mode != SubgroupMode::Emulated {
+        kernel_names.push("reduce_subgroup");
+    }
+    else {
+        println!("No subgroup functions on this device, skipping reduce_subgroup");
+    }
+    for kernel_name in kernel_names {
+        println!("---\n{}", kernel_name);
+        /* Each pass shrinks the input by 2 * local_size until one value is left */
+        let (mut partials, mut events) = (Vec::<MatrixBuffer>::new(), Vec::new());
+        let mut pass_len = len;
+        while pass_len > 1 || partials.is_empty() {
+            let groups = ceil_divisible_by(pass_len, 2 * local_size) / (2 * local_size);
+            let output = MatrixBuffer::new(queue, strategy, groups as usize, Access::ReadWrite)?;
+            let kernel = Kernel::builder()
+                .queue(queue.clone())
+                .program(&program).name(kernel_name)
+                .arg(&partials.last().unwrap_or(&buffer_input).buffer).arg(&output.buffer).arg(pass_len)
+                .build()?;
+            events.push(workload::enqueue_timed(ocl_env, &kernel, kernel_name, [groups * local_size], [local_size])?);
+            partials.push(output);
+            pass_len = groups;
+        }
+        let mut actual = [0.0f32];
+        partials.last().unwrap().read(queue, &mut actual, tracer, "sum")?;
+
+        let error = (actual[0] as f64 - expected).abs();
+        if error > tolerance {
+            println!("Expected {:.6}, got {:.6} (error {:.3e}, tolerance {:.3e})", expected, actual[0], error, tolerance);
+        }
+        else {
+            println!("Result verified, no errors found (error {:.3e})", error);
+        }
+        println!("{} passes", events.len());
+        workload::print_perf(peaks, total_time_ns(&events)?, Some(len as u64), len as u64 * 4);
+    }
+    Ok(())
+}
+
+pub fn run_scan(args: &cli::Args, ocl_env: &OclEnv, strategy: MemStrategy, peaks: &Peaks) -> GenResult<()> {
+    let (queue, tracer, len) = (&ocl_env.queue, &ocl_env.tracer, args.elements);
+    let local_size = workload::local_size_1d(args, ocl_env);
+    println!("===\nRunning exclusive scan of {} uints, local size {}, memory strategy: {}", len, local_size, strategy);
+
+    let input = generate_uints(len as usize, 16, 8);
+    let mut expected = Vec::with_capacity(input.len());
+    input.iter().fold(0u32, |sum, &v| { expected.push(sum); sum.wrapping_add(v) });
+
+    let buffer_input = MatrixBuffer::with_data(queue, strategy, &input, Access::ReadOnly, tracer, "input")?;
+    let buffer_output = MatrixBuffer::<u32>::new(queue, strategy, len as usize, Access::ReadWrite)?;
+    let program = build_primitives(args, ocl_env, local_size, 0)?;
+
+    let mut scan = Scan { ocl_env, program: &program, strategy, local_size, events: Vec::new() };
+    scan.run(&buffer_input, &buffer_output, len)?;
+    let events = scan.events;
+    let mut actual = vec![0u32; len as usize];
+    buffer_output.read(queue, &mut actual, tracer, "output")?;
+    workload::verify_exact(&expected, &actual);
+
+    println!("{} kernel launches", events.len());
+    workload::print_perf(peaks, total_time_ns(&events)?, Some(len as u64), 2 * len as u64 * 4);
+    Ok(())
+}
+
+/* Recursive scan state: the block sums of each level are scanned like the input */
+struct Scan<'a> {
+    ocl_env: &'a OclEnv,
+    program: &'a Program,
+    strategy: MemStrategy,
+    local_size: u32,
+    events: Vec<Event>
+}
+
+impl<'a> Scan<'a> {
+    fn run(&mut self, input: &MatrixBuffer<u32>, output: &MatrixBuffer<u32>, len: u32) -> GenResult<()> {
+        let (queue, local_size) = (&self.ocl_env.queue, self.local_size);
+        let groups = ceil_divisible_by(len, 2 * local_size) / (2 * local_size);
+        let block_sums = MatrixBuffer::<u32>::new(queue, self.strategy, groups as usize, Access::ReadWrite)?;
+        let kernel = Kernel::builder()
+            .queue(queue.clone())
+            .program(self.program).name("scan_blocks")
+            .arg(&input.buffer).arg(&output.buffer).arg(&block_sums.buffer).arg(len)
+            .build()?;
+        self.events.push(workload::enqueue_timed(self.ocl_env, &kernel, "scan_blocks", [groups * local_size], [local_size])?);
+        if groups == 1 {
+            return Ok(());
+        }
+
+        let block_offsets = MatrixBuffer::<u32>::new(queue, self.strategy, groups as usize, Access::ReadWrite)?;
+        self.run(&block_sums, &block_offsets, groups)?;
+        let kernel = Kernel::builder()
+            .queue(queue.clone())
+            .program(self.program).name("scan_add_offsets")
+            .arg(&output.buffer).arg(&block_offsets.buffer).arg(len)
+            .build()?;
+        self.events.push(workload::enqueue_timed(self.ocl_env, &kernel, "scan_add_offsets", [ceil_divisible_by(len, local_size)], [local_size])?);
+        Ok(())
+    }
+}
+
+/* Transposes the m-by-n matrix A shape */
+pub fn run_transpose(args: &cli::Args, ocl_env: &OclEnv, strategy: MemStrategy, peaks: &Peaks) -> GenResult<()> {
+    let (queue, tracer, rows, cols, tile_size) = (&ocl_env.queue, &ocl_env.tracer, args.m, args.n, args.tile_size);
+    println!("===\nRunning transposes of a {}x{} matrix, tile size {}, memory strategy: {}", rows, cols, tile_size, strategy);
+
+    let input = batch::generate_matrix((rows * cols) as usize, 9);
+    let mut expected = vec![0.0f32; input.len()];
+    for row in 0..rows as usize {
+        for col in 0..cols as usize {
+            expected[col * rows as usize + row] = input[row * cols as usize + col];
+        }
+    }
+    let buffer_input = MatrixBuffer::with_data(queue, strategy, &input, Access::ReadOnly, tracer, "input")?;
+    let buffer_output = MatrixBuffer::<f32>::new(queue, strategy, input.len(), Access::WriteOnly)?;
+    let global_size = [ceil_divisible_by(cols, tile_size), ceil_divisible_by(rows, tile_size)];
+    let local_size_1d = workload::local_size_1d(args, ocl_env);
+
+    for &(kernel_name, tile_pad) in [("transpose_naive", 0), ("transpose_tiled", 0), ("transpose_tiled", 1)].iter() {
+        println!("---\n{}{}", kernel_name, if tile_pad > 0 { format!(", tile padded by {} column", tile_pad) } else { String::new() });
+        let program = build_primitives(args, ocl_env, local_size_1d, tile_pad)?;
+        let kernel = Kernel::builder()
+            .queue(queue.clone())
+            .program(&program).name(kernel_name)
+            .arg(&buffer_input.buffer).arg(&buffer_output.buffer).arg(rows).arg(cols)
+            .build()?;
+        let event = workload::enqueue_timed(ocl_env, &kernel, kernel_name, global_size, [tile_size, tile_size])?;
+        let mut actual = vec![0.0f32; input.len()];
+        buffer_output.read(queue, &mut actual, tracer, "output")?;
+        workload::verify_exact(&expected, &actual);
+        workload::print_perf(peaks, get_execution_time_ns(&event)?, None, 2 * input.len() as u64 * 4);
+    }
+    Ok(())
+}
+
+pub fn run_histogram(args: &cli::Args, ocl_env: &OclEnv, strategy: MemStrategy, peaks: &Peaks) -> GenResult<()> {
+    let (queue, tracer, len) = (&ocl_env.queue, &ocl_env.tracer, args.elements);
+    let local_size = workload::local_size_1d(args, ocl_env);
+    println!("===\nRunning {}-bin histograms of {} values, local size {}, memory strategy: {}", HISTOGRAM_BINS, len, local_size, strategy);
+
+    let data = generate_uints(len as usize, HISTOGRAM_BINS, 10);
+    let mut expected = vec![0u32; HISTOGRAM_BINS as usize];
+    for &v in data.iter() {
+        expected[v as usize] += 1;
+    }
+    let buffer_data = MatrixBuffer::with_data(queue, strategy, &data, Access::ReadOnly, tracer, "data")?;
+    let buffer_bins = MatrixBuffer::<u32>::new(queue, strategy, HISTOGRAM_BINS as usize, Access::ReadWrite)?;
+    let program = build_primitives(args, ocl_env, local_size, 0)?;
+    let groups = ::std::cmp::min(ceil_divisible_by(len, local_size) / local_size, HISTOGRAM_MAX_GROUPS);
+
+    for &kernel_name in ["histogram_global", "histogram_local"].iter() {
+        println!("---\n{}", kernel_name);
+        buffer_bins.write(queue, &vec![0u32; HISTOGRAM_BINS as usize], tracer, "bins (reset)")?;
+        let kernel = Kernel::builder()
+            .queue(queue.clone())
+            .program(&program).name(kernel_name)
+            .arg(&buffer_data.buffer).arg(&buffer_bins.buffer).arg(len)
+            .build()?;
+        let event = workload::enqueue_timed(ocl_env, &kernel, kernel_name, [groups * local_size], [local_size])?;
+        let mut actual = vec![0u32; HISTOGRAM_BINS as usize];
+        buffer_bins.read(queue, &mut actual, tracer, "bins")?;
+        workload::verify_exact(&expected, &actual);
+        workload::print_perf(peaks, get_execution_time_ns(&event)?, None, len as u64 * 4);
+    }
+    Ok(())
+}
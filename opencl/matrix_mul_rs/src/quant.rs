@@ -0,0 +1,115 @@
+use std::{cmp, fmt, fmt::{Display, Formatter}};
+use ocl::Device;
+use ocl::enums::{DeviceInfo, DeviceInfoResult};
+use gen_error::GenResult;
+
+/* Packed 4x8-bit dot products in hardware; otherwise int8.cl falls back to widening multiplies */
+pub fn has_integer_dot_product(device: &Device) -> GenResult<bool> {
+    Ok(match device.info(DeviceInfo::Extensions)? {
+        DeviceInfoResult::Extensions(extensions) => extensions.split_whitespace().any(|e| e == "cl_khr_integer_dot_product"),
+        _ => false
+    })
+}
+
+/* Per-tensor affine quantization: real = scale * (q - zero_point) */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantParams {
+    pub scale: f32,
+    pub zero_point: i32
+}
+
+impl QuantParams {
+    /* Maps the value range of the tensor (widened to include 0, so that 0 is exact) onto [-128, 127] */
+    pub fn for_tensor(data: &[f32]) -> QuantParams {
+        let min = data.iter().cloned().fold(0.0f32, f32::min);
+        let max = data.iter().cloned().fold(0.0f32, f32::max);
+        if max == min {
+            return QuantParams { scale: 1.0, zero_point: 0 };
+        }
+        let scale = (max - min) / 255.0;
+        let zero_point = (-128.0 - min / scale).round() as i32;
+        QuantParams { scale, zero_point: cmp::min(127, cmp::max(-128, zero_point)) }
+    }
+}
+
+impl Display for QuantParams {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "scale {:e}, zero point {}", self.scale, self.zero_point)
+    }
+}
+
+pub fn quantize(data: &[f32], params: QuantParams) -> Vec<i8> {
+    data.iter().map(|&x| {
+        let q = (x / params.scale).round() as i32 + params.zero_point;
+        cmp::min(127, cmp::max(-128, q)) as i8
+    }).collect()
+}
+
+pub fn dequantize(data: &[i8], params: QuantParams) -> Vec<f32> {
+    data.iter().map(|&q| params.scale * (q as i32 - params.zero_point) as f32).collect()
+}
+
+/* The int32 accumulators of a product of zero-point-corrected tensors scale by both scales */
+pub fn dequantize_accumulators(data: &[i32], a: QuantParams, b: QuantParams) -> Vec<f32> {
+    data.iter().map(|&acc| a.scale * b.scale * acc as f32).collect()
+}
+
+/* Exact CPU reference: C[i][j] = sum over k of (A[i][k] - za) * (B[k][j] - zb) */
+pub fn gemm_reference(a: &[i8], a_zero: i32, b: &[i8], b_zero: i32, m: u32, n: u32, p: u32) -> Vec<i32> {
+    let (m, n, p) = (m as usize, n as usize, p as usize);
+    let mut c = vec![0i32; m * p];
+    for i in 0..m {
+        for k in 0..n {
+            let a_ik = a[i * n + k] as i32 - a_zero;
+            for j in 0..p {
+                c[i * p + j] += a_ik * (b[k * p + j] as i32 - b_zero);
+            }
+        }
+    }
+    c
+}
+
+/* Packs each row of a rows-by-cols int8 matrix into little-endian 32-bit words of four values,
+ * padding the row with zeros to a multiple of four (zeros contribute nothing to products or sums) */
+pub fn pack_rows(data: &[i8], rows: u32, cols: u32) -> Vec<u32> {
+    let (rows, cols) = (rows as usize, cols as usize);
+    let words_per_row = (cols + 3) / 4;
+    let mut packed = vec![0u32; rows * words_per_row];
+    for row in 0..rows {
+        for col in 0..cols {
+            packed[row * words_per_row + col / 4] |= (data[row * cols + col] as u8 as u32) << (8 * (col % 4));
+        }
+    }
+    packed
+}
+
+pub fn transpose(data: &[i8], rows: u32, cols: u32) -> Vec<i8> {
+    let (rows, cols) = (rows as usize, cols as usize);
+    let mut transposed = vec![0i8; rows * cols];
+    for row in 0..rows {
+        for col in 0..cols {
+            transposed[col * rows + row] = data[row * cols + col];
+        }
+    }
+    transposed
+}
+
+/* Prints up to `max_print` mismatches and returns their count; integer results must match exactly */
+pub fn verify_exact(expected: &[i32], actual: &[i32], cols: u32, max_print: u32) -> u32 {
+    let mut errors_encountered = 0;
+    for (i, (expected, actual)) in expected.iter().zip(actual.iter()).enumerate() {
+        if expected != actual {
+            errors_encountered += 1;
+            if errors_encountered < max_print {
+                println!("Row {}, col {}: expected {}, got {}", i as u32 / cols, i as u32 % cols, expected, actual);
+            }
+        }
+    }
+    if errors_encountered > max_print {
+        println!("...\n({} errors omitted)", errors_encountered - max_print);
+    }
+    else if errors_encountered == 0 {
+        println!("Result verified, all values match exactly");
+    }
+    errors_encountered
+}
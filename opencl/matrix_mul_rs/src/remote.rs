@@ -0,0 +1,277 @@
+use std::{env, thread, io::prelude::*, io::BufReader, net::{TcpListener, TcpStream}, path::{Component, Path}, process::{Command, Stdio},
+          sync::mpsc, time::Duration};
+use gen_error::{GenResult, GenError};
+use cli::{self, JobUse};
+use {json, open_file};
+
+/* Output lines starting with this carry a JSON result; printed with --result-lines for the agent to forward */
+pub const RESULT_PREFIX: &str = "@result ";
+const DEFAULT_ADDRESS: &str = "127.0.0.1:7878";
+/* A client has this long to send its job, so that an idle connection can't hold up the agent */
+const JOB_READ_TIMEOUT_S: u64 = 10;
+
+/* Prints a structured result when the run was started by an agent */
+pub fn print_result(args: &cli::Args, kind: &str, fields: &[(&str, String)]) {
+    if args.result_lines {
+        println!("{}{}", RESULT_PREFIX, message(kind, fields));
+    }
+}
+
+/* `agent [ADDRESS [PLATFORM [DATA_DIR]]]`: accepts one job per connection as a JSON line and runs it in a child
+ * process, streaming its output back as JSON lines. Jobs run one at a time so that they don't skew each other's
+ * timings. There is no authentication, so jobs are limited to the options cli::OPTIONS allows them, and may only
+ * name input files in DATA_DIR (none without it); still, only listen on trusted networks. */
+pub fn run_agent(command_line: &[String]) -> GenResult<()> {
+    let address = command_line.get(2).map(|s| s.as_str()).unwrap_or(DEFAULT_ADDRESS);
+    let default_platform = command_line.get(3).map(|s| s.as_str());
+    let data_dir = command_line.get(4).map(Path::new);
+    let executable = env::current_exe()?;
+    let listener = TcpListener::bind(address).or(gen_error_format!("Unable to listen on {}", address))?;
+    println!("Agent listening on {}", listener.local_addr()?);
+    for connection in listener.incoming() {
+        let served = connection.map_err(GenError::from).and_then(|stream| serve(stream, &executable, default_platform, data_dir));
+        if let Err(err) = served {
+            println!("Job failed: {}", err);
+        }
+    }
+    Ok(())
+}
+
+/* Messages sent to the client, one per line:
+ *   {"type": "accepted", "command_line": [...]}, or {"type": "error", "message": ...} for a malformed job
+ *   {"type": "log", "stream": "stdout" | "stderr", "line": ...} for each line of output
+ *   {"type": "run" | "timeout" | "environment", ...} for each result line (see print_result)
+ *   {"type": "done", "status": exit code, or null if the process was killed} */
+fn serve(stream: TcpStream, executable: &Path, default_platform: Option<&str>, data_dir: Option<&Path>) -> GenResult<()> {
+    let peer = stream.peer_addr()?;
+    stream.set_read_timeout(Some(Duration::from_secs(JOB_READ_TIMEOUT_S)))?;
+    let mut job = String::new();
+    BufReader::new(stream.try_clone()?).read_line(&mut job)?;
+    let mut writer = stream;
+    let command_line = match json::parse(&job).and_then(|job| job_command_line(&job, default_platform, data_dir)) {
+        Ok(command_line) => command_line,
+        Err(err) => return send(&mut writer, &message("error", &[("message", json::string(&err.to_string()))]))
+    };
+    println!("Job from {}: {:?}", peer, command_line);
+
+    let mut child = Command::new(executable).args(&command_line).arg("--result-lines")
+        .stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn()?;
+    send(&mut writer, &message("accepted", &[("command_line", json::array(&command_line.iter().map(|arg| json::string(arg)).collect::<Vec<_>>()))]))?;
+
+    let (sender, receiver) = mpsc::channel();
+    let stdout_reader = forward_lines(child.stdout.take(), "stdout", sender.clone());
+    let stderr_reader = forward_lines(child.stderr.take(), "stderr", sender);
+    for line in receiver {
+        /* The client went away; a job nobody receives results of isn't worth the device time */
+        if let Err(err) = send(&mut writer, &line) {
+            child.kill().ok();
+            child.wait()?;
+            return Err(err);
+        }
+    }
+    stdout_reader.join().ok();
+    stderr_reader.join().ok();
+    let status = child.wait()?;
+    println!("Job from {} finished ({})", peer, status);
+    send(&mut writer, &message("done", &[("status", status.code().map(|code| code.to_string()).unwrap_or_else(|| "null".to_owned()))]))
+}
+
+/* Turns each line of a child's output into a message; result lines are forwarded as they are */
+fn forward_lines<R: Read + Send + 'static>(output: Option<R>, stream_name: &'static str, sender: mpsc::Sender<String>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let output = match output {
+            Some(output) => BufReader::new(output),
+            None => return
+        };
+        for line in output.split(b'\n') {
+            let line = match line {
+                Ok(line) => String::from_utf8_lossy(&line).trim_end_matches('\r').to_owned(),
+                Err(_) => break
+            };
+            let is_result = line.starts_with(RESULT_PREFIX) && json::parse(&line[RESULT_PREFIX.len()..]).is_ok();
+            let message = if is_result { line[RESULT_PREFIX.len()..].to_owned() }
+                          else { message("log", &[("stream", json::string(stream_name)), ("line", json::string(&line))]) };
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/* A job gives the positional arguments and the options as they would appear on the command line, e.g.
+ * {"platform": "AMD Accelerated Parallel Processing", "tile_size": 16, "m": 1024, "n": 1024, "p": 1024,
+ *  "device_gflops": 400, "workloads": ["gemm", "reduce"], "defines": ["tiled:UNROLL=1,4"],
+ *  "options": ["--sweep", "--mem-strategy", "all", "--matrix-a", "large/matrix_a.npy"]}
+ * Only the platform (if the agent has a default) and the arrays are optional. "options" are checked against
+ * cli::OPTIONS, and the files they name are looked up in data_dir. */
+fn job_command_line(job: &json::Value, default_platform: Option<&str>, data_dir: Option<&Path>) -> GenResult<Vec<String>> {
+    let platform = match job.get("platform").and_then(|p| p.as_str()).or(default_platform) {
+        Some(platform) => platform.to_owned(),
+        None => return gen_error_format!("The job names no platform, and the agent has no default")
+    };
+    /* These would make the child run another mode instead of a benchmark */
+    if ["agent", "client", "replay"].contains(&platform.as_str()) {
+        return gen_error_format!("\"{}\" is not a platform name", platform);
+    }
+    let mut command_line = vec![platform];
+    for &key in ["tile_size", "m", "n", "p", "device_gflops"].iter() {
+        match job.get(key).and_then(|v| v.as_f64()) {
+            Some(value) => command_line.push(value.to_string()),
+            None => return gen_error_format!("The job has no numeric {}", key)
+        }
+    }
+    let strings = |key: &str| -> GenResult<Vec<String>> {
+        match job.get(key) {
+            Some(&json::Value::Array(ref items)) => items.iter()
+                .map(|item| item.as_str().map(|s| s.to_owned()).ok_or(GenError::from(format!("{} must only contain strings", key))))
+                .collect(),
+            Some(_) => gen_error_format!("{} must be an array of strings", key),
+            None => Ok(Vec::new())
+        }
+    };
+    let workloads = strings("workloads")?;
+    if !workloads.is_empty() {
+        command_line.push("--workload".to_owned());
+        command_line.push(workloads.join(","));
+    }
+    if job.get("compiler_options").is_some() {
+        return gen_error_format!("Jobs may not pass compiler options");
+    }
+    for define in strings("defines")? {
+        command_line.push("-D".to_owned());
+        command_line.push(define);
+    }
+    let options = strings("options")?;
+    let mut options_iter = options.iter();
+    while let Some(option) = options_iter.next() {
+        let (takes_value, job_use) = match cli::OPTIONS.iter().find(|&&(name, _, _)| name == option) {
+            Some(&(_, takes_value, job_use)) if job_use != JobUse::Denied => (takes_value, job_use),
+            _ => return gen_error_format!("Jobs may not pass {}", option)
+        };
+        command_line.push(option.clone());
+        if takes_value {
+            match options_iter.next() {
+                Some(value) if job_use == JobUse::DataFile => command_line.push(data_file(data_dir, option, value)?),
+                Some(value) => command_line.push(value.clone()),
+                None => return gen_error_format!("{} expects a value", option)
+            }
+        }
+    }
+    Ok(command_line)
+}
+
+/* Absolute paths and .. could reach any file the agent can read */
+fn data_file(data_dir: Option<&Path>, option: &str, name: &str) -> GenResult<String> {
+    let data_dir = match data_dir {
+        Some(data_dir) => data_dir,
+        None => return gen_error_format!("{} needs an agent started with a data directory", option)
+    };
+    let inside = Path::new(name).components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    if name.is_empty() || !inside {
+        return gen_error_format!("{} must name a file inside the agent's data directory", option);
+    }
+    Ok(data_dir.join(name).to_string_lossy().into_owned())
+}
+
+fn message(kind: &str, fields: &[(&str, String)]) -> String {
+    let mut fields = fields.to_vec();
+    fields.insert(0, ("type", json::string(kind)));
+    json::object(&fields)
+}
+
+fn send(stream: &mut TcpStream, message: &str) -> GenResult<()> {
+    stream.write_all(message.as_bytes())?;
+    stream.write_all(b"\n")?;
+    Ok(())
+}
+
+/* `client JOB_FILE AGENT [AGENT...]`: sends the job to every agent at once, prints their output as it arrives
+ * (prefixed with the agent's address), then a summary of each agent's results */
+pub fn run_client(command_line: &[String]) -> GenResult<()> {
+    if command_line.len() < 4 {
+        return gen_error_format!("client expects a job file and at least one agent address");
+    }
+    let mut contents = String::new();
+    open_file(&command_line[2])?.read_to_string(&mut contents)?;
+    let job = json::parse(&contents).map_err(|e| GenError::from(format!("{}: {}", command_line[2], e)))?;
+    /* Catch malformed jobs before they reach the agents; the platform may be left to their defaults,
+     * and files are looked up by each agent */
+    job_command_line(&job, Some(""), Some(Path::new("")))?;
+    let job_line = job.to_string();
+
+    let agents = &command_line[3..];
+    let (sender, receiver) = mpsc::channel();
+    for (index, agent) in agents.iter().enumerate() {
+        let (agent, job_line, sender) = (agent.clone(), job_line.clone(), sender.clone());
+        thread::spawn(move || {
+            if let Err(err) = receive_job(index, &agent, &job_line, &sender) {
+                sender.send((index, Err(err))).ok();
+            }
+        });
+    }
+    drop(sender);
+
+    let mut results = agents.iter().map(|agent| (agent.clone(), Vec::new())).collect::<Vec<(String, Vec<json::Value>)>>();
+    for (index, message) in receiver {
+        /* Connection failures are reported like errors of the agent */
+        let message = message.unwrap_or_else(|err| json::Value::Object(vec![
+            ("type".to_owned(), json::Value::String("error".to_owned())),
+            ("message".to_owned(), json::Value::String(err.to_string()))
+        ]));
+        print_message(&agents[index], &message);
+        results[index].1.push(message);
+    }
+    print_summary(&results);
+    Ok(())
+}
+
+/* Messages are tagged with the agent's index, as the same agent may be given more than once */
+fn receive_job(index: usize, agent: &str, job_line: &str, sender: &mpsc::Sender<(usize, GenResult<json::Value>)>) -> GenResult<()> {
+    let mut stream = TcpStream::connect(agent).or(gen_error_format!("Unable to connect to {}", agent))?;
+    send(&mut stream, job_line)?;
+    for line in BufReader::new(stream).lines() {
+        let message = json::parse(&line?)?;
+        if sender.send((index, Ok(message))).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn print_message(agent: &str, message: &json::Value) {
+    let field = |key| message.get(key).map(|v| v.as_str().map(|s| s.to_owned()).unwrap_or_else(|| v.to_string())).unwrap_or_default();
+    match field("type").as_str() {
+        "log" => println!("[{}] {}", agent, field("line")),
+        "accepted" => println!("[{}] Running {}", agent, message.get("command_line").map(|c| c.to_string()).unwrap_or_default()),
+        "error" => println!("[{}] Error: {}", agent, field("message")),
+        "done" => println!("[{}] Finished with status {}", agent, field("status")),
+        /* Results are repeated in the summary, and as log lines in the agent's output */
+        _ => ()
+    }
+}
+
+fn print_summary(results: &[(String, Vec<json::Value>)]) {
+    println!("===\nResults");
+    for &(ref agent, ref messages) in results.iter() {
+        let device = messages.iter().filter_map(|m| m.get("environment"))
+            .map(|env| format!(" ({}, driver {})", env.get("device").and_then(|d| d.as_str()).unwrap_or("?"),
+                               env.get("driver_version").and_then(|d| d.as_str()).unwrap_or("?")))
+            .next().unwrap_or_default();
+        println!("{}{}:", agent, device);
+        for message in messages.iter() {
+            let field = |key| message.get(key).and_then(|v| v.as_str()).unwrap_or("");
+            let number = |key| message.get(key).and_then(|v| v.as_f64()).unwrap_or(0.0);
+            match field("type") {
+                "run" => println!("    {} [{}]: {:.3} [ms], {:.3} [GFLOPS], {} errors", field("label"), field("strategy"),
+                                  number("time_ns") / 1_000_000.0, number("flops") / number("time_ns"), number("errors")),
+                /* int8 kernels don't go through the memory strategies */
+                "timeout" if field("strategy").is_empty() => println!("    {}: timed out", field("label")),
+                "timeout" => println!("    {} [{}]: timed out", field("label"), field("strategy")),
+                "error" => println!("    error: {}", field("message")),
+                "done" => println!("    exit status: {}", message.get("status").map(|s| s.to_string()).unwrap_or_default()),
+                _ => ()
+            }
+        }
+    }
+}
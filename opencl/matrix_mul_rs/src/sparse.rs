@@ -0,0 +1,271 @@
+use ocl::{Kernel, Program};
+use gen_error::{GenResult, GenError};
+use matrix_io::{self, MtxEntries, MatrixFormat};
+use memory::{MemStrategy, MatrixBuffer, Access};
+use subgroup::SubgroupMode;
+use workload::{self, Peaks};
+use {cli, batch, OclEnv, build_ocl_program, get_execution_time_ns, ceil_divisible_by, verify_results};
+
+/* Compressed sparse rows: the entries of row r are row_offsets[r]..row_offsets[r + 1], sorted by column */
+pub struct CsrMatrix {
+    pub rows: u32,
+    pub cols: u32,
+    pub row_offsets: Vec<u32>,
+    pub col_indices: Vec<u32>,
+    pub values: Vec<f32>
+}
+
+impl CsrMatrix {
+    /* Duplicate coordinates are summed, as Matrix Market readers usually do */
+    pub fn from_entries(mtx: &MtxEntries) -> CsrMatrix {
+        let mut entries = mtx.entries.clone();
+        entries.sort_by_key(|&(row, col, _)| (row, col));
+        let mut csr = CsrMatrix { rows: mtx.rows, cols: mtx.cols, row_offsets: vec![0; mtx.rows as usize + 1], col_indices: Vec::new(), values: Vec::new() };
+        let mut last = None;
+        for &(row, col, value) in entries.iter() {
+            if last == Some((row, col)) {
+                *csr.values.last_mut().unwrap() += value;
+                continue;
+            }
+            csr.col_indices.push(col);
+            csr.values.push(value);
+            csr.row_offsets[row as usize + 1] += 1;
+            last = Some((row, col));
+        }
+        for row in 0..mtx.rows as usize {
+            csr.row_offsets[row + 1] += csr.row_offsets[row];
+        }
+        csr
+    }
+
+    /* Deterministic random matrix; row lengths vary uniformly between 0 and twice the mean */
+    pub fn generate(rows: u32, cols: u32, density: f64, seed: u32) -> CsrMatrix {
+        let mean_row_len = (density * cols as f64).max(1.0);
+        let random = batch::generate_matrix(rows as usize, seed);
+        let mut entries = Vec::new();
+        for row in 0..rows {
+            let row_len = ::std::cmp::min(((random[row as usize] + 1.0) as f64 * mean_row_len).round() as u32, cols);
+            let positions = batch::generate_matrix(row_len as usize, seed.wrapping_add(row + 1));
+            let mut row_cols = positions.iter().map(|p| ::std::cmp::min(((p + 1.0) / 2.0 * cols as f32) as u32, cols - 1)).collect::<Vec<_>>();
+            row_cols.sort();
+            row_cols.dedup();
+            let values = batch::generate_matrix(row_cols.len(), seed ^ row.wrapping_mul(7919));
+            entries.extend(row_cols.into_iter().zip(values).map(|(col, value)| (row, col, value)));
+        }
+        CsrMatrix::from_entries(&MtxEntries { rows, cols, entries })
+    }
+
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn max_row_len(&self) -> u32 {
+        self.row_offsets.windows(2).map(|w| w[1] - w[0]).max().unwrap_or(0)
+    }
+
+    pub fn spmv(&self, x: &[f32]) -> Vec<f32> {
+        (0..self.rows as usize).map(|row| {
+            (self.row_offsets[row]..self.row_offsets[row + 1]).map(|i| self.values[i as usize] * x[self.col_indices[i as usize] as usize]).sum()
+        }).collect()
+    }
+
+    /* B is cols x p, row-major */
+    pub fn spmm(&self, b: &[f32], p: u32) -> Vec<f32> {
+        let p = p as usize;
+        let mut c = vec![0.0f32; self.rows as usize * p];
+        for row in 0..self.rows as usize {
+            for i in self.row_offsets[row] as usize..self.row_offsets[row + 1] as usize {
+                let (value, b_row) = (self.values[i], self.col_indices[i] as usize);
+                for col in 0..p {
+                    c[row * p + col] += value * b[b_row * p + col];
+                }
+            }
+        }
+        c
+    }
+
+    /* Minimum traffic of one SpMV: the matrix, x and y once each */
+    fn spmv_bytes(&self) -> u64 {
+        (self.nnz() as u64 * 8) + (self.rows as u64 + 1) * 4 + self.cols as u64 * 4 + self.rows as u64 * 4
+    }
+}
+
+/* ELLPACK: every row padded to the longest one, stored entry-major (entry k of row r at k * rows + r) */
+pub struct EllMatrix {
+    pub rows: u32,
+    pub width: u32,
+    pub col_indices: Vec<u32>,
+    pub values: Vec<f32>
+}
+
+impl EllMatrix {
+    pub fn from_csr(csr: &CsrMatrix) -> EllMatrix {
+        let (rows, width) = (csr.rows as usize, csr.max_row_len() as usize);
+        let mut ell = EllMatrix { rows: csr.rows, width: width as u32, col_indices: vec![0; rows * width], values: vec![0.0; rows * width] };
+        for row in 0..rows {
+            let start = csr.row_offsets[row] as usize;
+            for (k, i) in (start..csr.row_offsets[row + 1] as usize).enumerate() {
+                ell.col_indices[k * rows + row] = csr.col_indices[i];
+                ell.values[k * rows + row] = csr.values[i];
+            }
+        }
+        ell
+    }
+
+    /* Stored entries per nonzero; ELL only pays off while this stays close to 1 */
+    pub fn padding_ratio(&self, nnz: usize) -> f64 {
+        self.rows as f64 * self.width as f64 / nnz.max(1) as f64
+    }
+}
+
+/* The --sparse-matrix file, or a generated m-by-n matrix of the requested density */
+fn load_matrix(args: &cli::Args) -> GenResult<CsrMatrix> {
+    Ok(match args.sparse_matrix {
+        Some(ref filename) => {
+            if MatrixFormat::detect(filename)? != MatrixFormat::MatrixMarket {
+                return gen_error_format!("{}: sparse matrices must be Matrix Market files", filename);
+            }
+            CsrMatrix::from_entries(&matrix_io::read_mtx(filename)?)
+        },
+        None => CsrMatrix::generate(args.m, args.n, args.density, 11)
+    })
+}
+
+fn build_sparse(ocl_env: &OclEnv, local_size: u32, ell_width: u32) -> GenResult<Program> {
+    let subgroup_define = match ocl_env.subgroup_support.mode {
+        SubgroupMode::Khr => "#define SUBGROUP_KHR\n",
+        SubgroupMode::Intel => "#define SUBGROUP_INTEL\n",
+        SubgroupMode::Emulated => ""
+    };
+    let kernel_defs = format!("#define LOCAL_SIZE {}\n#define VECTOR_WIDTH {}\n#define ELL_WIDTH {}\n{}",
+                              local_size, ::std::cmp::min(32, local_size), ell_width, subgroup_define);
+    build_ocl_program(ocl_env, kernel_defs, "", "sparse.cl")
+}
+
+struct CsrBuffers {
+    row_offsets: MatrixBuffer<u32>,
+    col_indices: MatrixBuffer<u32>,
+    values: MatrixBuffer
+}
+
+impl CsrBuffers {
+    fn new(ocl_env: &OclEnv, strategy: MemStrategy, csr: &CsrMatrix) -> GenResult<CsrBuffers> {
+        let (queue, tracer) = (&ocl_env.queue, &ocl_env.tracer);
+        Ok(CsrBuffers {
+            row_offsets: MatrixBuffer::with_data(queue, strategy, &csr.row_offsets, Access::ReadOnly, tracer, "row offsets")?,
+            col_indices: MatrixBuffer::with_data(queue, strategy, &csr.col_indices, Access::ReadOnly, tracer, "column indices")?,
+            values: MatrixBuffer::with_data(queue, strategy, &csr.values, Access::ReadOnly, tracer, "values")?
+        })
+    }
+}
+
+fn print_matrix_summary(csr: &CsrMatrix) {
+    println!("{}x{} matrix, {} nonzeros ({:.4}% dense), rows of up to {} entries ({:.1} on average)",
+             csr.rows, csr.cols, csr.nnz(), csr.nnz() as f64 / (csr.rows as f64 * csr.cols as f64).max(1.0) * 100.0,
+             csr.max_row_len(), csr.nnz() as f64 / csr.rows.max(1) as f64);
+}
+
+/* Without nonzeros the CSR and ELL arrays are empty, and OpenCL has no zero-length buffers */
+fn skip_empty(csr: &CsrMatrix, workload: &str) -> bool {
+    if csr.nnz() == 0 {
+        println!("The matrix has no nonzeros; skipping {}", workload);
+    }
+    csr.nnz() == 0
+}
+
+pub fn run_spmv(args: &cli::Args, ocl_env: &OclEnv, strategy: MemStrategy, peaks: &Peaks) -> GenResult<()> {
+    let (queue, tracer) = (&ocl_env.queue, &ocl_env.tracer);
+    let csr = load_matrix(args)?;
+    let ell = EllMatrix::from_csr(&csr);
+    let local_size = workload::local_size_1d(args, ocl_env);
+    println!("===\nRunning SpMV, local size {}, memory strategy: {}", local_size, strategy);
+    print_matrix_summary(&csr);
+    if skip_empty(&csr, "SpMV") {
+        return Ok(());
+    }
+
+    let x = batch::generate_matrix(csr.cols as usize, 12);
+    let expected = csr.spmv(&x);
+    let csr_buffers = CsrBuffers::new(ocl_env, strategy, &csr)?;
+    let buffer_x = MatrixBuffer::with_data(queue, strategy, &x, Access::ReadOnly, tracer, "x")?;
+    let buffer_y = MatrixBuffer::<f32>::new(queue, strategy, csr.rows as usize, Access::ReadWrite)?;
+    let program = build_sparse(ocl_env, local_size, ell.width)?;
+
+    let vector_width = ::std::cmp::min(32, local_size);
+    let subgroup_size = ocl_env.subgroup_support.sizes.iter().cloned().min().unwrap_or(32);
+    let mut runs = vec![("spmv_csr_scalar", csr.rows), ("spmv_csr_vector", csr.rows * vector_width)];
+    if ocl_env.subgroup_support.mode != SubgroupMode::Emulated {
+        runs.push(("spmv_csr_subgroup", csr.rows * subgroup_size));
+    }
+    else {
+        println!("No subgroup functions on this device, skipping spmv_csr_subgroup");
+    }
+
+    for &(kernel_name, work_items) in runs.iter() {
+        println!("---\n{}", kernel_name);
+        buffer_y.write(queue, &vec![0.0f32; csr.rows as usize], tracer, "y (reset)")?;
+        let kernel = Kernel::builder()
+            .queue(queue.clone())
+            .program(&program).name(kernel_name)
+            .arg(&csr_buffers.row_offsets.buffer).arg(&csr_buffers.col_indices.buffer).arg(&csr_buffers.values.buffer)
+            .arg(&buffer_x.buffer).arg(&buffer_y.buffer).arg(csr.rows)
+            .build()?;
+        let event = workload::enqueue_timed(ocl_env, &kernel, kernel_name, [ceil_divisible_by(work_items, local_size)], [local_size])?;
+        let mut actual = vec![0.0f32; csr.rows as usize];
+        buffer_y.read(queue, &mut actual, tracer, "y")?;
+        verify_results(&expected, &actual, 1);
+        workload::print_perf(peaks, get_execution_time_ns(&event)?, Some(2 * csr.nnz() as u64), csr.spmv_bytes());
+    }
+
+    println!("---\nspmv_ell, {} entries per row, {:.2}x the nonzeros stored", ell.width, ell.padding_ratio(csr.nnz()));
+    let ell_col_indices = MatrixBuffer::with_data(queue, strategy, &ell.col_indices, Access::ReadOnly, tracer, "ELL column indices")?;
+    let ell_values = MatrixBuffer::with_data(queue, strategy, &ell.values, Access::ReadOnly, tracer, "ELL values")?;
+    buffer_y.write(queue, &vec![0.0f32; csr.rows as usize], tracer, "y (reset)")?;
+    let kernel = Kernel::builder()
+        .queue(queue.clone())
+        .program(&program).name("spmv_ell")
+        .arg(&ell_col_indices.buffer).arg(&ell_values.buffer).arg(&buffer_x.buffer).arg(&buffer_y.buffer).arg(csr.rows)
+        .build()?;
+    let event = workload::enqueue_timed(ocl_env, &kernel, "spmv_ell", [ceil_divisible_by(csr.rows, local_size)], [local_size])?;
+    let mut actual = vec![0.0f32; csr.rows as usize];
+    buffer_y.read(queue, &mut actual, tracer, "y")?;
+    verify_results(&expected, &actual, 1);
+    /* Padding is real traffic, but only the nonzeros count as useful work and bytes */
+    workload::print_perf(peaks, get_execution_time_ns(&event)?, Some(2 * csr.nnz() as u64), csr.spmv_bytes());
+    Ok(())
+}
+
+/* Sparse A (m-by-n) times a generated dense n-by-p B */
+pub fn run_spmm(args: &cli::Args, ocl_env: &OclEnv, strategy: MemStrategy, peaks: &Peaks) -> GenResult<()> {
+    let (queue, tracer, p, tile_size) = (&ocl_env.queue, &ocl_env.tracer, args.p, args.tile_size);
+    let csr = load_matrix(args)?;
+    println!("===\nRunning SpMM with a dense {}x{} B, memory strategy: {}", csr.cols, p, strategy);
+    print_matrix_summary(&csr);
+    if skip_empty(&csr, "SpMM") {
+        return Ok(());
+    }
+
+    let b = batch::generate_matrix(csr.cols as usize * p as usize, 13);
+    let expected = csr.spmm(&b, p);
+    let csr_buffers = CsrBuffers::new(ocl_env, strategy, &csr)?;
+    let buffer_b = MatrixBuffer::with_data(queue, strategy, &b, Access::ReadOnly, tracer, "B")?;
+    let buffer_c = MatrixBuffer::<f32>::new(queue, strategy, expected.len(), Access::WriteOnly)?;
+    /* spmv_ell is unused here, any width compiles */
+    let program = build_sparse(ocl_env, workload::local_size_1d(args, ocl_env), 1)?;
+
+    let kernel = Kernel::builder()
+        .queue(queue.clone())
+        .program(&program).name("spmm_csr")
+        .arg(&csr_buffers.row_offsets.buffer).arg(&csr_buffers.col_indices.buffer).arg(&csr_buffers.values.buffer)
+        .arg(&buffer_b.buffer).arg(&buffer_c.buffer).arg(csr.rows).arg(p)
+        .build()?;
+    let global_size = [ceil_divisible_by(p, tile_size), ceil_divisible_by(csr.rows, tile_size)];
+    let event = workload::enqueue_timed(ocl_env, &kernel, "spmm_csr", global_size, [tile_size, tile_size])?;
+    let mut actual = vec![0.0f32; expected.len()];
+    buffer_c.read(queue, &mut actual, tracer, "C")?;
+    verify_results(&expected, &actual, p);
+
+    let bytes = csr.nnz() as u64 * 8 + (csr.rows as u64 + 1) * 4 + (b.len() + expected.len()) as u64 * 4;
+    workload::print_perf(peaks, get_execution_time_ns(&event)?, Some(2 * csr.nnz() as u64 * p as u64), bytes);
+    Ok(())
+}
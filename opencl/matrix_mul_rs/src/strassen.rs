@@ -0,0 +1,260 @@
+use std::{cmp, time::Instant};
+use ocl::{Kernel, Event, Program};
+use gen_error::GenResult;
+use memory::{self, MemStrategy, MatrixBuffer, Access};
+use accuracy::{self, ErrorStats};
+use epilogue::Epilogue;
+use workload::{self, Peaks};
+use {cli, coverage, batch, OclEnv, build_ocl_program, build_gemm_kernel, get_execution_time_ns, ceil_divisible_by};
+
+/* A rows x cols block of a row-major matrix with `stride` elements per row */
+#[derive(Clone, Copy)]
+struct View<'a> {
+    buffer: &'a MatrixBuffer,
+    offset: u32,
+    stride: u32,
+    rows: u32,
+    cols: u32
+}
+
+impl<'a> View<'a> {
+    fn whole(buffer: &'a MatrixBuffer, rows: u32, cols: u32) -> View<'a> {
+        View { buffer, offset: 0, stride: cols, rows, cols }
+    }
+
+    /* Quadrants 11, 12, 21 and 22; rows and cols must be even */
+    fn quadrants(&self) -> [View<'a>; 4] {
+        let (rows, cols) = (self.rows / 2, self.cols / 2);
+        let quadrant = |row: u32, col: u32| View { offset: self.offset + row * rows * self.stride + col * cols, rows, cols, ..*self };
+        [quadrant(0, 0), quadrant(0, 1), quadrant(1, 0), quadrant(1, 1)]
+    }
+
+    /* Whether the GEMM kernels can take the block's buffer as it is */
+    fn is_packed(&self, width: u32) -> bool {
+        self.offset == 0 && self.stride == self.cols && self.cols == width
+    }
+}
+
+/* Strassen-Winograd on top of a base GEMM kernel: `levels` recursive splits, each replacing 8 block products
+ * with 7 and 15 block additions, after which the blocks are multiplied by the base kernel */
+pub fn run_strassen(args: &cli::Args, ocl_env: &OclEnv, strategy: MemStrategy, peaks: &Peaks) -> GenResult<()> {
+    let (queue, tracer, tile_size) = (&ocl_env.queue, &ocl_env.tracer, args.tile_size);
+    let (m, n, p) = (args.m, args.n, args.p);
+    println!("===\nRunning Strassen-Winograd on {}x{} by {}x{}, memory strategy: {}", m, n, n, p, strategy);
+    if tile_size * tile_size > ocl_env.max_work_group_size {
+        println!("Local work size exceeds device limits; skipping Strassen-Winograd.");
+        return Ok(());
+    }
+
+    let (a, b) = (batch::generate_matrix((m * n) as usize, 11), batch::generate_matrix((n * p) as usize, 12));
+    let reference_start = Instant::now();
+    let expected = accuracy::cpu_gemm_f64(&a, &b, m, n, p);
+    println!("f64 CPU reference took {:.3} [ms]", memory::duration_ms(reference_start.elapsed()));
+    let block_program = build_ocl_program(ocl_env, String::new(), "", "strassen.cl")?;
+    let flops = (2 * (n as u64) - 1) * (m as u64) * (p as u64);
+
+    let mut base_kernels = vec!["tiled"];
+    if tile_size % 4 == 0 {
+        base_kernels.push("wideloads");
+    }
+    else {
+        println!("tile_size is not divisible by 4; skipping wideloads");
+    }
+    for base_kernel in base_kernels {
+        let src_filename = format!("{}.cl", base_kernel);
+        let gemm_defs = format!("#define TILE_SIZE {}\n{}{}", tile_size, coverage::source_defines(false), Epilogue::source_defines(None));
+        let gemm_program = build_ocl_program(ocl_env, gemm_defs, "", &src_filename)?;
+
+        /* Zero levels is the base kernel on its own, which the recursive runs are compared against */
+        let mut classical: Option<(u64, ErrorStats)> = None;
+        let mut level_counts = vec![0];
+        level_counts.extend(args.strassen_cutoffs.iter().map(|&cutoff| levels_for(cmp::max(m, cmp::max(n, p)), cutoff)).filter(|&levels| levels > 0));
+        level_counts.sort();
+        level_counts.dedup();
+        for levels in level_counts {
+            /* Every split halves the dimensions, so they are padded to a multiple of 2^levels */
+            let (m_pad, n_pad, p_pad) = (ceil_divisible_by(m, 1 << levels), ceil_divisible_by(n, 1 << levels), ceil_divisible_by(p, 1 << levels));
+            if levels == 0 {
+                println!("---\n{}", base_kernel);
+            }
+            else {
+                println!("---\n{} with {} Strassen-Winograd level(s): {} base products of {}x{}x{}{}", base_kernel, levels, 7u64.pow(levels),
+                         m_pad >> levels, n_pad >> levels, p_pad >> levels,
+                         if (m_pad, n_pad, p_pad) != (m, n, p) { format!(", padded to {}x{}x{}", m_pad, n_pad, p_pad) } else { String::new() });
+            }
+            let buffer_a = MatrixBuffer::with_data(queue, strategy, &pad(&a, m, n, m_pad, n_pad), Access::ReadOnly, tracer, "A (padded)")?;
+            let buffer_b = MatrixBuffer::with_data(queue, strategy, &pad(&b, n, p, n_pad, p_pad), Access::ReadOnly, tracer, "B (padded)")?;
+            let buffer_c = MatrixBuffer::new(queue, strategy, (m_pad * p_pad) as usize, Access::ReadWrite)?;
+
+            let mut strassen = Strassen { ocl_env, strategy, tile_size, base_kernel, gemm_program: &gemm_program,
+                                          block_program: &block_program, events: Vec::new() };
+            let wall_start = Instant::now();
+            strassen.multiply(View::whole(&buffer_a, m_pad, n_pad), View::whole(&buffer_b, n_pad, p_pad), View::whole(&buffer_c, m_pad, p_pad), levels)?;
+            let wall_ms = memory::duration_ms(wall_start.elapsed());
+
+            let mut c_padded = vec![0.0f32; (m_pad * p_pad) as usize];
+            buffer_c.read(queue, &mut c_padded, tracer, "C (padded)")?;
+            let actual = c_padded.chunks(p_pad as usize).take(m as usize).flat_map(|row| row[..p as usize].iter().cloned()).collect::<Vec<_>>();
+            let errors = ErrorStats::measure(&expected, &actual);
+            errors.print();
+
+            let time_ns = strassen.events.iter().map(get_execution_time_ns).sum::<GenResult<u64>>()?;
+            println!("{} kernel launches, {:.3} [ms] including host orchestration", strassen.events.len(), wall_ms);
+            /* Effective GFLOPS count the classical operations, so they are comparable across levels */
+            workload::print_perf(peaks, time_ns, Some(flops), 4 * (m * n + n * p + m * p) as u64);
+            match classical {
+                Some((classical_ns, ref classical_errors)) => {
+                    /* The classical result may be exact, e.g. for small integer-valued inputs */
+                    let growth = |error, classical_error| if classical_error > 0.0 { format!("{:.2}x", error / classical_error) } else { "-".to_owned() };
+                    println!("Speedup over {} alone: {:.2}x; error growth: {} max, {} rms", base_kernel, classical_ns as f64 / time_ns as f64,
+                             growth(errors.max_abs, classical_errors.max_abs), growth(errors.rms, classical_errors.rms));
+                },
+                None => classical = Some((time_ns, errors))
+            }
+        }
+    }
+    Ok(())
+}
+
+/* Smallest number of splits that brings the largest dimension down to the cutoff */
+fn levels_for(max_dim: u32, cutoff: u32) -> u32 {
+    let mut levels = 0;
+    while (max_dim + (1 << levels) - 1) >> levels > cutoff {
+        levels += 1;
+    }
+    levels
+}
+
+/* Copies a rows x cols matrix into the top left corner of a zero rows_pad x cols_pad matrix */
+fn pad(matrix: &[f32], rows: u32, cols: u32, rows_pad: u32, cols_pad: u32) -> Vec<f32> {
+    if (rows, cols) == (rows_pad, cols_pad) {
+        return matrix.to_vec();
+    }
+    let mut padded = vec![0.0f32; (rows_pad * cols_pad) as usize];
+    for (padded_row, row) in padded.chunks_mut(cols_pad as usize).zip(matrix.chunks(cols as usize)) {
+        padded_row[..cols as usize].copy_from_slice(row);
+    }
+    padded
+}
+
+struct Strassen<'a> {
+    ocl_env: &'a OclEnv,
+    strategy: MemStrategy,
+    tile_size: u32,
+    base_kernel: &'a str,
+    gemm_program: &'a Program,
+    block_program: &'a Program,
+    events: Vec<Event>
+}
+
+impl<'a> Strassen<'a> {
+    /* C = A * B; the dimensions must be divisible by 2^levels */
+    fn multiply(&mut self, a: View, b: View, c: View, levels: u32) -> GenResult<()> {
+        if levels == 0 {
+            return self.base_multiply(a, b, c);
+        }
+        let queue = &self.ocl_env.queue;
+        let ([a11, a12, a21, a22], [b11, b12, b21, b22], [c11, c12, c21, c22]) = (a.quadrants(), b.quadrants(), c.quadrants());
+        let (rows, inner, cols) = (a11.rows, a11.cols, b11.cols);
+        let temporaries = |count: usize, len: u32| -> GenResult<Vec<MatrixBuffer>> {
+            (0..count).map(|_| MatrixBuffer::new(queue, self.strategy, len as usize, Access::ReadWrite)).collect()
+        };
+        let (s, t, products) = (temporaries(4, rows * inner)?, temporaries(4, inner * cols)?, temporaries(7, rows * cols)?);
+        let s = s.iter().map(|buffer| View::whole(buffer, rows, inner)).collect::<Vec<_>>();
+        let t = t.iter().map(|buffer| View::whole(buffer, inner, cols)).collect::<Vec<_>>();
+        let mp = products.iter().map(|buffer| View::whole(buffer, rows, cols)).collect::<Vec<_>>();
+
+        self.add(a21, a22, s[0], 1.0)?;
+        self.add(s[0], a11, s[1], -1.0)?;
+        self.add(a11, a21, s[2], -1.0)?;
+        self.add(a12, s[1], s[3], -1.0)?;
+        self.add(b12, b11, t[0], -1.0)?;
+        self.add(b22, t[0], t[1], -1.0)?;
+        self.add(b22, b12, t[2], -1.0)?;
+        self.add(t[1], b21, t[3], -1.0)?;
+
+        self.multiply(a11, b11, mp[0], levels - 1)?;
+        self.multiply(a12, b21, mp[1], levels - 1)?;
+        self.multiply(s[3], b22, mp[2], levels - 1)?;
+        self.multiply(a22, t[3], mp[3], levels - 1)?;
+        self.multiply(s[0], t[0], mp[4], levels - 1)?;
+        self.multiply(s[1], t[1], mp[5], levels - 1)?;
+        self.multiply(s[2], t[2], mp[6], levels - 1)?;
+
+        /* U2 = M1 + M6, U3 = U2 + M7 and U4 = U2 + M5 are kept in M6 and M7 */
+        self.add(mp[0], mp[1], c11, 1.0)?;
+        self.add(mp[5], mp[0], mp[5], 1.0)?;
+        self.add(mp[5], mp[6], mp[6], 1.0)?;
+        self.add(mp[5], mp[4], mp[5], 1.0)?;
+        self.add(mp[5], mp[2], c12, 1.0)?;
+        self.add(mp[6], mp[3], c21, -1.0)?;
+        self.add(mp[6], mp[4], c22, 1.0)
+    }
+
+    fn base_multiply(&mut self, a: View, b: View, c: View) -> GenResult<()> {
+        let (queue, tile_size) = (&self.ocl_env.queue, self.tile_size);
+        let (m, n, p) = (a.rows, a.cols, b.cols);
+        /* wideloads reads rows padded to whole tiles */
+        let wide = self.base_kernel == "wideloads";
+        let (a_width, b_width) = if wide { (ceil_divisible_by(n, tile_size), ceil_divisible_by(p, tile_size)) } else { (n, p) };
+        let a_packed = self.pack(a, a_width)?;
+        let b_packed = self.pack(b, b_width)?;
+        let c_packed = if c.is_packed(p) { None } else { Some(MatrixBuffer::new(queue, self.strategy, (m * p) as usize, Access::ReadWrite)?) };
+
+        let buffers = [&a_packed.as_ref().unwrap_or(a.buffer).buffer, &b_packed.as_ref().unwrap_or(b.buffer).buffer,
+                       &c_packed.as_ref().unwrap_or(c.buffer).buffer];
+        let kernel = build_gemm_kernel(queue, self.gemm_program, self.base_kernel, buffers, [m, n, p], None, None)?;
+        let mut global_size = [ceil_divisible_by(m, tile_size), ceil_divisible_by(p, tile_size)];
+        let mut local_size = [tile_size, tile_size];
+        if wide { global_size[1] /= 4; local_size[1] /= 4; }
+        self.events.push(workload::enqueue_timed(self.ocl_env, &kernel, self.base_kernel, global_size, local_size)?);
+
+        match c_packed {
+            Some(ref c_packed) => self.copy(View::whole(c_packed, m, p), c, p),
+            None => Ok(())
+        }
+    }
+
+    /* The block itself if the GEMM kernels can read it, otherwise a packed copy `width` elements wide */
+    fn pack(&mut self, view: View, width: u32) -> GenResult<Option<MatrixBuffer>> {
+        if view.is_packed(width) {
+            return Ok(None);
+        }
+        let packed = MatrixBuffer::new(&self.ocl_env.queue, self.strategy, (view.rows * width) as usize, Access::ReadWrite)?;
+        self.copy(view, View::whole(&packed, view.rows, width), width)?;
+        Ok(Some(packed))
+    }
+
+    /* z = x + y_scale * y */
+    fn add(&mut self, x: View, y: View, z: View, y_scale: f32) -> GenResult<()> {
+        let kernel = Kernel::builder()
+            .queue(self.ocl_env.queue.clone())
+            .program(self.block_program).name("block_add")
+            .arg(&x.buffer.buffer).arg(x.offset).arg(x.stride)
+            .arg(&y.buffer.buffer).arg(y.offset).arg(y.stride)
+            .arg(&z.buffer.buffer).arg(z.offset).arg(z.stride)
+            .arg(z.rows).arg(z.cols).arg(y_scale)
+            .build()?;
+        let name = if y_scale > 0.0 { "block add" } else { "block subtract" };
+        self.launch_block(&kernel, name, z.rows, z.cols)
+    }
+
+    /* Copies x into the first x.cols columns of z and zeroes the rest up to z_cols */
+    fn copy(&mut self, x: View, z: View, z_cols: u32) -> GenResult<()> {
+        let kernel = Kernel::builder()
+            .queue(self.ocl_env.queue.clone())
+            .program(self.block_program).name("block_copy")
+            .arg(&x.buffer.buffer).arg(x.offset).arg(x.stride)
+            .arg(&z.buffer.buffer).arg(z.offset).arg(z.stride)
+            .arg(x.rows).arg(x.cols).arg(z_cols)
+            .build()?;
+        self.launch_block(&kernel, "block copy", x.rows, z_cols)
+    }
+
+    fn launch_block(&mut self, kernel: &Kernel, name: &str, rows: u32, cols: u32) -> GenResult<()> {
+        let tile_size = self.tile_size;
+        let global_size = [ceil_divisible_by(rows, tile_size), ceil_divisible_by(cols, tile_size)];
+        self.events.push(workload::enqueue_timed(self.ocl_env, kernel, name, global_size, [tile_size, tile_size])?);
+        Ok(())
+    }
+}
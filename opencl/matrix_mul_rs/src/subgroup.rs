@@ -0,0 +1,185 @@
+use std::{cmp, mem, ptr, ffi::CString};
+use ocl::{Device, Kernel};
+use ocl::enums::{DeviceInfo, DeviceInfoResult};
+use ocl_core::{ffi, ClDeviceIdPtr};
+use gen_error::{GenResult, GenError};
+
+/* Vendor queries missing from ocl's DeviceInfo */
+const CL_DEVICE_SUB_GROUP_SIZES_INTEL: u32 = 0x4108;
+const CL_DEVICE_WARP_SIZE_NV: u32 = 0x4003;
+const CL_DEVICE_WAVEFRONT_WIDTH_AMD: u32 = 0x4043;
+const CL_KERNEL_MAX_SUB_GROUP_SIZE_FOR_NDRANGE: u32 = 0x2033;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SubgroupMode {
+    /* intel_sub_group_shuffle */
+    Intel,
+    /* sub_group_broadcast from cl_khr_subgroups */
+    Khr,
+    /* No subgroup functions (e.g. NVIDIA); lanes exchange values through __local memory */
+    Emulated
+}
+
+#[derive(Debug, Clone)]
+pub struct SubgroupSupport {
+    pub mode: SubgroupMode,
+    /* The kernel can demand a size via cl_intel_required_subgroup_size */
+    pub required_size: bool,
+    /* Sizes worth trying on this device, best guess first */
+    pub sizes: Vec<u32>,
+    /* Bytes of __local memory per work group, which bounds the emulated exchange buffer */
+    pub local_mem_size: u64
+}
+
+impl SubgroupSupport {
+    pub fn query(device: &Device) -> GenResult<SubgroupSupport> {
+        let extensions = match device.info(DeviceInfo::Extensions)? {
+            DeviceInfoResult::Extensions(extensions) => extensions,
+            _ => String::new()
+        };
+        let has_extension = |name: &str| extensions.split_whitespace().any(|e| e == name);
+        let local_mem_size = match device.info(DeviceInfo::LocalMemSize)? {
+            DeviceInfoResult::LocalMemSize(size) => size,
+            _ => 0
+        };
+
+        if has_extension("cl_intel_subgroups") {
+            let required_sizes = if has_extension("cl_intel_required_subgroup_size") {
+                device_info_size_ts(device, CL_DEVICE_SUB_GROUP_SIZES_INTEL)
+            }
+            else { Vec::new() };
+            return Ok(if required_sizes.is_empty() {
+                /* The compiler picks SIMD8 or SIMD16; the size is checked after the build */
+                SubgroupSupport { mode: SubgroupMode::Intel, required_size: false, sizes: vec![8, 16], local_mem_size }
+            }
+            else {
+                SubgroupSupport { mode: SubgroupMode::Intel, required_size: true, sizes: required_sizes.iter().map(|&s| s as u32).collect(), local_mem_size }
+            });
+        }
+
+        let hardware_width = device_info_u32(device, CL_DEVICE_WAVEFRONT_WIDTH_AMD)
+            .or_else(|| device_info_u32(device, CL_DEVICE_WARP_SIZE_NV));
+        let mode = if has_extension("cl_khr_subgroups") { SubgroupMode::Khr } else { SubgroupMode::Emulated };
+        let sizes = match hardware_width {
+            Some(width) => vec![width],
+            None => vec![64, 32]
+        };
+        Ok(SubgroupSupport { mode, required_size: false, sizes, local_mem_size })
+    }
+
+    pub fn source_defines(&self, subgroup_size: u32) -> String {
+        let mode = match self.mode {
+            SubgroupMode::Intel => "SUBGROUP_INTEL",
+            SubgroupMode::Khr => "SUBGROUP_KHR",
+            SubgroupMode::Emulated => "SUBGROUP_EMULATED"
+        };
+        let mut defines = format!("#define SUBGROUP_SIZE {}\n#define {}\n", subgroup_size, mode);
+        if self.required_size {
+            defines += "#define SUBGROUP_REQUIRED_SIZE\n";
+        }
+        defines
+    }
+}
+
+/* Work split of the subgroups kernel: each work item computes a rows x cols block of C */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockShape {
+    pub rows: u32,
+    pub cols: u32
+}
+
+impl BlockShape {
+    /* Parses "ROWSxCOLS", e.g. "8x4" */
+    pub fn parse(s: &str) -> GenResult<BlockShape> {
+        let dims = s.split('x').map(|d| d.trim().parse::<u32>()).collect::<Result<Vec<_>, _>>()?;
+        match dims.as_slice() {
+            &[rows, cols] if rows > 0 && [1, 2, 4, 8].contains(&cols) => Ok(BlockShape { rows, cols }),
+            _ => gen_error_format!("Invalid block shape \"{}\": expected ROWSxCOLS with COLS one of 1, 2, 4, 8", s)
+        }
+    }
+}
+
+impl Default for BlockShape {
+    fn default() -> BlockShape { BlockShape { rows: 8, cols: 4 } }
+}
+
+pub struct SubgroupLaunch {
+    pub kernel_defs: String,
+    pub global_size: [u32; 2],
+    pub local_size: [u32; 2]
+}
+
+/* None if the emulated exchange buffer doesn't fit in local memory even with one subgroup per group */
+pub fn plan_launch(support: &SubgroupSupport, subgroup_size: u32, block: BlockShape, max_work_group_size: u32,
+                   m: u32, p_padded: u32) -> Option<SubgroupLaunch> {
+    let mut subgroups_per_group = cmp::max(1, cmp::min(8, max_work_group_size / subgroup_size));
+    if support.mode == SubgroupMode::Emulated {
+        /* a_shared holds BLOCK_ROWS x SUBGROUP_SIZE vectors of BLOCK_COLS floats per subgroup */
+        let subgroup_bytes = block.rows as u64 * subgroup_size as u64 * block.cols as u64 * 4;
+        subgroups_per_group = cmp::min(subgroups_per_group as u64, support.local_mem_size / subgroup_bytes) as u32;
+        if subgroups_per_group == 0 {
+            return None;
+        }
+    }
+    let global_x = ceil_div(p_padded, block.cols);
+    let global_y = ceil_div(m, block.rows);
+    Some(SubgroupLaunch {
+        kernel_defs: format!("{}#define SUBGROUPS_PER_GROUP {}\n#define BLOCK_ROWS {}\n#define BLOCK_COLS {}\n",
+                             support.source_defines(subgroup_size), subgroups_per_group, block.rows, block.cols),
+        global_size: [ceil_div(global_x, subgroup_size) * subgroup_size,
+                      ceil_div(global_y, subgroups_per_group) * subgroups_per_group],
+        local_size: [subgroup_size, subgroups_per_group]
+    })
+}
+
+type GetKernelSubGroupInfoFn = extern "C" fn(ffi::cl_kernel, ffi::cl_device_id, u32, usize, *const ffi::c_void,
+                                             usize, *mut ffi::c_void, *mut usize) -> i32;
+
+/* Subgroup size the built kernel will actually run with, if the driver can tell. The query is looked up as
+ * clGetKernelSubGroupInfoKHR, which cl_khr_subgroups and cl_intel_subgroups provide without requiring OpenCL 2.1 */
+pub fn kernel_subgroup_size(kernel: &Kernel, device: &Device, local_size: [u32; 2]) -> Option<u32> {
+    let platform = match device.info(DeviceInfo::Platform) {
+        Ok(DeviceInfoResult::Platform(platform)) => platform,
+        _ => return None
+    };
+    let query_fn = unsafe {
+        let name = CString::new("clGetKernelSubGroupInfoKHR").unwrap();
+        let address = ffi::clGetExtensionFunctionAddressForPlatform(platform.as_ptr(), name.as_ptr());
+        if address.is_null() { return None; }
+        mem::transmute::<*mut ffi::c_void, GetKernelSubGroupInfoFn>(address)
+    };
+
+    let local_size = [local_size[0] as usize, local_size[1] as usize];
+    let mut size: usize = 0;
+    let status = query_fn(kernel.as_core().as_ptr(), device.as_ptr(),
+        CL_KERNEL_MAX_SUB_GROUP_SIZE_FOR_NDRANGE,
+        mem::size_of_val(&local_size), local_size.as_ptr() as *const _,
+        mem::size_of::<usize>(), &mut size as *mut usize as *mut _, ptr::null_mut());
+    if status == ffi::CL_SUCCESS as i32 && size > 0 { Some(size as u32) } else { None }
+}
+
+fn device_info_u32(device: &Device, param: u32) -> Option<u32> {
+    let mut value: u32 = 0;
+    let status = unsafe {
+        ffi::clGetDeviceInfo(device.as_ptr(), param, mem::size_of::<u32>(), &mut value as *mut u32 as *mut _, ptr::null_mut())
+    };
+    if status == ffi::CL_SUCCESS as i32 && value > 0 { Some(value) } else { None }
+}
+
+fn device_info_size_ts(device: &Device, param: u32) -> Vec<usize> {
+    let mut bytes: usize = 0;
+    unsafe {
+        if ffi::clGetDeviceInfo(device.as_ptr(), param, 0, ptr::null_mut(), &mut bytes) != ffi::CL_SUCCESS as i32 {
+            return Vec::new();
+        }
+        let mut values = vec![0usize; bytes / mem::size_of::<usize>()];
+        if ffi::clGetDeviceInfo(device.as_ptr(), param, bytes, values.as_mut_ptr() as *mut _, ptr::null_mut()) != ffi::CL_SUCCESS as i32 {
+            return Vec::new();
+        }
+        values
+    }
+}
+
+fn ceil_div(n: u32, by: u32) -> u32 {
+    (n + by - 1) / by
+}
@@ -0,0 +1,158 @@
+use std::{cell::RefCell, fs::File, io::BufWriter, io::prelude::*, time::Instant};
+use ocl::{Event, Queue};
+use ocl::enums::{EventInfo, EventInfoResult, ProfilingInfo, ProfilingInfoResult};
+use gen_error::{GenResult, GenError};
+use json;
+
+const HOST_PID: u32 = 0;
+
+/* Device timestamps come from the device clock; each queue's offset to the host clock is estimated
+ * from the first command recorded on it, assuming it is recorded right after the command completed */
+struct QueueTrack {
+    queue_ptr: usize,
+    pid: u32,
+    tid: u32,
+    device_offset_ns: Option<i64>
+}
+
+struct TraceEvent {
+    name: String,
+    category: &'static str,
+    pid: u32,
+    tid: u32,
+    ts_ns: i64,
+    dur_ns: i64,
+    args: Vec<(&'static str, String)>
+}
+
+/* Collects host-side spans and profiled OpenCL commands and writes them in the Chrome trace event
+ * format (chrome://tracing, https://ui.perfetto.dev). The host is one process, each device another,
+ * with a thread track per command queue. When disabled, recording does nothing. */
+pub struct Tracer {
+    enabled: bool,
+    start: Instant,
+    tracks: RefCell<Vec<QueueTrack>>,
+    /* (pid, device name) and (pid, tid, queue name) for track metadata */
+    device_names: RefCell<Vec<(u32, String)>>,
+    queue_names: RefCell<Vec<(u32, u32, String)>>,
+    events: RefCell<Vec<TraceEvent>>
+}
+
+impl Tracer {
+    pub fn new(enabled: bool) -> Tracer {
+        Tracer {
+            enabled,
+            start: Instant::now(),
+            tracks: RefCell::new(Vec::new()),
+            device_names: RefCell::new(Vec::new()),
+            queue_names: RefCell::new(Vec::new()),
+            events: RefCell::new(Vec::new())
+        }
+    }
+
+    /* Host timestamp to pass to `host_span` once the traced work is done */
+    pub fn now(&self) -> i64 {
+        let elapsed = self.start.elapsed();
+        elapsed.as_secs() as i64 * 1_000_000_000 + elapsed.subsec_nanos() as i64
+    }
+
+    pub fn add_queue(&self, queue: &Queue, device_name: &str, queue_name: &str) {
+        if !self.enabled { return; }
+        let mut device_names = self.device_names.borrow_mut();
+        let pid = match device_names.iter().find(|&&(_, ref name)| name == device_name) {
+            Some(&(pid, _)) => pid,
+            None => {
+                let pid = device_names.len() as u32 + 1;
+                device_names.push((pid, device_name.to_owned()));
+                pid
+            }
+        };
+        let mut tracks = self.tracks.borrow_mut();
+        let tid = tracks.iter().filter(|t| t.pid == pid).count() as u32;
+        tracks.push(QueueTrack { queue_ptr: queue.as_ptr() as usize, pid, tid, device_offset_ns: None });
+        self.queue_names.borrow_mut().push((pid, tid, queue_name.to_owned()));
+    }
+
+    pub fn host_span(&self, name: &str, category: &'static str, start_ns: i64) {
+        self.host_span_with_args(name, category, start_ns, Vec::new());
+    }
+
+    pub fn host_span_with_args(&self, name: &str, category: &'static str, start_ns: i64, args: Vec<(&'static str, String)>) {
+        if !self.enabled { return; }
+        let end_ns = self.now();
+        self.events.borrow_mut().push(TraceEvent {
+            name: name.to_owned(), category, pid: HOST_PID, tid: 0, ts_ns: start_ns, dur_ns: end_ns - start_ns, args
+        });
+    }
+
+    /* Records a completed command; the event must come from a queue with profiling enabled */
+    pub fn command(&self, name: &str, category: &'static str, event: &Event) -> GenResult<()> {
+        if !self.enabled || event.is_empty() { return Ok(()); }
+
+        let queue_ptr = match event.info(EventInfo::CommandQueue)? {
+            EventInfoResult::CommandQueue(queue) => queue.as_ptr() as usize,
+            _ => return gen_error_format!("Unable to obtain the command queue of {}", name)
+        };
+        let timestamps = [ProfilingInfo::Queued, ProfilingInfo::Submit, ProfilingInfo::Start, ProfilingInfo::End].iter()
+            .map(|&info| Ok(match event.profiling_info(info)? {
+                ProfilingInfoResult::Queued(ns) | ProfilingInfoResult::Submit(ns)
+                | ProfilingInfoResult::Start(ns) | ProfilingInfoResult::End(ns) => ns as i64
+            }))
+            .collect::<GenResult<Vec<i64>>>()?;
+        let (queued, submit, start, end) = (timestamps[0], timestamps[1], timestamps[2], timestamps[3]);
+
+        let mut tracks = self.tracks.borrow_mut();
+        let track = tracks.iter_mut().find(|t| t.queue_ptr == queue_ptr)
+            .ok_or(GenError::from(format!("{} was enqueued on a queue that is not traced", name)))?;
+        let host_now = self.now();
+        let offset = *track.device_offset_ns.get_or_insert(host_now - end);
+
+        self.events.borrow_mut().push(TraceEvent {
+            name: name.to_owned(), category, pid: track.pid, tid: track.tid,
+            ts_ns: start + offset, dur_ns: end - start,
+            args: vec![("queued_to_start_ms", format!("{:.6}", (start - queued) as f64 / 1_000_000.0)),
+                       ("submit_to_start_ms", format!("{:.6}", (start - submit) as f64 / 1_000_000.0))]
+        });
+        Ok(())
+    }
+
+    /* `other_data` is a JSON object stored alongside the events (shown as trace metadata by the viewers) */
+    pub fn write(&self, filename: &str, other_data: &str) -> GenResult<()> {
+        let mut entries = Vec::new();
+        entries.push(metadata_event("process_name", HOST_PID, None, "host"));
+        entries.push(metadata_event("thread_name", HOST_PID, Some(0), "main"));
+        for &(pid, ref name) in self.device_names.borrow().iter() {
+            entries.push(metadata_event("process_name", pid, None, name));
+        }
+        for &(pid, tid, ref name) in self.queue_names.borrow().iter() {
+            entries.push(metadata_event("thread_name", pid, Some(tid), name));
+        }
+        for event in self.events.borrow().iter() {
+            let args = event.args.iter().map(|&(key, ref value)| (key, value.clone())).collect::<Vec<_>>();
+            entries.push(json::object(&[
+                ("name", json::string(&event.name)),
+                ("cat", json::string(event.category)),
+                ("ph", json::string("X")),
+                ("pid", event.pid.to_string()),
+                ("tid", event.tid.to_string()),
+                ("ts", format!("{:.3}", event.ts_ns as f64 / 1000.0)),
+                ("dur", format!("{:.3}", event.dur_ns as f64 / 1000.0)),
+                ("args", json::object(&args))
+            ]));
+        }
+
+        let mut out = BufWriter::new(File::create(filename).or(gen_error_format!("Unable to open {} for writing", filename))?);
+        write!(out, "{{\"displayTimeUnit\": \"ns\", \"otherData\": {}, \"traceEvents\": [\n{}\n]}}\n", other_data, entries.join(",\n"))?;
+        println!("Trace written to {}", filename);
+        Ok(())
+    }
+}
+
+fn metadata_event(kind: &str, pid: u32, tid: Option<u32>, name: &str) -> String {
+    let mut fields = vec![("name", json::string(kind)), ("ph", json::string("M")), ("pid", pid.to_string())];
+    if let Some(tid) = tid {
+        fields.push(("tid", tid.to_string()));
+    }
+    fields.push(("args", json::object(&[("name", json::string(name))])));
+    json::object(&fields)
+}
@@ -0,0 +1,130 @@
+use std::{cmp, thread, cell::RefCell, time::{Duration, Instant}};
+use ocl::{Queue, Event};
+use gen_error::{GenResult, GenError};
+use memory::duration_ms;
+use cli;
+
+/* Slowest rate a working kernel is assumed to reach, in operations per ns (1 GOPS). Far below any GPU,
+ * since a false timeout loses a result while a late one only costs waiting time. */
+const FLOOR_OPS_PER_NS: f64 = 1.0;
+/* Covers launch overhead, lazy compilation and clock ramp-up of small problems */
+const MIN_BUDGET_MS: u64 = 2000;
+const MAX_POLL_INTERVAL_MS: u64 = 50;
+
+thread_local! {
+    /* Kernels given up on by any watchdog on this thread (or whose wait failed) and not seen finishing since */
+    static RUNNING_ABANDONED: RefCell<Vec<Event>> = const { RefCell::new(Vec::new()) };
+}
+
+/* Whether a kernel given up on may still be running, in which case host memory it uses must not be freed */
+pub fn abandoned_kernel_running() -> bool {
+    RUNNING_ABANDONED.with(|events| {
+        let mut events = events.borrow_mut();
+        events.retain(|event| !event.is_complete().unwrap_or(false));
+        !events.is_empty()
+    })
+}
+
+fn note_running(event: &Event) {
+    RUNNING_ABANDONED.with(|events| events.borrow_mut().push(event.clone()));
+}
+
+/* Replaces blocking waits on kernel events. clWaitForEvents can't be interrupted, and with the driver's
+ * hang check disabled (see the README) a hung kernel would block the harness forever. */
+pub struct Watchdog {
+    fixed_budget: Option<Duration>,
+    scale: f64,
+    /* The last kernel given up on, while it hasn't finished: the queue is in order, so any command
+     * enqueued after it (including blocking transfers) would wait for it */
+    abandoned: RefCell<Option<(String, Event)>>
+}
+
+impl Watchdog {
+    pub fn new(args: &cli::Args) -> Watchdog {
+        Watchdog {
+            fixed_budget: args.kernel_timeout.map(Duration::from_millis),
+            scale: args.timeout_scale,
+            abandoned: RefCell::new(None)
+        }
+    }
+
+    /* Estimated budgets, as without --kernel-timeout and --timeout-scale */
+    pub fn estimated() -> Watchdog {
+        Watchdog { fixed_budget: None, scale: 1.0, abandoned: RefCell::new(None) }
+    }
+
+    /* Time a kernel performing `ops` operations (flops, or elements touched) may take */
+    pub fn budget(&self, ops: u64) -> Duration {
+        self.fixed_budget.unwrap_or_else(|| {
+            let estimate_ms = MIN_BUDGET_MS as f64 + ops as f64 / FLOOR_OPS_PER_NS / 1_000_000.0;
+            Duration::from_millis((estimate_ms * self.scale) as u64)
+        })
+    }
+
+    /* Polls the event until it completes (true) or the budget for `ops` runs out (false). A timed out
+     * kernel can't be cancelled; it is left running and blocks the queue until it finishes. A kernel whose
+     * wait failed is counted as still running as well, since nothing is known about it. */
+    pub fn wait(&self, queue: &Queue, event: &Event, name: &str, ops: u64) -> GenResult<bool> {
+        let completed = self.poll(queue, event, name, ops);
+        match completed {
+            Ok(true) => {},
+            Ok(false) => {
+                *self.abandoned.borrow_mut() = Some((name.to_owned(), event.clone()));
+                note_running(event);
+            },
+            Err(_) => note_running(event)
+        }
+        completed
+    }
+
+    fn poll(&self, queue: &Queue, event: &Event, name: &str, ops: u64) -> GenResult<bool> {
+        let (budget, start) = (self.budget(ops), Instant::now());
+        /* Unlike clWaitForEvents, polling doesn't submit the command by itself */
+        queue.flush()?;
+        let mut poll_interval = Duration::from_millis(1);
+        while !event.is_complete()? {
+            if start.elapsed() > budget {
+                println!("{} is still running after its budget of {:.0} [ms]; abandoning it", name, duration_ms(budget));
+                queue.flush()?;
+                return Ok(false);
+            }
+            thread::sleep(poll_interval);
+            poll_interval = cmp::min(poll_interval * 2, Duration::from_millis(MAX_POLL_INTERVAL_MS));
+        }
+        Ok(true)
+    }
+
+    /* For callers that can't record a timed out run: the timeout becomes an error */
+    pub fn wait_or_fail(&self, queue: &Queue, event: &Event, name: &str, ops: u64) -> GenResult<()> {
+        if self.wait(queue, event, name, ops)? { Ok(()) }
+        else { Err(GenError::from(format!("{} timed out", name))) }
+    }
+
+    /* Name of the abandoned kernel if it is still running, in which case nothing else can run.
+     * Once it finishes (e.g. it was merely slow) the queue is usable again. */
+    pub fn blocking_kernel(&self) -> GenResult<Option<String>> {
+        let mut abandoned = self.abandoned.borrow_mut();
+        let finished = match *abandoned {
+            Some((_, ref event)) => event.is_complete()?,
+            None => return Ok(None)
+        };
+        if finished {
+            *abandoned = None;
+            return Ok(None);
+        }
+        Ok(abandoned.as_ref().map(|&(ref name, _)| name.clone()))
+    }
+
+    /* Checked before enqueuing anything; prints what gets skipped otherwise */
+    pub fn queue_available(&self, what: &str) -> GenResult<bool> {
+        match self.blocking_kernel()? {
+            Some(kernel) => { println!("Skipping {}: the queue is still blocked by {}, which timed out", what, kernel); Ok(false) },
+            None => Ok(true)
+        }
+    }
+
+    /* Whether a kernel has been abandoned and not seen finishing since, i.e. a failure may be a timeout */
+    pub fn tripped(&self) -> bool {
+        self.abandoned.borrow().is_some()
+    }
+}
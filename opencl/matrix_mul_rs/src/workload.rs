@@ -0,0 +1,126 @@
+use std::fmt::Display;
+use ocl::{Kernel, Event, SpatialDims};
+use gen_error::GenResult;
+use memory::{MemStrategy, MatrixBuffer, Access};
+use cli::{self, Workload};
+use {chain, conv, precision, primitives, sparse, strassen, batch, OclEnv, build_ocl_program, get_execution_time_ns, ceil_divisible_by, MAX_PRINT_ERRORS};
+
+/* Generous bound on the operations of one work item, for the watchdog budget of kernels run through enqueue_timed */
+const WORK_ITEM_OPS: u64 = 1024;
+
+/* What measured rates are compared against */
+pub struct Peaks {
+    pub gflops: f64,
+    pub bandwidth_gbps: f64,
+    /* The bandwidth comes from copy_float4 rather than --peak-bandwidth */
+    pub measured: bool
+}
+
+impl Peaks {
+    pub fn new(args: &cli::Args, ocl_env: &OclEnv, strategy: MemStrategy) -> GenResult<Peaks> {
+        let bandwidth_gbps = match args.peak_bandwidth {
+            Some(bandwidth_gbps) => bandwidth_gbps,
+            None => measure_copy_bandwidth(args, ocl_env, strategy)?
+        };
+        Ok(Peaks { gflops: args.device_max_gflops, bandwidth_gbps, measured: args.peak_bandwidth.is_none() })
+    }
+}
+
+/* Runs one non-GEMM workload with buffers allocated according to the memory strategy. The peaks of each
+ * strategy are measured by the first workload using it and kept in `measured_peaks` for the others. */
+pub fn run(args: &cli::Args, ocl_env: &OclEnv, workload: Workload, strategy: MemStrategy, measured_peaks: &mut Vec<(MemStrategy, Peaks)>) -> GenResult<()> {
+    if !measured_peaks.iter().any(|&(measured, _)| measured == strategy) {
+        let peaks = Peaks::new(args, ocl_env, strategy)?;
+        measured_peaks.push((strategy, peaks));
+    }
+    let peaks = &measured_peaks.iter().find(|&&(measured, _)| measured == strategy).unwrap().1;
+    match workload {
+        Workload::Pool => conv::run_pooling(args, ocl_env, strategy, peaks),
+        Workload::Conv => conv::run_convolution(args, ocl_env, strategy, peaks),
+        Workload::Reduce => primitives::run_reduction(args, ocl_env, strategy, peaks),
+        Workload::Scan => primitives::run_scan(args, ocl_env, strategy, peaks),
+        Workload::Transpose => primitives::run_transpose(args, ocl_env, strategy, peaks),
+        Workload::Histogram => primitives::run_histogram(args, ocl_env, strategy, peaks),
+        Workload::Spmv => sparse::run_spmv(args, ocl_env, strategy, peaks),
+        Workload::Spmm => sparse::run_spmm(args, ocl_env, strategy, peaks),
+        Workload::Strassen => strassen::run_strassen(args, ocl_env, strategy, peaks),
+        Workload::Precision => precision::run_precision_study(args, ocl_env, strategy, peaks),
+        Workload::Chain => chain::run_chain(args, ocl_env, strategy, peaks),
+        Workload::Gemm => unreachable!("GEMM runs through run_gemm_kernels")
+    }
+}
+
+/* Enqueues a kernel, waits for it and records it in the trace; `name` labels the trace event */
+pub fn enqueue_timed<D: Into<SpatialDims> + Copy>(ocl_env: &OclEnv, kernel: &Kernel, name: &str, global_size: D, local_size: D) -> GenResult<Event> {
+    let work_items = global_size.into().to_len() as u64;
+    enqueue_timed_ops(ocl_env, kernel, name, global_size, local_size, work_items * WORK_ITEM_OPS)
+}
+
+/* For kernels whose work items may exceed WORK_ITEM_OPS; `ops` is the kernel's total, which sets the watchdog budget */
+pub fn enqueue_timed_ops<D: Into<SpatialDims> + Copy>(ocl_env: &OclEnv, kernel: &Kernel, name: &str, global_size: D, local_size: D, ops: u64) -> GenResult<Event> {
+    let mut event = Event::empty();
+    unsafe {
+        kernel.cmd().queue(&ocl_env.queue).global_work_size(global_size).local_work_size(local_size).enew(&mut event).enq()?;
+    }
+    ocl_env.watchdog.wait_or_fail(&ocl_env.queue, &event, name, ops)?;
+    ocl_env.tracer.command(name, "kernel", &event)?;
+    Ok(event)
+}
+
+/* Work group size of 1D kernels: tile_size^2 like the 2D ones, capped by the device and rounded down to a power of two */
+pub fn local_size_1d(args: &cli::Args, ocl_env: &OclEnv) -> u32 {
+    let size = ::std::cmp::min(args.tile_size * args.tile_size, ocl_env.max_work_group_size);
+    1 << (31 - size.leading_zeros())
+}
+
+/* `ops` are arithmetic operations, if meaningful for the workload. `bytes` is the minimum traffic (every input
+ * read and every output written once), so caches can push the effective bandwidth past the peak. */
+pub fn print_perf(peaks: &Peaks, time_ns: u64, ops: Option<u64>, bytes: u64) {
+    println!("Execution time is {} [ms]", time_ns as f64 / 1_000_000.0);
+    if let Some(ops) = ops {
+        let gflops = ops as f64 / time_ns as f64;
+        println!("Measured perf: {:.3} [GFLOPS], efficiency: {:.1}%", gflops, gflops / peaks.gflops * 100.0);
+    }
+    let bandwidth_gbps = bytes as f64 / time_ns as f64;
+    println!("Effective bandwidth: {:.3} [GB/s], {:.1}% of {}", bandwidth_gbps, bandwidth_gbps / peaks.bandwidth_gbps * 100.0,
+             if peaks.measured { "the copy kernel" } else { "peak" });
+}
+
+/* Element-wise comparison for results that must match bit for bit */
+pub fn verify_exact<T: PartialEq + Display>(expected: &[T], actual: &[T]) -> u32 {
+    let mut errors_encountered = 0;
+    for (i, (expected, actual)) in expected.iter().zip(actual.iter()).enumerate() {
+        if expected != actual {
+            errors_encountered += 1;
+            if errors_encountered < MAX_PRINT_ERRORS {
+                println!("Index {}: expected {}, got {}", i, expected, actual);
+            }
+        }
+    }
+    if errors_encountered > MAX_PRINT_ERRORS {
+        println!("...\n({} errors omitted)", errors_encountered - MAX_PRINT_ERRORS);
+    }
+    else if errors_encountered == 0 {
+        println!("Result verified, no errors found");
+    }
+    errors_encountered
+}
+
+fn measure_copy_bandwidth(args: &cli::Args, ocl_env: &OclEnv, strategy: MemStrategy) -> GenResult<f64> {
+    let (queue, tracer) = (&ocl_env.queue, &ocl_env.tracer);
+    let len = ceil_divisible_by(args.elements, 4);
+    let input = MatrixBuffer::<f32>::with_data(queue, strategy, &batch::generate_matrix(len as usize, 6), Access::ReadOnly, tracer, "copy input")?;
+    let output = MatrixBuffer::<f32>::new(queue, strategy, len as usize, Access::WriteOnly)?;
+
+    let program = build_ocl_program(ocl_env, String::new(), "", "bandwidth.cl")?;
+    let kernel = Kernel::builder()
+        .queue(queue.clone())
+        .program(&program).name("copy_float4")
+        .arg(&input.buffer).arg(&output.buffer).arg(len / 4)
+        .build()?;
+    let local_size = local_size_1d(args, ocl_env);
+    let event = enqueue_timed(ocl_env, &kernel, "copy_float4", [ceil_divisible_by(len / 4, local_size)], [local_size])?;
+    let bandwidth_gbps = 2.0 * 4.0 * len as f64 / get_execution_time_ns(&event)? as f64;
+    println!("Copy kernel bandwidth ({} floats, memory strategy: {}): {:.3} [GB/s]", len, strategy, bandwidth_gbps);
+    Ok(bandwidth_gbps)
+}
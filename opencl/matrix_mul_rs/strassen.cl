@@ -0,0 +1,27 @@
+/* Elementwise steps of Strassen-Winograd. Operands are rows x cols blocks of row-major matrices, each given
+ * by the offset of its first element and the row stride of the matrix it is part of, so that quadrants
+ * are used in place. NDRange (rows, cols), rounded up. */
+
+/* Z = X + Y_SCALE * Y; Z may be X or Y */
+__kernel void block_add(const __global float* X, const uint x_offset, const uint x_stride,
+                        const __global float* Y, const uint y_offset, const uint y_stride,
+                        __global float* Z, const uint z_offset, const uint z_stride,
+                        const uint rows, const uint cols, const float y_scale) {
+    const uint row = get_global_id(0);
+    const uint col = get_global_id(1);
+    if (row >= rows || col >= cols) return;
+
+    Z[z_offset + row * z_stride + col] = X[x_offset + row * x_stride + col] + y_scale * Y[y_offset + row * y_stride + col];
+}
+
+/* Packs a block into a rows x z_cols matrix for the GEMM kernels, filling columns past cols with zeros
+ * (the padding wideloads expects) */
+__kernel void block_copy(const __global float* X, const uint x_offset, const uint x_stride,
+                         __global float* Z, const uint z_offset, const uint z_stride,
+                         const uint rows, const uint cols, const uint z_cols) {
+    const uint row = get_global_id(0);
+    const uint col = get_global_id(1);
+    if (row >= rows || col >= z_cols) return;
+
+    Z[z_offset + row * z_stride + col] = (col < cols) ? X[x_offset + row * x_stride + col] : 0.0f;
+}
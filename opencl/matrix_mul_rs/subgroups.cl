@@ -1,77 +1,124 @@
-#define COMP_PER_THREAD 8
-#define GROUP_COLS 8
-#define TILE_ROWS (COMP_PER_THREAD * GROUP_COLS)
-#define TILE_WIDTH (TILE_SIZE / 4)
-
-/* Assuming input matrices are MxN (A), NxP (B):
- * global x size is M / 8, because each thread computes 8 values (see COMP_PER_THREAD)
- * global y size is P / 4, because we're using wide loads (float4)
- * local x size is COMP_PER_THREAD
- * local y size is GROUP_COLS
- */
-
-__kernel void subgroups(const __global float4* A,
-                        const __global float4* B,
-                        __global float4* C,
-                        const uint M,
-                        const uint N,
-                        const uint P) {
-    const size_t n_wide = N / 4;
-    const size_t p_wide = P / 4;
-
-    const size_t tile_row = get_group_id(0);
-    const size_t tile_col = get_group_id(1);
-    const size_t row = get_local_id(0);
-    const size_t col = get_local_id(1);
-
-    float4 c_tile[] = { 0.0f, 0.0f, 0.0f, 0.0f, 0.0f, 0.0f, 0.0f, 0.0f };
-
-    __global float4* C_tile = C + row + (tile_col * TILE_WIDTH) + (tile_row * TILE_ROWS + col * GROUP_COLS) * p_wide;
-    __global float4* A_tile = A + row + (tile_row * TILE_ROWS + col * GROUP_COLS) * n_wide;
-    __global float4* B_tile = B + row + (tile_col * TILE_WIDTH);
-
-    for (size_t tile = 0; tile < N / TILE_SIZE; tile++) {
-        const float4 a_tile_rows[] = {
-            A_tile[0], A_tile[n_wide], A_tile[2 * n_wide], A_tile[3 * n_wide], A_tile[4 * n_wide], A_tile[5 * n_wide], A_tile[6 * n_wide], A_tile[7 * n_wide]
-        };
-        A_tile += TILE_WIDTH;
-        
-#pragma unroll
-        for (size_t section = 0; section < TILE_WIDTH; section++) {
-            /* TODO: When using an array, shuffle always loads data from the first thread, no matter what `section` it is.
-             * Why? Need to check out assembly */
-            const float4 a0 = intel_sub_group_shuffle(a_tile_rows[0], section);
-            const float4 a1 = intel_sub_group_shuffle(a_tile_rows[1], section);
-            const float4 a2 = intel_sub_group_shuffle(a_tile_rows[2], section);
-            const float4 a3 = intel_sub_group_shuffle(a_tile_rows[3], section);
-            const float4 a4 = intel_sub_group_shuffle(a_tile_rows[4], section);
-            const float4 a5 = intel_sub_group_shuffle(a_tile_rows[5], section);
-            const float4 a6 = intel_sub_group_shuffle(a_tile_rows[6], section);
-            const float4 a7 = intel_sub_group_shuffle(a_tile_rows[7], section);
-
-            const float4 b_section[4] = {
-                B_tile[0], B_tile[1 * p_wide], B_tile[2 * p_wide], B_tile[3 * p_wide]
-            };
-            B_tile += p_wide * 4;
+/* Parameters (set by the host):
+ * SUBGROUP_SIZE is the number of lanes in a subgroup; the local x size must match it
+ * SUBGROUPS_PER_GROUP is the local y size
+ * BLOCK_ROWS x BLOCK_COLS is the block of C computed by a single work item (BLOCK_COLS is 1, 2, 4 or 8)
+ * One of SUBGROUP_INTEL (cl_intel_subgroups), SUBGROUP_KHR (cl_khr_subgroups) or SUBGROUP_EMULATED
+ * (no subgroup support, lanes exchange values through __local memory) selects the broadcast implementation;
+ * SUBGROUP_REQUIRED_SIZE additionally pins the subgroup size with cl_intel_required_subgroup_size.
+ *
+ * Assuming input matrices are MxN (A), NxP (B), padded to N_PAD and P_PAD columns by pad_cols.cl:
+ * global x size is P_PAD / BLOCK_COLS rounded up to SUBGROUP_SIZE
+ * global y size is M / BLOCK_ROWS rounded up to SUBGROUPS_PER_GROUP
+ *
+ * A subgroup computes a BLOCK_ROWS x (SUBGROUP_SIZE * BLOCK_COLS) block of C. Each lane loads a BLOCK_COLS-wide
+ * chunk of the block's A rows, and the chunks are then broadcast lane by lane, so an A element is read
+ * from global memory only once per subgroup. */
+
+#include "include/subgroup_extensions.h"
+
+#define CONCAT_(a, b) a##b
+#define CONCAT(a, b) CONCAT_(a, b)
+
+#if BLOCK_COLS == 1
+typedef float floatv;
+#define VLOAD(p) (*(p))
+#define VELEM(v, i) (v)
+#else
+typedef CONCAT(float, BLOCK_COLS) floatv;
+#define VLOAD(p) CONCAT(vload, BLOCK_COLS)(0, p)
+#define VELEM(v, i) (((float*) &(v))[i])
+#endif
+
+#define K_CHUNK (SUBGROUP_SIZE * BLOCK_COLS)
+
+#ifdef SUBGROUP_REQUIRED_SIZE
+#define SUBGROUP_ATTRIBUTES __attribute__((intel_reqd_sub_group_size(SUBGROUP_SIZE)))
+#else
+#define SUBGROUP_ATTRIBUTES
+#endif
+
+#ifndef SUBGROUP_EMULATED
+floatv broadcast_lane(floatv v, uint lane) {
+#ifdef SUBGROUP_INTEL
+    return intel_sub_group_shuffle(v, lane);
+#else
+    /* sub_group_broadcast is only defined for scalars */
+    floatv result;
+    for (uint i = 0; i < BLOCK_COLS; i++) VELEM(result, i) = sub_group_broadcast(VELEM(v, i), lane);
+    return result;
+#endif
+}
+#endif
+
+__kernel SUBGROUP_ATTRIBUTES
+void subgroups(const __global float* A,
+               const __global float* B,
+               __global float* C,
+               const uint M,
+               const uint N,
+               const uint P
+               EPILOGUE_PARAM
+               COVERAGE_PARAM) {
+    COVERAGE_RECORD();
+
+    const uint n_pad = ((N + TILE_SIZE - 1) / TILE_SIZE) * TILE_SIZE;
+    const uint p_pad = ((P + TILE_SIZE - 1) / TILE_SIZE) * TILE_SIZE;
+
+    const uint lane = get_local_id(0);
+    const uint row = get_global_id(1) * BLOCK_ROWS;
+    const uint col = get_global_id(0) * BLOCK_COLS;
+
+    floatv c_block[BLOCK_ROWS];
+    for (uint r = 0; r < BLOCK_ROWS; r++) c_block[r] = (floatv) 0.0f;
+
+#ifdef SUBGROUP_EMULATED
+    __local floatv a_shared[SUBGROUPS_PER_GROUP][BLOCK_ROWS][SUBGROUP_SIZE];
+    __local floatv (*a_lanes)[SUBGROUP_SIZE] = a_shared[get_local_id(1)];
+#endif
+
+    /* Work items outside of C still take part in loads and broadcasts; the loop bounds are uniform
+     * across the work group, which keeps shuffles and barriers well-defined */
+    for (uint k0 = 0; k0 < N; k0 += K_CHUNK) {
+        const uint a_col = k0 + lane * BLOCK_COLS;
+
+        floatv a_rows[BLOCK_ROWS];
+        for (uint r = 0; r < BLOCK_ROWS; r++)
+            a_rows[r] = (row + r < M && a_col < n_pad) ? VLOAD(A + (row + r) * n_pad + a_col) : (floatv) 0.0f;
+
+#ifdef SUBGROUP_EMULATED
+        for (uint r = 0; r < BLOCK_ROWS; r++) a_lanes[r][lane] = a_rows[r];
+        barrier(CLK_LOCAL_MEM_FENCE);
+#endif
+
+        for (uint section = 0; section < SUBGROUP_SIZE; section++) {
+            const uint k = k0 + section * BLOCK_COLS;
+            if (k >= N) break;
+
+            floatv b_rows[BLOCK_COLS];
+            for (uint c = 0; c < BLOCK_COLS; c++)
+                b_rows[c] = (k + c < N && col < p_pad) ? VLOAD(B + (k + c) * p_pad + col) : (floatv) 0.0f;
 
 #pragma unroll
-            for (size_t c = 0; c < 4; c++) c_tile[0] += b_section[c] * a0[c];
-#pragma unroll
-            for (size_t c = 0; c < 4; c++) c_tile[1] += b_section[c] * a1[c];
-#pragma unroll
-            for (size_t c = 0; c < 4; c++) c_tile[2] += b_section[c] * a2[c];
-#pragma unroll
-            for (size_t c = 0; c < 4; c++) c_tile[3] += b_section[c] * a3[c];
-#pragma unroll
-            for (size_t c = 0; c < 4; c++) c_tile[4] += b_section[c] * a4[c];
-#pragma unroll
-            for (size_t c = 0; c < 4; c++) c_tile[5] += b_section[c] * a5[c];
-#pragma unroll
-            for (size_t c = 0; c < 4; c++) c_tile[6] += b_section[c] * a6[c];
+            for (uint r = 0; r < BLOCK_ROWS; r++) {
+#ifdef SUBGROUP_EMULATED
+                const floatv a = a_lanes[r][section];
+#else
+                const floatv a = broadcast_lane(a_rows[r], section);
+#endif
 #pragma unroll
-            for (size_t c = 0; c < 4; c++) c_tile[7] += b_section[c] * a7[c];
+                for (uint c = 0; c < BLOCK_COLS; c++) c_block[r] += b_rows[c] * VELEM(a, c);
+            }
         }
+
+#ifdef SUBGROUP_EMULATED
+        /* Wait for all lanes to finish reading before the next chunk overwrites a_shared */
+        barrier(CLK_LOCAL_MEM_FENCE);
+#endif
     }
 
-    for (size_t r = 0; r < TILE_WIDTH; r++) C_tile[r * p_wide] = c_tile[r];
+    for (uint r = 0; r < BLOCK_ROWS; r++) {
+        if (row + r >= M) break;
+        for (uint c = 0; c < BLOCK_COLS && col + c < P; c++)
+            C[(row + r) * P + col + c] = EPILOGUE(VELEM(c_block[r], c), col + c);
+    }
 }
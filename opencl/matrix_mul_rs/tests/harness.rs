@@ -0,0 +1,289 @@
+/* Runs the harness end to end on a CPU OpenCL implementation, so that kernels can be checked without a GPU.
+ * The platform is MATRIX_MUL_TEST_PLATFORM if set, otherwise PoCL or the first platform with a CPU device;
+ * without one, every test passes after printing that it was skipped. E.g.
+ *   MATRIX_MUL_TEST_PLATFORM="Portable Computing Language" cargo test -- --nocapture
+ * Matrices are generated with odd sizes so that the padded edges of the tiled kernels get exercised. */
+
+extern crate ocl;
+extern crate matrix_mul_rs;
+#[cfg(feature = "ndarray")]
+#[macro_use]
+extern crate ndarray;
+
+use std::{env, fs, thread, path::PathBuf, process::{Command, Output, Stdio}, io::prelude::*, io::BufReader, sync::OnceLock};
+use ocl::{Platform, Device, DeviceType};
+use matrix_mul_rs::{Gemm, generate_matrix};
+
+const TEST_PLATFORM_VAR: &str = "MATRIX_MUL_TEST_PLATFORM";
+const POCL_PLATFORM: &str = "Portable Computing Language";
+
+/* Printed by the harness when a result doesn't verify. Build failures only skip a variant, so they are
+ * caught by counting the runs instead (subgroups.cl may legitimately fail to build on a CPU). */
+const FAILURE_MARKERS: [&str; 6] = [": expected ", "Expected ", "errors omitted", "wrong elements", "have errors", "timed out"];
+
+/* Looked up once: without any platform, the ICD loader is retried for a few seconds before giving up */
+static PLATFORM: OnceLock<Option<String>> = OnceLock::new();
+
+fn test_platform() -> Option<String> {
+    let platform = PLATFORM.get_or_init(find_platform).clone();
+    if platform.is_none() {
+        println!("No CPU OpenCL platform found (set {} to pick one); skipping", TEST_PLATFORM_VAR);
+    }
+    platform
+}
+
+fn find_platform() -> Option<String> {
+    if let Ok(name) = env::var(TEST_PLATFORM_VAR) {
+        return Some(name);
+    }
+    let platforms = ocl::core::get_platform_ids().map(Platform::list_from_core).unwrap_or_default();
+    let has_cpu = |platform: &Platform| Device::list(platform, Some(DeviceType::CPU)).map(|devices| !devices.is_empty()).unwrap_or(false);
+    let platform = platforms.iter().find(|platform| platform.name().map(|name| name == POCL_PLATFORM).unwrap_or(false))
+        .or_else(|| platforms.iter().find(|platform| has_cpu(platform)));
+    platform.and_then(|platform| platform.name().ok())
+}
+
+fn cpu_gemm(a: &[f32], b: &[f32], m: usize, n: usize, p: usize) -> Vec<f32> {
+    (0..m * p).map(|i| (0..n).map(|k| a[i / p * n + k] as f64 * b[k * p + i % p] as f64).sum::<f64>() as f32).collect()
+}
+
+/* A, B and C = A * B as text files in a directory of their own */
+struct MatrixFiles {
+    dir: PathBuf
+}
+
+impl MatrixFiles {
+    fn generate(test_name: &str, m: usize, n: usize, p: usize) -> MatrixFiles {
+        let dir = env::temp_dir().join(format!("matrix_mul_rs_{}_{}_{}x{}x{}", std::process::id(), test_name, m, n, p));
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (generate_matrix(m * n, 1), generate_matrix(n * p, 2));
+        let c = cpu_gemm(&a, &b, m, n, p);
+        for &(name, data) in [("matrix_a", &a), ("matrix_b", &b), ("matrix_c", &c)].iter() {
+            let text = data.iter().map(|v| format!("{:.8}\n", v)).collect::<String>();
+            fs::write(dir.join(name), text).unwrap();
+        }
+        MatrixFiles { dir }
+    }
+
+    fn args(&self) -> Vec<String> {
+        ["a", "b", "c"].iter().flat_map(|name| {
+            vec![format!("--matrix-{}", name), self.dir.join(format!("matrix_{}", name)).to_string_lossy().into_owned()]
+        }).collect()
+    }
+}
+
+impl Drop for MatrixFiles {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir).ok();
+    }
+}
+
+fn harness() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_matrix_mul_rs"));
+    /* Kernel sources are read from the working directory */
+    command.current_dir(env!("CARGO_MANIFEST_DIR"));
+    command
+}
+
+fn run(platform: &str, sizes: [usize; 4], options: &[String]) -> Output {
+    let positional = sizes.iter().map(|size| size.to_string()).collect::<Vec<_>>();
+    harness().arg(platform).args(&positional).arg("1").args(options).arg("--result-lines").output().unwrap()
+}
+
+/* Fails on a non-zero exit status or any failure printed; returns the GEMM result lines */
+fn check_output(output: &Output) -> Vec<String> {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let context = format!("stdout:\n{}\nstderr:\n{}", stdout, String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success(), "the harness exited with {}\n{}", output.status, context);
+    for marker in FAILURE_MARKERS.iter() {
+        assert!(!stdout.contains(marker), "\"{}\" in the output\n{}", marker, context);
+    }
+    let results = stdout.lines().filter(|line| line.starts_with("@result ")).map(|line| line.to_owned()).collect::<Vec<_>>();
+    for result in results.iter().filter(|result| result.contains("\"type\": \"run\"")) {
+        assert!(result.contains("\"errors\": 0"), "{}\n{}", result, context);
+    }
+    results
+}
+
+fn gemm_runs(results: &[String], kernel: &str) -> usize {
+    let kernel_field = format!("\"kernel\": \"{}\"", kernel);
+    results.iter().filter(|result| result.contains("\"type\": \"run\"") && result.contains(&kernel_field)).count()
+}
+
+#[test]
+fn gemm_kernels_handle_padded_edges() {
+    let platform = match test_platform() { Some(platform) => platform, None => return };
+    /* n and p multiples of the tile size, then not: wideloads pads A and B, tiled zero-fills partial tiles */
+    for &[tile_size, m, n, p] in [[4, 8, 8, 8], [4, 13, 7, 9], [8, 17, 33, 5], [8, 1, 9, 1], [4, 5, 4, 11]].iter() {
+        let matrices = MatrixFiles::generate("gemm", m, n, p);
+        let results = check_output(&run(&platform, [tile_size, m, n, p], &matrices.args()));
+        assert_eq!(gemm_runs(&results, "tiled"), 1, "{}x{}x{} with tile size {}", m, n, p, tile_size);
+        assert_eq!(gemm_runs(&results, "wideloads"), 1, "{}x{}x{} with tile size {}", m, n, p, tile_size);
+    }
+}
+
+#[test]
+fn gemm_memory_strategies_and_epilogue() {
+    let platform = match test_platform() { Some(platform) => platform, None => return };
+    let matrices = MatrixFiles::generate("strategies", 19, 10, 6);
+    let mut options = matrices.args();
+    options.extend(["--mem-strategy", "all", "--epilogue", "bias,relu"].iter().map(|s| s.to_string()));
+    let results = check_output(&run(&platform, [4, 19, 10, 6], &options));
+    assert_eq!(gemm_runs(&results, "tiled"), 4);
+}
+
+#[test]
+fn gemm_build_variants() {
+    let platform = match test_platform() { Some(platform) => platform, None => return };
+    let matrices = MatrixFiles::generate("variants", 9, 12, 7);
+    let mut options = matrices.args();
+    options.extend(["--options", "tiled:-cl-mad-enable", "--options", "tiled:-cl-opt-disable", "--sweep"].iter().map(|s| s.to_string()));
+    let results = check_output(&run(&platform, [4, 9, 12, 7], &options));
+    assert_eq!(gemm_runs(&results, "tiled"), 2);
+}
+
+#[test]
+fn int8_kernels() {
+    let platform = match test_platform() { Some(platform) => platform, None => return };
+    let matrices = MatrixFiles::generate("int8", 11, 14, 3);
+    let mut options = matrices.args();
+    options.push("--int8".to_owned());
+    let output = run(&platform, [4, 11, 14, 3], &options);
+    check_output(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Running int8_naive") && stdout.contains("Running int8_tiled"));
+}
+
+#[test]
+fn batched_kernel() {
+    let platform = match test_platform() { Some(platform) => platform, None => return };
+    for options in [vec!["--batch", "7"], vec!["--batch-shapes", "3x5x7,8x8x8,1x9x2"]].iter() {
+        let options = options.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let output = run(&platform, [4, 6, 5, 3], &options);
+        check_output(&output);
+        assert!(String::from_utf8_lossy(&output.stdout).contains("Result verified, no errors found in"));
+    }
+}
+
+#[test]
+fn other_workloads() {
+    let platform = match test_platform() { Some(platform) => platform, None => return };
+    for layout in ["nchw", "nhwc"].iter() {
+        let options = ["--workload", "pool,conv,primitives,sparse,strassen,precision", "--tensor", "2x3x9x7", "--layout", layout,
+                       "--window", "3x2", "--stride", "2x1", "--padding", "1x1", "--filters", "5", "--elements", "3001",
+                       "--density", "0.2", "--strassen-cutoff", "4,8", "--precision-sizes", "7,33,130", "--peak-bandwidth", "1"];
+        let output = run(&platform, [4, 21, 13, 10], &options.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        check_output(&output);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        for workload in ["Running max_pool", "Running convolution", "Running reductions", "Running exclusive scan", "Running transposes",
+                         "-bin histograms", "Running SpMV", "Running SpMM", "Running Strassen-Winograd", "Precision study"].iter() {
+            assert!(stdout.contains(workload), "no \"{}\" in the output\n{}", workload, stdout);
+        }
+        /* Strassen-Winograd only reports its error; with cutoffs 4 and 8, 21x13 by 13x10 is padded for 3 and 2 levels */
+        let strassen_errors = stdout.lines().filter(|line| line.starts_with("Error against the f64 reference")).map(|line| {
+            let normwise = line.split("normwise ").nth(1).and_then(|rest| rest.split(',').next()).unwrap();
+            normwise.parse::<f64>().unwrap()
+        }).collect::<Vec<_>>();
+        assert_eq!(strassen_errors.len(), 6, "{}", stdout);
+        assert!(strassen_errors.iter().all(|&error| error < 1e-5), "{}", stdout);
+    }
+}
+
+#[test]
+fn device_resident_chain() {
+    let platform = match test_platform() { Some(platform) => platform, None => return };
+    /* Odd dimensions pad every product; the chained result must match the round trip bit for bit */
+    let options = ["--workload", "chain", "--chain-dims", "9,14,5,17,3", "--peak-bandwidth", "1"];
+    let output = run(&platform, [4, 6, 5, 3], &options.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+    check_output(&output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Running a chain of 3 multiplications, 9x14 * 14x5 * 5x17 * 17x3"), "{}", stdout);
+    assert!(stdout.contains("Result verified, no errors found"), "{}", stdout);
+    let error = stdout.lines().find(|line| line.starts_with("Error against the f64 reference")).unwrap();
+    let normwise = error.split("normwise ").nth(1).and_then(|rest| rest.split(',').next()).unwrap();
+    assert!(normwise.parse::<f64>().unwrap() < 1e-5, "{}", stdout);
+}
+
+#[test]
+fn library_gemm() {
+    let platform = match test_platform() { Some(platform) => platform, None => return };
+    let gemm = Gemm::new(&platform, 4, env!("CARGO_MANIFEST_DIR")).unwrap();
+    let (m, n, p) = (7, 9, 5);
+    let (a, b, c) = (generate_matrix(m * n, 1), generate_matrix(n * p, 2), generate_matrix(p * m, 3));
+    let ab = gemm.gemm(&a, &b, m as u32, n as u32, p as u32).unwrap();
+    let expected = cpu_gemm(&a, &b, m, n, p);
+    assert!(ab.iter().zip(expected.iter()).all(|(x, y)| (x - y).abs() < 1e-4), "{:?}\n{:?}", ab, expected);
+
+    /* The intermediate product never leaves the device, and its handle is dropped before anything waits for it */
+    let (a_handle, b_handle, c_handle) = (gemm.upload(&a, 7, 9).unwrap(), gemm.upload(&b, 9, 5).unwrap(), gemm.upload(&c, 5, 7).unwrap());
+    let mut abc = gemm.multiply(&gemm.multiply(&a_handle, &b_handle).unwrap(), &c_handle).unwrap();
+    assert_eq!(gemm.read(&mut abc).unwrap(), gemm.gemm(&ab, &c, 7, 5, 7).unwrap());
+    assert!(gemm.multiply(&a_handle, &c_handle).is_err());
+
+    /* A stored column-major is read in place */
+    let a_column_major = (0..m * n).map(|i| a[i % m * n + i / m]).collect::<Vec<_>>();
+    let mut a_transposed = gemm.upload_transposed(&a_column_major, 7, 9).unwrap();
+    assert_eq!(gemm.read(&mut a_transposed).unwrap(), a);
+    assert_eq!(gemm.read(&mut gemm.multiply(&a_transposed, &b_handle).unwrap()).unwrap(), ab);
+}
+
+#[cfg(feature = "ndarray")]
+#[test]
+fn ndarray_views() {
+    use ndarray::Array2;
+    let platform = match test_platform() { Some(platform) => platform, None => return };
+    let gemm = Gemm::new(&platform, 4, env!("CARGO_MANIFEST_DIR")).unwrap();
+    let a_transposed = Array2::from_shape_vec((9, 7), generate_matrix(63, 1)).unwrap();
+    let b_wide = Array2::from_shape_vec((9, 10), generate_matrix(90, 2)).unwrap();
+    /* A column-major view, read in place as a transposed operand, and a strided one, which gets packed */
+    let (a, b) = (a_transposed.t(), b_wide.slice(s![.., ..;2]));
+    let c = gemm.multiply_arrays(a, b).unwrap();
+    let expected = a.dot(&b);
+    assert_eq!(c.dim(), (7, 5));
+    assert!(c.iter().zip(expected.iter()).all(|(x, y)| (x - y).abs() < 1e-4), "{}\n{}", c, expected);
+    let c_row_major = gemm.multiply_arrays(a.to_owned().view(), b.to_owned().view()).unwrap();
+    assert_eq!(c, c_row_major);
+}
+
+#[test]
+fn agent_and_client() {
+    let platform = match test_platform() { Some(platform) => platform, None => return };
+    let matrices = MatrixFiles::generate("agent", 7, 9, 5);
+    let mut agent = harness().args(["agent", "127.0.0.1:0", &platform]).arg(&matrices.dir).stdout(Stdio::piped()).spawn().unwrap();
+    let mut agent_output = BufReader::new(agent.stdout.take().unwrap());
+    let mut listening = String::new();
+    while !listening.starts_with("Agent listening on ") {
+        listening.clear();
+        assert!(agent_output.read_line(&mut listening).unwrap() > 0, "the agent exited without listening");
+    }
+    let address = listening.trim()["Agent listening on ".len()..].to_owned();
+    /* Keep draining the agent's log so that it never blocks on a full pipe */
+    thread::spawn(move || { for _ in agent_output.lines() {} });
+
+    /* Jobs name files relative to the agent's data directory; anything that could leave it is refused */
+    let job = |matrix_a: &str| format!("{{\"tile_size\": 4, \"m\": 7, \"n\": 9, \"p\": 5, \"device_gflops\": 1, \"options\": \
+                                        [\"--matrix-a\", {:?}, \"--matrix-b\", \"matrix_b\", \"--matrix-c\", \"matrix_c\"]}}", matrix_a);
+    let job_file = matrices.dir.join("job.json");
+    for escaping in [matrices.dir.join("matrix_a").to_string_lossy().into_owned(), "../matrix_a".to_owned()].iter() {
+        fs::write(&job_file, job(escaping)).unwrap();
+        let output = harness().arg("client").arg(&job_file).arg(&address).output().unwrap();
+        assert!(!output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    }
+    fs::write(&job_file, job("matrix_a")).unwrap();
+    let output = harness().arg("client").arg(&job_file).arg(&address).arg(&address).output().unwrap();
+    agent.kill().ok();
+    agent.wait().ok();
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    let summary = stdout.split("===\nResults").nth(1).unwrap_or_else(|| panic!("no summary\n{}", stdout));
+    assert_eq!(summary.matches("exit status: 0").count(), 2, "{}", stdout);
+    assert!(summary.contains("tiled") && !summary.contains("timed out"), "{}", stdout);
+    assert!(!summary.lines().any(|line| line.trim_start().starts_with("error:")), "{}", stdout);
+    let runs = summary.lines().filter(|line| line.contains("[GFLOPS]")).collect::<Vec<_>>();
+    assert!(!runs.is_empty(), "{}", stdout);
+    for line in runs {
+        let errors = line.rsplit(", ").next().and_then(|field| field.trim_end_matches(" errors").parse::<u32>().ok());
+        assert_eq!(errors, Some(0), "{}", line);
+    }
+}
@@ -1,9 +1,25 @@
+#include "include/tile_load.h" TYPE=float
+
+/* Accumulation is in float unless the host sets (e.g. for the precision study):
+ * ACCUMULATE_KAHAN to compensate the rounding error of every addition (Kahan summation), or
+ * ACCUMULATE_DOUBLE to accumulate the products of the float inputs in double precision (needs cl_khr_fp64) */
+#ifdef ACCUMULATE_DOUBLE
+#pragma OPENCL EXTENSION cl_khr_fp64 : enable
+typedef double accumulator;
+#else
+typedef float accumulator;
+#endif
+
 __kernel void tiled(const __global float* A,
                     const __global float* B,
                     __global float* C,
                     const uint M,
                     const uint N,
-                    const uint P) {
+                    const uint P
+                    EPILOGUE_PARAM
+                    COVERAGE_PARAM) {
+    COVERAGE_RECORD();
+
     /* This kernel takes advantage of the __local memory shared between
      * all work items in a work group.
      *
@@ -35,7 +51,11 @@ __kernel void tiled(const __global float* A,
     const size_t col = get_local_id(1);
 
     /* The element is accumulated through iterations on A and B matrix tiles */
-    float c_acc = 0.0f;
+    accumulator c_acc = 0.0f;
+#ifdef ACCUMULATE_KAHAN
+    /* The low-order part lost by the previous addition, subtracted back in the next one */
+    float c_err = 0.0f;
+#endif
     
     /* The tiles currently being iterated on are shared within a work group:
      * each work item loads a single element from each input matrix, and once
@@ -52,18 +72,34 @@ __kernel void tiled(const __global float* A,
         const size_t b_i_col = (tile_col * TILE_SIZE) + col;
 
         /* If the dimensions are not divisible by the number of work items (TILE_SIZE),
-         * we may encounter elements that are outside the matrix -- treat those as 0s. */
-        if (a_i_row >= M || a_i_col >= N) current_a_tile[row][col] = 0.0f;
-        else current_a_tile[row][col] = A[a_i_row * N + a_i_col];
-
-        if (b_i_row >= N || b_i_col >= P) current_b_tile[row][col] = 0.0f;
-        else current_b_tile[row][col] = B[b_i_row * P + b_i_col];
+         * we may encounter elements that are outside the matrix -- treat those as 0s.
+         * With TRANSPOSE_A (TRANSPOSE_B) the host passes A (B) stored transposed, as a row-major N x M (P x N)
+         * matrix, so that column-major operands don't have to be repacked. */
+#ifdef TRANSPOSE_A
+        current_a_tile[row][col] = load_or_zero_float(A, a_i_col, a_i_row, N, M, M);
+#else
+        current_a_tile[row][col] = load_or_zero_float(A, a_i_row, a_i_col, M, N, N);
+#endif
+#ifdef TRANSPOSE_B
+        current_b_tile[row][col] = load_or_zero_float(B, b_i_col, b_i_row, P, N, N);
+#else
+        current_b_tile[row][col] = load_or_zero_float(B, b_i_row, b_i_col, N, P, P);
+#endif
 
         /* After synchronization, we'll have access to all elements in current A and B tiles */
         barrier(CLK_LOCAL_MEM_FENCE);
 
+#ifdef ACCUMULATE_KAHAN
+        for (size_t n = 0; n < TILE_SIZE; n++) {
+            const float term = current_a_tile[row][n] * current_b_tile[n][col] - c_err;
+            const float sum = c_acc + term;
+            c_err = (sum - c_acc) - term;
+            c_acc = sum;
+        }
+#else
         for (size_t n = 0; n < TILE_SIZE; n++)
-            c_acc += current_a_tile[row][n] * current_b_tile[n][col];
+            c_acc += (accumulator) current_a_tile[row][n] * current_b_tile[n][col];
+#endif
 
         /* Wait for all work items to finish reading current tiles before loading the next ones */
         barrier(CLK_LOCAL_MEM_FENCE);
@@ -73,5 +109,5 @@ __kernel void tiled(const __global float* A,
     const size_t result_row = get_global_id(0);
     const size_t result_col = get_global_id(1);
     const size_t result_index = (tile_row * TILE_SIZE * P) + (tile_col * TILE_SIZE) + (row * P) + col;
-    if (result_row < M && result_col < P) C[result_index] = c_acc;
+    if (result_row < M && result_col < P) C[result_index] = EPILOGUE((float) c_acc, result_col);
 }
@@ -1,9 +1,15 @@
+#include "include/tile_load.h" TYPE=float4
+
 __kernel void wideloads(const __global float4* A,
                         const __global float4* B,
                         __global float* C,
                         const uint M,
                         const uint N,
-                        const uint P) {
+                        const uint P
+                        EPILOGUE_PARAM
+                        COVERAGE_PARAM) {
+    COVERAGE_RECORD();
+
     /* This kernel is similar to tiled.cl. The difference that makes it perform better
      * is having each work item calculate four instead of one elements in a row
      * (the number of rows, as well as the tile size, is required to be a multiple of
@@ -36,12 +42,11 @@ __kernel void wideloads(const __global float4* A,
         const size_t b_i_col = (tile_col * TILE_SIZE / 4) + col;
 
         /* If the vertical dimension is not divisible by the number of work items (TILE_SIZE),
-         * we may encounter elements that are outside the matrix -- treat those as 0s. */
-        if (a_i_row >= M) current_a_tile[row][col] = (float4) { 0.0f, 0.0f, 0.0f, 0.0f };
-        else current_a_tile[row][col] = A[a_i_row * n_wide + a_i_col];
-
-        if (b_i_row >= N) current_b_tile[row][col] = (float4) { 0.0f, 0.0f, 0.0f, 0.0f };
-        else current_b_tile[row][col] = B[b_i_row * p_wide + b_i_col];
+         * we may encounter elements that are outside the matrix -- treat those as 0s.
+         * Rows are zero-padded to n_wide (p_wide) float4s, so float4s past the last one holding
+         * any of the N (P) real columns are all padding and needn't be loaded either. */
+        current_a_tile[row][col] = load_or_zero_float4(A, a_i_row, a_i_col, M, (N + 3) / 4, n_wide);
+        current_b_tile[row][col] = load_or_zero_float4(B, b_i_row, b_i_col, N, (P + 3) / 4, p_wide);
 
         barrier(CLK_LOCAL_MEM_FENCE);
 
@@ -71,21 +76,21 @@ __kernel void wideloads(const __global float4* A,
 
     switch ((result_col + 4) - P) {
         case 1:
-            C[result_index] = c_acc.s0;
-            C[result_index + 1] = c_acc.s1;
-            C[result_index + 2] = c_acc.s2;
+            C[result_index] = EPILOGUE(c_acc.s0, result_col);
+            C[result_index + 1] = EPILOGUE(c_acc.s1, result_col + 1);
+            C[result_index + 2] = EPILOGUE(c_acc.s2, result_col + 2);
             break;
         case 2:
-            C[result_index] = c_acc.s0;
-            C[result_index + 1] = c_acc.s1;
+            C[result_index] = EPILOGUE(c_acc.s0, result_col);
+            C[result_index + 1] = EPILOGUE(c_acc.s1, result_col + 1);
             break;
         case 3:
-            C[result_index] = c_acc.s0;
+            C[result_index] = EPILOGUE(c_acc.s0, result_col);
             break;
         default:
-            C[result_index] = c_acc.s0;
-            C[result_index + 1] = c_acc.s1;
-            C[result_index + 2] = c_acc.s2;
-            C[result_index + 3] = c_acc.s3;
+            C[result_index] = EPILOGUE(c_acc.s0, result_col);
+            C[result_index + 1] = EPILOGUE(c_acc.s1, result_col + 1);
+            C[result_index + 2] = EPILOGUE(c_acc.s2, result_col + 2);
+            C[result_index + 3] = EPILOGUE(c_acc.s3, result_col + 3);
     }
 }